@@ -0,0 +1,396 @@
+use std::collections::HashMap;
+use std::fs;
+use rust_decimal::Decimal;
+use serde::{Serialize, Deserialize};
+use crate::strategy::control::StrategyParams;
+
+
+#[derive(Serialize, Deserialize)]
+#[derive(Debug, Clone, Default)]
+pub struct Config {
+    #[serde(default)]
+    pub reconciliation: ReconciliationConfig,
+    #[serde(default)]
+    pub kill_switch: KillSwitchConfig,
+    #[serde(default)]
+    pub mmp: MmpConfig,
+    #[serde(default)]
+    pub mass_quote: MassQuoteConfig,
+    #[serde(default)]
+    pub rate_limit: RateLimitConfig,
+    #[serde(default)]
+    pub runtime: ConnectorRuntime,
+    #[serde(default)]
+    pub event_bus: EventBusConfig,
+    #[serde(default)]
+    pub market_data: MarketDataConfig,
+    #[serde(default)]
+    pub margin_guard: MarginGuardConfig,
+    #[serde(default)]
+    pub risk: RiskConfig,
+    #[serde(default)]
+    pub stale_data: StaleDataConfig,
+    #[serde(default)]
+    pub metrics: MetricsConfig,
+    #[serde(default)]
+    pub strategy: StrategyParams,
+    #[serde(default)]
+    pub admin: AdminConfig,
+    #[serde(default)]
+    pub monitor: MonitorConfig,
+    #[serde(default)]
+    pub supervisor: SupervisorConfig,
+    #[serde(default)]
+    pub shutdown: ShutdownConfig,
+    #[serde(default)]
+    pub auth: AuthConfig,
+}
+
+impl Config {
+    pub fn load(path: &str) -> Config {
+        let raw = fs::read_to_string(path).expect("Can't read config");
+
+        serde_yaml::from_str(&raw).expect("Can't parse config")
+    }
+}
+
+// what to do with orders found on the exchange which the manager doesn't know about
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum OrphanOrderPolicy {
+    Adopt,
+    #[default]
+    Cancel,
+}
+
+// how the connector drives the socket
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum ConnectorRuntime {
+    // blocking tungstenite on OS threads
+    #[default]
+    Threaded,
+    // tokio-tungstenite with separate read and write tasks
+    Tokio,
+}
+
+#[derive(Serialize, Deserialize)]
+#[derive(Debug, Clone, Default)]
+pub struct ReconciliationConfig {
+    #[serde(default)]
+    pub orphan_orders: OrphanOrderPolicy,
+}
+
+#[derive(Serialize, Deserialize)]
+#[derive(Debug, Clone, Default)]
+pub struct KillSwitchConfig {
+    // close positions with reduce-only market orders when the switch is engaged
+    #[serde(default)]
+    pub flatten_positions: bool,
+}
+
+// deribit market maker protection, see private/set_mmp_config
+#[derive(Serialize, Deserialize)]
+#[derive(Debug, Clone)]
+pub struct MmpConfig {
+    pub enabled: bool,
+    pub index_name: String,
+    // seconds
+    pub interval: u32,
+    // seconds, 0 means the exchange keeps quotes frozen until reset_mmp
+    pub frozen_time: u32,
+    pub quantity_limit: Option<Decimal>,
+    pub delta_limit: Option<Decimal>,
+    // required by private/mass_quote
+    pub mmp_group: Option<String>,
+    // seconds to wait after a trigger before private/reset_mmp
+    pub cool_down: u64,
+}
+
+impl Default for MmpConfig {
+    fn default() -> MmpConfig {
+        MmpConfig {
+            enabled: false,
+            index_name: "btc_usd".to_string(),
+            interval: 1,
+            frozen_time: 0,
+            quantity_limit: None,
+            delta_limit: None,
+            mmp_group: None,
+            cool_down: 10,
+        }
+    }
+}
+
+// send both sides of a quote with one private/mass_quote request instead of buy + sell
+#[derive(Serialize, Deserialize)]
+#[derive(Debug, Clone, Default)]
+pub struct MassQuoteConfig {
+    #[serde(default)]
+    pub enabled: bool,
+}
+
+#[derive(Serialize, Deserialize)]
+#[derive(Debug, Clone)]
+pub struct BucketConfig {
+    pub capacity: u64,
+    // credits per second
+    pub refill_rate: u64,
+    // credits per request
+    pub cost: u64,
+}
+
+#[derive(Serialize, Deserialize)]
+#[derive(Debug, Clone)]
+pub struct RateLimitConfig {
+    pub matching_engine: BucketConfig,
+    pub non_matching_engine: BucketConfig,
+    // new orders waiting longer than this for credits are rejected
+    pub max_order_wait_ms: u64,
+}
+
+impl Default for RateLimitConfig {
+    fn default() -> RateLimitConfig {
+        RateLimitConfig {
+            matching_engine: BucketConfig { capacity: 20000, refill_rate: 5000, cost: 1000 },
+            non_matching_engine: BucketConfig { capacity: 50000, refill_rate: 10000, cost: 500 },
+            max_order_wait_ms: 200,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+#[derive(Debug, Clone)]
+pub struct EventBusConfig {
+    // ring buffer slots, power of two
+    pub capacity: usize,
+    // a reader which doesn't move for this long while the ring is full gets detached
+    pub reader_timeout_ms: u64,
+}
+
+impl Default for EventBusConfig {
+    fn default() -> EventBusConfig {
+        EventBusConfig { capacity: 4096, reader_timeout_ms: 5000 }
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+#[derive(Debug, Clone)]
+pub struct MarketDataConfig {
+    // ticker and perpetual channels: raw, 100ms or agg2
+    pub interval: String,
+    // deribit_price_index channels
+    pub price_indexes: Vec<String>,
+    // markprice.options channels, by index name
+    pub option_mark_prices: Vec<String>,
+}
+
+impl Default for MarketDataConfig {
+    fn default() -> MarketDataConfig {
+        MarketDataConfig {
+            interval: "100ms".to_string(),
+            price_indexes: vec!("btc_usd".to_string()),
+            option_mark_prices: vec!(),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+#[derive(Debug, Clone)]
+pub struct MarginGuardConfig {
+    pub currency: String,
+    // maintenance margin / equity
+    pub warning_utilization: Decimal,
+    pub reduce_only_utilization: Decimal,
+    pub flatten_utilization: Decimal,
+    // price move to the estimated liquidation price, as a fraction of the price
+    pub warning_liquidation_distance: Decimal,
+    pub reduce_only_liquidation_distance: Decimal,
+    pub flatten_liquidation_distance: Decimal,
+}
+
+impl Default for MarginGuardConfig {
+    fn default() -> MarginGuardConfig {
+        MarginGuardConfig {
+            currency: "BTC".to_string(),
+            warning_utilization: Decimal::new(5, 1),
+            reduce_only_utilization: Decimal::new(7, 1),
+            flatten_utilization: Decimal::new(85, 2),
+            warning_liquidation_distance: Decimal::new(2, 1),
+            reduce_only_liquidation_distance: Decimal::new(1, 1),
+            flatten_liquidation_distance: Decimal::new(5, 2),
+        }
+    }
+}
+
+// circuit breaker limits, reset with `kill -USR2 <pid>`
+#[derive(Serialize, Deserialize)]
+#[derive(Debug, Clone)]
+pub struct RiskConfig {
+    pub currency: String,
+    // equity lost since the start of the utc day, an absolute amount of currency,
+    // 1 with BTC trips after losing 1 BTC
+    pub max_daily_loss: Decimal,
+    // equity lost from the high-water mark as a fraction of it, 0.2 trips at 20% down
+    pub max_drawdown: Decimal,
+    // tripped state and marks survive restarts here
+    pub state_file: String,
+}
+
+impl Default for RiskConfig {
+    fn default() -> RiskConfig {
+        RiskConfig {
+            currency: "BTC".to_string(),
+            max_daily_loss: Decimal::ONE,
+            max_drawdown: Decimal::new(2, 1),
+            state_file: "state/circuit_breaker.json".to_string(),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+#[derive(Debug, Clone)]
+pub struct StaleDataConfig {
+    // quotes are pulled when a book doesn't update for this long
+    pub max_update_gap_ms: u64,
+    // or when local receive time, corrected by the clock offset, is this far behind the exchange timestamp
+    pub max_exchange_lag_ms: i64,
+    // or when the exchange heartbeat, sent every 60 seconds, is missing for this long
+    pub max_heartbeat_gap_ms: u64,
+}
+
+impl Default for StaleDataConfig {
+    fn default() -> StaleDataConfig {
+        StaleDataConfig { max_update_gap_ms: 5000, max_exchange_lag_ms: 1000, max_heartbeat_gap_ms: 90000 }
+    }
+}
+
+// prometheus endpoint, GET /metrics
+#[derive(Serialize, Deserialize)]
+#[derive(Debug, Clone)]
+pub struct MetricsConfig {
+    pub enabled: bool,
+    pub address: String,
+}
+
+impl Default for MetricsConfig {
+    fn default() -> MetricsConfig {
+        MetricsConfig { enabled: true, address: "127.0.0.1:9184".to_string() }
+    }
+}
+
+// local control API, only loopback addresses are accepted
+#[derive(Serialize, Deserialize)]
+#[derive(Debug, Clone)]
+pub struct AdminConfig {
+    pub enabled: bool,
+    pub address: String,
+    pub ws_address: String,
+    // environment variable with the bearer token
+    pub token_env: String,
+    // deepest quote_depth set_params accepts
+    pub max_quote_depth: usize,
+}
+
+impl Default for AdminConfig {
+    fn default() -> AdminConfig {
+        AdminConfig {
+            enabled: false,
+            address: "127.0.0.1:9185".to_string(),
+            ws_address: "127.0.0.1:9186".to_string(),
+            token_env: "CT_ADMIN_TOKEN".to_string(),
+            max_quote_depth: 10,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+#[derive(Debug, Clone)]
+pub struct MonitorConfig {
+    pub refresh_ms: u64,
+    // tailed for the log panel
+    pub log_file: String,
+    pub log_lines: usize,
+}
+
+impl Default for MonitorConfig {
+    fn default() -> MonitorConfig {
+        MonitorConfig {
+            refresh_ms: 500,
+            log_file: "log/ct.log".to_string(),
+            log_lines: 10,
+        }
+    }
+}
+
+// what the supervisor does when a component panics or stops
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum RestartPolicy {
+    #[default]
+    Restart,
+    // shut the bot down with a nonzero exit code
+    Escalate,
+}
+
+#[derive(Serialize, Deserialize)]
+#[derive(Debug, Clone)]
+pub struct SupervisorConfig {
+    // more restarts than this inside the window escalate
+    pub max_restarts: usize,
+    pub restart_window_secs: u64,
+    pub restart_delay_ms: u64,
+    // component name -> policy, components not listed are restarted
+    #[serde(default)]
+    pub policies: HashMap<String, RestartPolicy>,
+}
+
+impl Default for SupervisorConfig {
+    fn default() -> SupervisorConfig {
+        SupervisorConfig {
+            max_restarts: 3,
+            restart_window_secs: 60,
+            restart_delay_ms: 1000,
+            policies: HashMap::new(),
+        }
+    }
+}
+
+// public/auth with the client credentials from the build environment
+#[derive(Serialize, Deserialize)]
+#[derive(Debug, Clone)]
+pub struct AuthConfig {
+    // named session, sent as session:<name>
+    pub session: Option<String>,
+    // e.g. [trade:read] for a read-only session, empty takes the scopes of the api key
+    pub scopes: Vec<String>,
+    // the token is refreshed this long before it expires
+    pub refresh_before_secs: u64,
+}
+
+impl Default for AuthConfig {
+    fn default() -> AuthConfig {
+        AuthConfig { session: None, scopes: vec!(), refresh_before_secs: 300 }
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+#[derive(Debug, Clone)]
+pub struct ShutdownConfig {
+    // how long to wait for the open orders to be gone after cancel all
+    pub cancel_timeout_ms: u64,
+    // how long the connector gets to write what is queued and close the socket
+    pub close_timeout_ms: u64,
+}
+
+impl Default for ShutdownConfig {
+    fn default() -> ShutdownConfig {
+        ShutdownConfig {
+            cancel_timeout_ms: 5000,
+            close_timeout_ms: 5000,
+        }
+    }
+}
@@ -0,0 +1,207 @@
+use std::sync::Arc;
+use std::time::Duration;
+use futures_util::{SinkExt, StreamExt};
+use tokio::runtime::Runtime;
+use tokio::sync::mpsc;
+use tokio_tungstenite::connect_async;
+use tokio_tungstenite::tungstenite::Message;
+use crossbeam_channel::{unbounded, Sender};
+use log::{error, info, warn};
+use crate::connectors::deribit::ws_connector::{DeribitConnector, Inbound, Outbound, CLOCK_SYNC_INTERVAL, CLOSE_TIMEOUT, HEARTBEAT_TIMEOUT};
+use crate::core::latency::LatencyKind;
+
+const RECONNECT_INTERVAL: Duration = Duration::from_secs(1);
+const EXPIRE_INTERVAL: Duration = Duration::from_secs(1);
+// how often the writer checks whether the shutdown closes the connection
+const CLOSE_POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+// Same connector on a tokio runtime: socket reads and writes are separate tasks and
+// timers are tokio intervals. Commands and dispatch stay on blocking threads so the
+// strategies keep talking to the connector through the crossbeam channels.
+pub struct AsyncDeribitConnector {
+    connector: Arc<DeribitConnector>,
+    runtime: Runtime,
+}
+
+impl AsyncDeribitConnector {
+    pub fn new(connector: DeribitConnector) -> AsyncDeribitConnector {
+        let runtime = tokio::runtime::Builder::new_multi_thread()
+            .enable_all()
+            .thread_name("deribit-async")
+            .build()
+            .expect("Can't build tokio runtime");
+
+        AsyncDeribitConnector { connector: Arc::new(connector), runtime }
+    }
+
+    pub fn run(&self) {
+        self.runtime.block_on(async {
+            let (inbound_sender, inbound_receiver) = unbounded();
+
+            let connector = Arc::clone(&self.connector);
+            let commands = tokio::task::spawn_blocking(move || connector.supervisor.run("connector commands", || connector.run_commands()));
+
+            let connector = Arc::clone(&self.connector);
+            let dispatch = tokio::task::spawn_blocking(move || connector.supervisor.run("connector dispatch", || connector.run_dispatch(&inbound_receiver)));
+
+            let connector = Arc::clone(&self.connector);
+            tokio::spawn(async move {
+                let mut interval = tokio::time::interval(EXPIRE_INTERVAL);
+                loop {
+                    interval.tick().await;
+                    if connector.supervisor.shutdown().is_closing() {
+                        break;
+                    }
+                    connector.expire_requests();
+                    connector.refresh_token();
+                    connector.retry_auth();
+                }
+            });
+
+            let connector = Arc::clone(&self.connector);
+            tokio::spawn(async move {
+                let mut interval = tokio::time::interval(CLOCK_SYNC_INTERVAL);
+                // the first tick is immediate, the session start syncs already
+                interval.tick().await;
+                loop {
+                    interval.tick().await;
+                    if connector.supervisor.shutdown().is_closing() {
+                        break;
+                    }
+                    if let Err(e) = connector.sync_clock() {
+                        warn!("Can't sync the clock: {}", e);
+                    }
+                }
+            });
+
+            tokio::spawn(run_io(Arc::clone(&self.connector), inbound_sender));
+
+            let (commands, dispatch) = tokio::join!(commands, dispatch);
+            if self.connector.supervisor.shutdown().is_closing() {
+                info!("Connector stopped");
+            } else {
+                error!("Connector stopped: commands {:?}, dispatch {:?}", commands, dispatch);
+            }
+        });
+    }
+}
+
+async fn run_io(connector: Arc<DeribitConnector>, inbound_sender: Sender<Inbound>) {
+    while !connector.supervisor.shutdown().is_closing() {
+        let socket = match connect_async("wss://test.deribit.com/ws/api/v2").await {
+            Ok((socket, _)) => socket,
+            Err(e) => {
+                connector.transport_error("Can't connect", e);
+                tokio::time::sleep(RECONNECT_INTERVAL).await;
+                continue;
+            }
+        };
+        info!("Connected");
+
+        // a fresh queue per connection, forwarded so the writer can await on it. The
+        // forwarder ends with the queue
+        let (outbound_sender, outbound_receiver) = mpsc::unbounded_channel();
+        let queued = connector.open_outbound();
+        tokio::task::spawn_blocking(move || {
+            for outbound in queued.iter() {
+                if outbound_sender.send(outbound).is_err() {
+                    break;
+                }
+            }
+        });
+        // the dispatch is gone, nothing would read the frames
+        if inbound_sender.send(Inbound::Connected).is_err() {
+            connector.close_outbound();
+            return;
+        }
+
+        let (write, read) = socket.split();
+        let mut reader = tokio::spawn(read_frames(read, inbound_sender.clone(), Arc::clone(&connector)));
+        let mut writer = tokio::spawn(write_frames(write, outbound_receiver, Arc::clone(&connector)));
+
+        // a failing reader takes the writer down, the reader ends with the answer to the
+        // close frame of the writer
+        tokio::select! {
+            _ = &mut reader => writer.abort(),
+            _ = &mut writer => if tokio::time::timeout(CLOSE_TIMEOUT, &mut reader).await.is_err() {
+                warn!("No close frame from the exchange in {:?}", CLOSE_TIMEOUT);
+                reader.abort();
+            },
+        }
+
+        // pending requests of the connection are failed with the Disconnected
+        connector.close_outbound();
+        if inbound_sender.send(Inbound::Disconnected).is_err() || connector.supervisor.shutdown().is_closing() {
+            return;
+        }
+        warn!("Trying to reconnect....");
+        tokio::time::sleep(RECONNECT_INTERVAL).await;
+    }
+}
+
+async fn read_frames<S>(mut read: S, inbound_sender: Sender<Inbound>, connector: Arc<DeribitConnector>)
+    where S: StreamExt<Item=Result<Message, tokio_tungstenite::tungstenite::Error>> + Unpin {
+    loop {
+        match tokio::time::timeout(HEARTBEAT_TIMEOUT, read.next()).await {
+            Ok(Some(Ok(Message::Text(s)))) => if inbound_sender.send(Inbound::Text(s)).is_err() {
+                warn!("Dispatch is gone, dropping the connection");
+                return;
+            },
+            Ok(Some(Ok(Message::Close(_)))) => {
+                info!("Got Close frame");
+                return;
+            }
+            Ok(Some(Ok(msg))) => warn!("Got unexpected {:?}", msg),
+            Ok(Some(Err(e))) => {
+                connector.transport_error("Got error on reading from socket", e);
+                return;
+            }
+            Ok(None) => {
+                warn!("Socket stream ended");
+                return;
+            }
+            Err(_) => {
+                error!("Nothing received for {:?}, heartbeats are missing", HEARTBEAT_TIMEOUT);
+                return;
+            }
+        }
+    }
+}
+
+async fn write_frames<S>(mut write: S, mut outbound_receiver: mpsc::UnboundedReceiver<Outbound>, connector: Arc<DeribitConnector>)
+    where S: SinkExt<Message, Error=tokio_tungstenite::tungstenite::Error> + Unpin {
+    let mut close_check = tokio::time::interval(CLOSE_POLL_INTERVAL);
+
+    loop {
+        tokio::select! {
+            outbound = outbound_receiver.recv() => match outbound {
+                Some(outbound) => if !write_frame(&mut write, outbound, &connector).await {
+                    return;
+                },
+                // only the shutdown ends the queue of a live connection
+                None => break,
+            },
+            // requests queued before the close still go out, then the queue ends
+            _ = close_check.tick() => if connector.supervisor.shutdown().is_closing() {
+                connector.close_outbound();
+            },
+        }
+    }
+
+    info!("Closing the socket");
+    if let Err(e) = write.send(Message::Close(None)).await {
+        warn!("Can't close the socket: {:?}", e);
+    }
+}
+
+async fn write_frame<S>(write: &mut S, outbound: Outbound, connector: &DeribitConnector) -> bool
+    where S: SinkExt<Message, Error=tokio_tungstenite::tungstenite::Error> + Unpin {
+    connector.mark_written(&outbound);
+    if let Err(e) = write.send(Message::Text(outbound.text)).await {
+        connector.transport_error("Got error on writing to socket", e);
+        return false;
+    }
+    connector.latency.record(LatencyKind::Queue, &outbound.method, outbound.queued_at.elapsed());
+
+    true
+}
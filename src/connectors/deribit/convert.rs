@@ -0,0 +1,249 @@
+use crate::connectors::deribit::protocol::{self, AccountSummary, Action, Direction, OptionMarkPrice, OrderState, OrderbookChange, Portfolio, PriceIndex, PriceLevel, UserTrade};
+use crate::core::domain::{self, AccountState, Fill, IndexPrice, InstrumentKind, MarkPrice, OrderStatus, OrderbookUpdate, PriceLevelAction, PriceLevelChange, Side};
+
+impl From<Direction> for Side {
+    fn from(direction: Direction) -> Side {
+        match direction {
+            Direction::Buy => Side::Bid,
+            Direction::Sell => Side::Ask,
+        }
+    }
+}
+
+impl From<Side> for Direction {
+    fn from(side: Side) -> Direction {
+        match side {
+            Side::Bid => Direction::Buy,
+            Side::Ask => Direction::Sell,
+        }
+    }
+}
+
+impl From<OrderState> for OrderStatus {
+    fn from(order_state: OrderState) -> OrderStatus {
+        match order_state {
+            OrderState::Open => OrderStatus::Open,
+            OrderState::Filled => OrderStatus::Filled,
+            OrderState::Rejected => OrderStatus::Rejected,
+            OrderState::Cancelled => OrderStatus::Cancelled,
+            OrderState::Untriggered => OrderStatus::Untriggered,
+        }
+    }
+}
+
+impl From<Action> for PriceLevelAction {
+    fn from(action: Action) -> PriceLevelAction {
+        match action {
+            Action::New => PriceLevelAction::New,
+            Action::Change => PriceLevelAction::Change,
+            Action::Delete => PriceLevelAction::Delete,
+        }
+    }
+}
+
+impl From<PriceLevel> for PriceLevelChange {
+    fn from(level: PriceLevel) -> PriceLevelChange {
+        PriceLevelChange {
+            action: level.action.into(),
+            price: level.price,
+            amount: level.amount,
+        }
+    }
+}
+
+impl From<OrderbookChange> for OrderbookUpdate {
+    fn from(change: OrderbookChange) -> OrderbookUpdate {
+        OrderbookUpdate {
+            timestamp: change.timestamp,
+            instrument_name: change.instrument_name,
+            change_id: change.change_id,
+            bids: change.bids.into_iter().map(PriceLevelChange::from).collect(),
+            asks: change.asks.into_iter().map(PriceLevelChange::from).collect(),
+        }
+    }
+}
+
+impl From<protocol::InstrumentKind> for InstrumentKind {
+    fn from(kind: protocol::InstrumentKind) -> InstrumentKind {
+        match kind {
+            protocol::InstrumentKind::Future => InstrumentKind::Future,
+            protocol::InstrumentKind::Option => InstrumentKind::Option,
+            protocol::InstrumentKind::Spot => InstrumentKind::Spot,
+            protocol::InstrumentKind::FutureCombo | protocol::InstrumentKind::OptionCombo => InstrumentKind::Combo,
+        }
+    }
+}
+
+impl From<protocol::Instrument> for domain::Instrument {
+    fn from(instrument: protocol::Instrument) -> domain::Instrument {
+        domain::Instrument {
+            kind: instrument.kind.into(),
+            name: instrument.instrument_name,
+            base_currency: instrument.base_currency,
+            quote_currency: instrument.quote_currency,
+            tick_size: instrument.tick_size,
+            min_trade_amount: instrument.min_trade_amount,
+            contract_size: instrument.contract_size,
+            expiration_timestamp: instrument.expiration_timestamp,
+        }
+    }
+}
+
+impl From<protocol::Order> for domain::Order {
+    fn from(order: protocol::Order) -> domain::Order {
+        domain::Order {
+            direction: order.direction.into(),
+            status: order.order_state.into(),
+            id: order.order_id,
+            instrument: order.instrument_name,
+            price: order.price,
+            amount: order.amount,
+            filled_amount: order.filled_amount,
+            label: order.label,
+        }
+    }
+}
+
+impl From<protocol::Trade> for domain::Trade {
+    fn from(trade: protocol::Trade) -> domain::Trade {
+        domain::Trade {
+            direction: trade.direction.into(),
+            timestamp: trade.timestamp,
+            instrument_name: trade.instrument_name,
+            trade_id: trade.trade_id,
+            trade_seq: trade.trade_seq,
+            price: trade.price,
+            amount: trade.amount,
+            mark_price: trade.mark_price,
+            index_price: trade.index_price,
+        }
+    }
+}
+
+impl From<UserTrade> for Fill {
+    fn from(trade: UserTrade) -> Fill {
+        Fill {
+            direction: trade.direction.into(),
+            timestamp: trade.timestamp,
+            instrument_name: trade.instrument_name,
+            trade_id: trade.trade_id,
+            order_id: trade.order_id,
+            label: trade.label,
+            price: trade.price,
+            amount: trade.amount,
+        }
+    }
+}
+
+impl From<protocol::Ticker> for domain::Ticker {
+    fn from(ticker: protocol::Ticker) -> domain::Ticker {
+        domain::Ticker {
+            timestamp: ticker.timestamp,
+            instrument_name: ticker.instrument_name,
+            best_bid_price: ticker.best_bid_price,
+            best_bid_amount: ticker.best_bid_amount,
+            best_ask_price: ticker.best_ask_price,
+            best_ask_amount: ticker.best_ask_amount,
+            last_price: ticker.last_price,
+            mark_price: ticker.mark_price,
+            index_price: ticker.index_price,
+            open_interest: ticker.open_interest,
+            current_funding: ticker.current_funding,
+            funding_8h: ticker.funding_8h,
+        }
+    }
+}
+
+impl From<PriceIndex> for IndexPrice {
+    fn from(index: PriceIndex) -> IndexPrice {
+        IndexPrice {
+            timestamp: index.timestamp,
+            index_name: index.index_name,
+            price: index.price,
+        }
+    }
+}
+
+impl From<OptionMarkPrice> for MarkPrice {
+    fn from(mark_price: OptionMarkPrice) -> MarkPrice {
+        MarkPrice {
+            timestamp: mark_price.timestamp,
+            instrument_name: mark_price.instrument_name,
+            mark_price: mark_price.mark_price,
+            iv: Some(mark_price.iv),
+        }
+    }
+}
+
+impl From<Portfolio> for AccountState {
+    fn from(portfolio: Portfolio) -> AccountState {
+        AccountState {
+            currency: portfolio.currency,
+            balance: portfolio.balance,
+            equity: portfolio.equity,
+            available_funds: portfolio.available_funds,
+            margin_balance: portfolio.margin_balance,
+            initial_margin: portfolio.initial_margin,
+            maintenance_margin: portfolio.maintenance_margin,
+            estimated_liquidation_ratio: portfolio.estimated_liquidation_ratio,
+            delta_total: portfolio.delta_total,
+            options_delta: portfolio.options_delta,
+            options_gamma: portfolio.options_gamma,
+            options_vega: portfolio.options_vega,
+            options_theta: portfolio.options_theta,
+            session_rpl: portfolio.session_rpl,
+            session_upl: portfolio.session_upl,
+            total_pl: portfolio.total_pl,
+        }
+    }
+}
+
+impl From<AccountSummary> for AccountState {
+    fn from(summary: AccountSummary) -> AccountState {
+        AccountState {
+            currency: summary.currency,
+            balance: summary.balance,
+            equity: summary.equity,
+            available_funds: summary.available_funds,
+            margin_balance: summary.margin_balance,
+            initial_margin: summary.initial_margin,
+            maintenance_margin: summary.maintenance_margin,
+            estimated_liquidation_ratio: summary.estimated_liquidation_ratio,
+            delta_total: summary.delta_total,
+            options_delta: summary.options_delta,
+            options_gamma: summary.options_gamma,
+            options_vega: summary.options_vega,
+            options_theta: summary.options_theta,
+            session_rpl: summary.session_rpl,
+            session_upl: summary.session_upl,
+            total_pl: summary.total_pl,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn check_order_conversion() {
+        let order = r#"{"web":true,"time_in_force":"good_til_cancelled","risk_reducing":false,"replaced":false,"reject_post_only":false,"reduce_only":false,"profit_loss":0.0,"price":19094.0,"post_only":true,"order_type":"limit","order_state":"open","order_id":"14490265484","mmp":false,"max_show":10.0,"last_update_timestamp":1665867451646,"label":"ct1-1","is_liquidation":false,"instrument_name":"BTC-PERPETUAL","filled_amount":0.0,"direction":"sell","creation_timestamp":1665867451646,"commission":0.0,"average_price":0.0,"api":false,"amount":10.0}"#;
+
+        let order: domain::Order = serde_json::from_str::<protocol::Order>(order).unwrap().into();
+
+        assert_eq!(order.direction, Side::Ask);
+        assert_eq!(order.status, OrderStatus::Open);
+        assert_eq!(order.label, "ct1-1");
+    }
+
+    #[test]
+    fn check_instrument_conversion() {
+        let instrument = r#"{"tick_size":0.5,"taker_commission":0.0005,"settlement_period":"perpetual","quote_currency":"USD","min_trade_amount":10.0,"maker_commission":0.0,"kind":"future","is_active":true,"instrument_name":"BTC-PERPETUAL","expiration_timestamp":32503708800000,"creation_timestamp":1534242287000,"contract_size":10.0,"base_currency":"BTC"}"#;
+
+        let instrument: domain::Instrument = serde_json::from_str::<protocol::Instrument>(instrument).unwrap().into();
+
+        assert_eq!(instrument.name, "BTC-PERPETUAL");
+        assert_eq!(instrument.kind, InstrumentKind::Future);
+        assert_eq!(instrument.contract_size, rust_decimal::Decimal::TEN);
+    }
+}
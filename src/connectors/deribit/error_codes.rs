@@ -0,0 +1,111 @@
+use crate::core::domain::{ErrorAction, ErrorCategory};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ErrorSpec {
+    pub code: i32,
+    pub name: &'static str,
+    pub category: ErrorCategory,
+    pub action: ErrorAction,
+}
+
+const fn spec(code: i32, name: &'static str, category: ErrorCategory, action: ErrorAction) -> ErrorSpec {
+    ErrorSpec { code, name, category, action }
+}
+
+use ErrorAction::*;
+use ErrorCategory::*;
+
+// https://docs.deribit.com/#rpc-error-codes, the message of an error is its name
+const CATALOG: &[ErrorSpec] = &[
+    spec(10028, "too_many_requests", Retryable, Backoff),
+    spec(10066, "too_many_concurrent_requests", Retryable, Backoff),
+    spec(10047, "matching_engine_queue_full", Retryable, Backoff),
+    spec(10041, "settlement_in_progress", Retryable, Backoff),
+    spec(10040, "retry", Retryable, Backoff),
+    spec(11051, "system_maintenance", Retryable, Backoff),
+    spec(13028, "temporarily_unavailable", Retryable, Backoff),
+    spec(13043, "quotes_frozen", Retryable, Backoff),
+    // expired or revoked token
+    spec(13009, "unauthorized", Retryable, Reauthenticate),
+    spec(10000, "authorization_required", Retryable, Reauthenticate),
+
+    spec(10005, "price_too_low", Rejected, Reprice),
+    spec(10006, "price_too_low4idx", Rejected, Reprice),
+    spec(10007, "price_too_high", Rejected, Reprice),
+    spec(10008, "price_too_high4idx", Rejected, Reprice),
+    spec(11054, "post_only_reject", Rejected, Reprice),
+
+    spec(10002, "qty_too_low", Rejected, Alert),
+    spec(10009, "not_enough_funds", Rejected, Alert),
+    spec(10013, "pme_max_total_open_orders", Rejected, Alert),
+    spec(10014, "pme_max_future_open_orders", Rejected, Alert),
+    spec(10016, "pme_max_future_open_orders_size", Rejected, Alert),
+    spec(10018, "non_pme_max_future_position_size", Rejected, Alert),
+
+    // cancels racing with fills and exchange side cancels
+    spec(10004, "order_not_found", Rejected, Ignore),
+    spec(10010, "already_closed", Rejected, Ignore),
+    spec(11008, "already_filled", Rejected, Ignore),
+    spec(11044, "not_open_order", Rejected, Ignore),
+    // MMP already freezes quoting
+    spec(13030, "mmp_trigger", Rejected, Ignore),
+    spec(13906, "cancelled_due_to_mmp_trigger", Rejected, Ignore),
+
+    spec(9999, "api_not_enabled", Config, Alert),
+    spec(10020, "invalid_or_unsupported_instrument", Config, Alert),
+    spec(10021, "invalid_amount", Config, Alert),
+    spec(10022, "invalid_quantity", Config, Alert),
+    spec(10023, "invalid_price", Config, Alert),
+    spec(10026, "price_precision_exceeded", Config, Alert),
+    spec(10027, "non_integer_contract_amount", Config, Alert),
+    spec(10043, "price_wrong_tick", Config, Alert),
+    spec(11029, "invalid_arguments", Config, Alert),
+    spec(11042, "permission_denied", Config, Alert),
+    spec(11043, "bad_argument", Config, Alert),
+    spec(11049, "bad_arguments", Config, Alert),
+    spec(11050, "bad_request", Config, Alert),
+    spec(13021, "forbidden", Config, Alert),
+    spec(13025, "method_switched_off_by_admin", Config, Alert),
+    spec(13032, "non_unique_order_label", Config, Alert),
+    spec(13040, "mmp_required", Config, Alert),
+    spec(13042, "cod_not_enabled", Config, Alert),
+    spec(13403, "scope_exceeded", Config, Alert),
+    spec(13902, "mass_quotes_disabled", Config, Alert),
+    spec(-32000, "Missing params", Config, Alert),
+    spec(-32601, "Method not found", Config, Alert),
+    spec(-32602, "Invalid params", Config, Alert),
+
+    spec(10019, "locked_by_admin", Fatal, Halt),
+    spec(13002, "account_blocked", Fatal, Halt),
+    spec(13004, "invalid_credentials", Fatal, Halt),
+];
+
+// codes are matched first, the message only when the code isn't listed.
+// unknown errors are alerted on and otherwise treated as a rejection
+pub fn lookup(code: i32, message: &str) -> ErrorSpec {
+    CATALOG.iter().find(|spec| spec.code == code)
+        .or_else(|| CATALOG.iter().find(|spec| spec.name == message))
+        .cloned()
+        .unwrap_or(ErrorSpec { code, name: "unknown", category: Rejected, action: Alert })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn check_lookup() {
+        assert_eq!(lookup(10028, "too_many_requests").action, Backoff);
+        assert_eq!(lookup(10009, "not_enough_funds").category, Rejected);
+        // the code wins, names are matched when the code isn't listed
+        assert_eq!(lookup(10028, "post_only_reject").action, Backoff);
+        assert_eq!(lookup(1, "post_only_reject").action, Reprice);
+        assert_eq!(lookup(13009, "unauthorized, token expired").action, Reauthenticate);
+        assert_eq!(lookup(13004, "invalid_credentials").action, Halt);
+        assert_eq!(lookup(42, "new_error"), ErrorSpec { code: 42, name: "unknown", category: Rejected, action: Alert });
+
+        for (i, spec) in CATALOG.iter().enumerate() {
+            assert!(CATALOG[i + 1..].iter().all(|other| other.code != spec.code && other.name != spec.name), "{:?} is listed twice", spec);
+        }
+    }
+}
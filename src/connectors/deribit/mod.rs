@@ -1,3 +1,6 @@
 pub mod protocol;
 pub mod ws_connector;
-
+pub mod rate_limit;
+pub mod async_connector;
+pub mod convert;
+pub mod error_codes;
@@ -2,6 +2,7 @@ use serde::{Serialize, Deserialize};
 use serde_json::Value;
 use rust_decimal::Decimal;
 use uuid::Uuid;
+use crate::connectors::error::ConnectorError;
 
 
 #[derive(Serialize, Deserialize)]
@@ -17,16 +18,90 @@ pub(crate) struct RpcError {
 pub(crate) enum Params {
     Channels { channels: Vec<String> },
     Interval { interval: u32 },
-    Auth { grant_type: String, client_id: String, client_secret: String },
-    Order { instrument_name: String, price: Decimal, amount: Decimal, post_only: bool, label: String },
+    Auth {
+        grant_type: String,
+        client_id: String,
+        client_secret: String,
+        // space separated, e.g. "session:ct trade:read"
+        #[serde(skip_serializing_if = "Option::is_none")]
+        scope: Option<String>,
+    },
+    RefreshToken { grant_type: String, refresh_token: String },
+    Order { instrument_name: String, price: Decimal, amount: Decimal, post_only: bool, reduce_only: bool, label: String, mmp: bool },
+    OrderId { order_id: String },
+    Currency { currency: String },
+    Instruments { currency: String, kind: String },
+    Label { currency: String, label: String },
+    Scope { scope: String },
+    IndexName { index_name: String },
+    MassQuote {
+        quote_id: String,
+        mmp_group: String,
+        detailed: bool,
+        quotes: Vec<MassQuote>,
+    },
+    MmpConfig {
+        index_name: String,
+        interval: u32,
+        frozen_time: u32,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        quantity_limit: Option<Decimal>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        delta_limit: Option<Decimal>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        mmp_group: Option<String>,
+    },
+    ReduceOnlyOrder {
+        instrument_name: String,
+        amount: Decimal,
+        #[serde(rename = "type")]
+        order_type: OrderType,
+        reduce_only: bool,
+    },
+}
+
+#[derive(Serialize, Deserialize)]
+#[derive(Debug)]
+pub(crate) struct QuoteSide {
+    pub price: Decimal,
+    pub amount: Decimal,
+    pub post_only: bool,
+}
+
+#[derive(Serialize, Deserialize)]
+#[derive(Debug)]
+pub(crate) struct MassQuote {
+    pub instrument_name: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub bid: Option<QuoteSide>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub ask: Option<QuoteSide>,
+}
+
+// result of private/mass_quote with detailed = true
+#[derive(Serialize, Deserialize)]
+#[derive(Debug)]
+pub(crate) struct MassQuoteResult {
+    pub orders: Vec<Order>,
+    #[serde(default)]
+    pub errors: Vec<MassQuoteError>,
+}
+
+#[derive(Serialize, Deserialize)]
+#[derive(Debug)]
+pub(crate) struct MassQuoteError {
+    pub instrument_name: String,
+    pub side: Direction,
+    pub code: Option<i32>,
+    pub message: Option<String>,
 }
 
 #[derive(Serialize, Deserialize)]
 #[derive(Debug)]
 pub(crate) struct JsonRpcRequest {
     jsonrpc: String,
-    method: String,
-    id: Uuid,
+    pub(crate) method: String,
+    pub(crate) id: Uuid,
     #[serde(skip_serializing_if = "Option::is_none")]
     params: Option<Params>,
 }
@@ -103,35 +178,30 @@ pub struct OrderbookChange {
 }
 
 impl OrderbookChange {
-    pub fn new(s: Value) -> OrderbookChange {
-        let timestamp = s["timestamp"].clone().as_i64().unwrap();
-        let change_id = s["change_id"].clone().as_i64().unwrap();
-        let instrument_name = s["instrument_name"].clone().as_str().unwrap().to_string();
-
-
-        let l = s["bids"].as_array().unwrap().clone();
-
-        let bids = l.into_iter().map(|q| serde_json::from_value(q).unwrap()).collect();
-        let asks = s["asks"].as_array().unwrap().clone().into_iter().map(|q| serde_json::from_value(q).unwrap()).collect();
-
+    // price levels come as ["new", price, amount]
+    pub fn new(s: Value) -> Result<OrderbookChange, ConnectorError> {
+        let field = |name: &str| s[name].as_i64().ok_or_else(|| ConnectorError::Parse(format!("book update without {}", name)));
+        let levels = |name: &str| -> Result<Vec<PriceLevel>, ConnectorError> {
+            s[name].as_array()
+                .ok_or_else(|| ConnectorError::Parse(format!("book update without {}", name)))?
+                .iter()
+                .map(|level| serde_json::from_value(level.clone())
+                    .map_err(|e| ConnectorError::Parse(format!("bad {} level {}: {}", name, level, e))))
+                .collect()
+        };
 
-        OrderbookChange {
-            timestamp,
-            instrument_name,
-            change_id,
-            bids,
-            asks,
-        }
+        Ok(OrderbookChange {
+            timestamp: field("timestamp")?,
+            instrument_name: s["instrument_name"].as_str()
+                .ok_or_else(|| ConnectorError::Parse("book update without instrument_name".to_string()))?
+                .to_string(),
+            change_id: field("change_id")?,
+            bids: levels("bids")?,
+            asks: levels("asks")?,
+        })
     }
 }
 
-// todo union with order_manager
-#[derive(Debug)]
-pub enum TradeDirection {
-    Bid,
-    Ask,
-}
-
 
 #[derive(Serialize, Deserialize)]
 #[serde(rename_all = "camelCase")]
@@ -157,6 +227,7 @@ pub enum TimeInForce {
 #[derive(Serialize, Deserialize)]
 #[serde(rename_all = "snake_case")]
 #[derive(Debug)]
+#[derive(PartialEq)]
 pub enum Direction {
     Buy,
     Sell,
@@ -184,14 +255,14 @@ pub enum Trigger {
 #[derive(Serialize, Deserialize)]
 #[derive(Debug)]
 pub struct Order {
-    mmp_cancelled: Option<bool>,
+    pub(crate) mmp_cancelled: Option<bool>,
     pub(crate) order_state: OrderState,
     max_show: Decimal,
     reject_post_only: Option<bool>,
     api: bool,
     pub(crate) amount: Decimal,
     web: Option<bool>,
-    instrument_name: String,
+    pub(crate) instrument_name: String,
     advanced: Option<String>,
     triggered: Option<bool>,
     block_trade: Option<bool>,
@@ -203,7 +274,7 @@ pub struct Order {
     last_update_timestamp: i64,
     post_only: bool,
     replaced: bool,
-    filled_amount: Decimal,
+    pub(crate) filled_amount: Decimal,
     average_price: Decimal,
     pub(crate) order_id: String,
     reduce_only: bool,
@@ -228,57 +299,192 @@ pub struct Order {
 #[derive(Serialize, Deserialize)]
 #[derive(Debug)]
 pub struct Portfolio {
-    available_funds: Decimal,
+    pub(crate) available_funds: Decimal,
     available_withdrawal_funds: Decimal,
-    pub balance: Decimal,
-    currency: String,
-    delta_total: Decimal,
+    pub(crate) balance: Decimal,
+    pub(crate) currency: String,
+    pub(crate) delta_total: Decimal,
     delta_total_map: Value,
-    equity: Decimal,
-    estimated_liquidation_ratio: Option<Decimal>,
+    pub(crate) equity: Decimal,
+    pub(crate) estimated_liquidation_ratio: Option<Decimal>,
     estimated_liquidation_ratio_map: Option<Value>,
     fee_balance: Decimal,
     futures_pl: Decimal,
     futures_session_rpl: Decimal,
     futures_session_upl: Decimal,
-    initial_margin: Decimal,
-    maintenance_margin: Decimal,
-    margin_balance: Decimal,
-    options_delta: Decimal,
-    options_gamma: Decimal,
+    pub(crate) initial_margin: Decimal,
+    pub(crate) maintenance_margin: Decimal,
+    pub(crate) margin_balance: Decimal,
+    pub(crate) options_delta: Decimal,
+    pub(crate) options_gamma: Decimal,
     options_pl: Decimal,
     options_session_rpl: Decimal,
     options_session_upl: Decimal,
-    options_theta: Decimal,
+    pub(crate) options_theta: Decimal,
     options_value: Decimal,
-    options_vega: Decimal,
+    pub(crate) options_vega: Decimal,
     portfolio_margining_enabled: bool,
     projected_delta_total: Decimal,
     projected_initial_margin: Decimal,
     projected_maintenance_margin: Decimal,
-    session_rpl: Decimal,
-    session_upl: Decimal,
-    total_pl: Decimal,
+    pub(crate) session_rpl: Decimal,
+    pub(crate) session_upl: Decimal,
+    pub(crate) total_pl: Decimal,
+}
+
+#[derive(Serialize, Deserialize)]
+#[derive(Debug)]
+pub struct Position {
+    pub(crate) instrument_name: String,
+    pub(crate) kind: String,
+    // negative for short positions
+    pub(crate) size: Decimal,
+    average_price: Decimal,
+    mark_price: Decimal,
+    floating_profit_loss: Option<Decimal>,
+    total_profit_loss: Decimal,
+}
+
+// result of public/auth, the socket is authorized by the request itself so the access token isn't kept
+#[derive(Serialize, Deserialize)]
+#[derive(Debug)]
+pub struct AuthResult {
+    pub(crate) refresh_token: String,
+    // seconds
+    pub(crate) expires_in: u64,
+    pub(crate) scope: String,
+}
+
+#[derive(Serialize, Deserialize)]
+#[derive(Debug)]
+pub struct AccountSummary {
+    pub(crate) currency: String,
+    pub(crate) balance: Decimal,
+    pub(crate) equity: Decimal,
+    pub(crate) available_funds: Decimal,
+    pub(crate) margin_balance: Decimal,
+    pub(crate) initial_margin: Decimal,
+    pub(crate) maintenance_margin: Decimal,
+    pub(crate) estimated_liquidation_ratio: Option<Decimal>,
+    pub(crate) delta_total: Decimal,
+    pub(crate) options_delta: Decimal,
+    pub(crate) options_gamma: Decimal,
+    pub(crate) options_vega: Decimal,
+    pub(crate) options_theta: Decimal,
+    pub(crate) session_rpl: Decimal,
+    pub(crate) session_upl: Decimal,
+    pub(crate) total_pl: Decimal,
 }
 
 #[derive(Serialize, Deserialize)]
 #[derive(Debug)]
 pub struct Trade {
-    amount: Decimal,
+    pub(crate) amount: Decimal,
     block_trade_id: Option<String>,
-    direction: Direction,
-    index_price: Decimal,
-    instrument_name: String,
+    pub(crate) direction: Direction,
+    pub(crate) index_price: Decimal,
+    pub(crate) instrument_name: String,
     iv: Option<Decimal>,
     liquidation: Option<String>,
-    mark_price: Decimal,
-    price: Decimal,
+    pub(crate) mark_price: Decimal,
+    pub(crate) price: Decimal,
     tick_direction: u8,
-    timestamp: i64,
-    trade_id: String,
+    pub(crate) timestamp: i64,
+    pub(crate) trade_id: String,
+    pub(crate) trade_seq: i64
+}
+
+// execution of an own order from user.trades
+#[derive(Serialize, Deserialize)]
+#[derive(Debug)]
+pub struct UserTrade {
+    pub(crate) amount: Decimal,
+    pub(crate) direction: Direction,
+    pub(crate) instrument_name: String,
+    pub(crate) order_id: String,
+    #[serde(default)]
+    pub(crate) label: String,
+    pub(crate) price: Decimal,
+    fee: Decimal,
+    fee_currency: String,
+    pub(crate) timestamp: i64,
+    pub(crate) trade_id: String,
     trade_seq: i64
 }
 
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+#[derive(Debug)]
+pub enum InstrumentKind {
+    Future,
+    Option,
+    Spot,
+    FutureCombo,
+    OptionCombo,
+}
+
+// public/get_instruments
+#[derive(Serialize, Deserialize)]
+#[derive(Debug)]
+pub struct Instrument {
+    pub(crate) instrument_name: String,
+    pub(crate) kind: InstrumentKind,
+    pub(crate) base_currency: String,
+    pub(crate) quote_currency: String,
+    pub(crate) tick_size: Decimal,
+    pub(crate) min_trade_amount: Decimal,
+    pub(crate) contract_size: Decimal,
+    pub(crate) expiration_timestamp: i64,
+}
+
+// public/ticker and the ticker channel
+#[derive(Serialize, Deserialize)]
+#[derive(Debug)]
+pub struct Ticker {
+    pub(crate) timestamp: i64,
+    pub(crate) instrument_name: String,
+    pub(crate) best_bid_price: Option<Decimal>,
+    pub(crate) best_bid_amount: Decimal,
+    pub(crate) best_ask_price: Option<Decimal>,
+    pub(crate) best_ask_amount: Decimal,
+    pub(crate) last_price: Option<Decimal>,
+    pub(crate) mark_price: Decimal,
+    pub(crate) index_price: Decimal,
+    pub(crate) open_interest: Decimal,
+    // perpetuals only
+    pub(crate) current_funding: Option<Decimal>,
+    pub(crate) funding_8h: Option<Decimal>,
+    state: String,
+}
+
+// deribit_price_index.{index_name}
+#[derive(Serialize, Deserialize)]
+#[derive(Debug)]
+pub struct PriceIndex {
+    pub(crate) timestamp: i64,
+    pub(crate) index_name: String,
+    pub(crate) price: Decimal,
+}
+
+// markprice.options.{index_name}, comes as a list
+#[derive(Serialize, Deserialize)]
+#[derive(Debug)]
+pub struct OptionMarkPrice {
+    pub(crate) timestamp: i64,
+    pub(crate) instrument_name: String,
+    pub(crate) mark_price: Decimal,
+    pub(crate) iv: Decimal,
+}
+
+// perpetual.{instrument_name}.{interval}, the instrument is only in the channel name
+#[derive(Serialize, Deserialize)]
+#[derive(Debug)]
+pub struct PerpetualUpdate {
+    pub(crate) timestamp: i64,
+    pub(crate) interest: Decimal,
+    pub(crate) index_price: Decimal,
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -328,7 +534,7 @@ mod tests {
             Response::Notification { jsonrpc, method, params } => {
                 let data: serde_json::Value = params["data"].clone();
 
-                let update = OrderbookChange::new(data);
+                let update = OrderbookChange::new(data).unwrap();
 
                 assert_eq!(update.bids, vec!(PriceLevel { action: Action::New, price: Decimal::from_f64_retain(1898.0).unwrap(), amount: Decimal::from_f64_retain(1150.0).unwrap() }, PriceLevel { action: Action::New, price: Decimal::from_f64_retain(2222.0).unwrap(), amount: Decimal::from_f64_retain(333.0).unwrap() }))
             }
@@ -336,6 +542,15 @@ mod tests {
         };
     }
 
+    #[test]
+    fn check_bad_book_update_is_an_error() {
+        let missing_asks = serde_json::json!({"timestamp": 1662760941557_i64, "instrument_name": "ETH-PERPETUAL", "change_id": 1, "bids": []});
+        let bad_level = serde_json::json!({"timestamp": 1662760941557_i64, "instrument_name": "ETH-PERPETUAL", "change_id": 1, "bids": [["new", "x", 1.0]], "asks": []});
+
+        assert!(matches!(OrderbookChange::new(missing_asks), Err(ConnectorError::Parse(message)) if message == "book update without asks"));
+        assert!(matches!(OrderbookChange::new(bad_level), Err(ConnectorError::Parse(message)) if message.starts_with("bad bids level")));
+    }
+
     #[test]
     fn check_order_deserialize() {
         let order = r#"{"jsonrpc":"2.0","method":"subscription","params":{"channel":"user.orders.BTC-PERPETUAL.raw","data":{"web":true,"time_in_force":"good_til_cancelled","risk_reducing":false,"replaced":false,"reject_post_only":false,"reduce_only":false,"profit_loss":0.0,"price":19094.0,"post_only":true,"order_type":"limit","order_state":"open","order_id":"14490265484","mmp":false,"max_show":10.0,"last_update_timestamp":1665867451646,"label":"","is_liquidation":false,"instrument_name":"BTC-PERPETUAL","filled_amount":0.0,"direction":"buy","creation_timestamp":1665867451646,"commission":0.0,"average_price":0.0,"api":false,"amount":10.0}}}"#;
@@ -354,6 +569,45 @@ mod tests {
         };
     }
 
+    #[test]
+    fn check_position_deserialize() {
+        let positions = r#"{"jsonrpc":"2.0","id":"b1288e7d-5f00-4d7f-b89f-66ae19b56563","result":[{"total_profit_loss":0.000507756,"size_currency":-0.001451008,"size":-20.0,"settlement_price":13803.79,"realized_profit_loss":-2.3e-8,"realized_funding":-2e-8,"open_orders_margin":0.0,"mark_price":13783.6,"maintenance_margin":0.000014514,"leverage":50,"kind":"future","interest_value":1.722635,"instrument_name":"BTC-PERPETUAL","initial_margin":0.000029028,"index_price":13787.16,"floating_profit_loss":0.000032066,"estimated_liquidation_price":null,"direction":"sell","delta":-0.001451008,"average_price":13797.84}],"usIn":1662838375635617,"usOut":1662838375635666,"usDiff":49,"testnet":true}"#;
+
+        let v: Response = serde_json::from_str(positions).unwrap();
+
+        match v {
+            Response::Result { result, .. } => {
+                let positions: Vec<Position> = serde_json::from_value(result).unwrap();
+
+                assert_eq!(positions[0].instrument_name, "BTC-PERPETUAL");
+                assert_eq!(positions[0].size, Decimal::from(-20));
+            }
+            _ => panic!("Unexpected parsing result"),
+        };
+    }
+
+    #[test]
+    fn check_mass_quote_serialize() {
+        let quotes = Params::MassQuote {
+            quote_id: "q1".to_string(),
+            mmp_group: "ct".to_string(),
+            detailed: true,
+            quotes: vec!(MassQuote {
+                instrument_name: "BTC-PERPETUAL".to_string(),
+                bid: Some(QuoteSide { price: Decimal::from(19000), amount: Decimal::from(10), post_only: true }),
+                ask: None,
+            }),
+        };
+
+        let request = JsonRpcRequest::new("private/mass_quote".to_string(), Uuid::nil(), Some(quotes));
+        let json: Value = serde_json::to_value(&request).unwrap();
+
+        assert_eq!(json["params"]["mmp_group"], "ct");
+        assert_eq!(json["params"]["quotes"][0]["instrument_name"], "BTC-PERPETUAL");
+        assert_eq!(json["params"]["quotes"][0]["bid"]["post_only"], true);
+        assert!(json["params"]["quotes"][0].get("ask").is_none());
+    }
+
     #[test]
     fn check_subscription_request_serialize() {
         let expected = r#"{"jsonrpc": "2.0",
@@ -366,4 +620,39 @@ mod tests {
                 "id" : 42,
                 "method" : "public/set_heartbeat", "params" : {"interval" : 60}}"#;
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn check_market_data_deserialize() {
+        let ticker = r#"{"timestamp":1623060194301,"stats":{"volume_usd":284061480,"volume":7871.02139035,"price_change":0.7229,"low":35213.5,"high":36824.5},"state":"open","settlement_price":36169.49,"open_interest":502097590,"min_price":35898.37,"max_price":36991.72,"mark_price":36446.51,"last_price":36457.5,"instrument_name":"BTC-PERPETUAL","index_price":36441.64,"funding_8h":0.0000211,"estimated_delivery_price":36441.64,"current_funding":0,"best_bid_price":36442.5,"best_bid_amount":5000,"best_ask_price":36443,"best_ask_amount":100}"#;
+        let index = r#"{"timestamp":1550588002899,"price":3937.89,"index_name":"btc_usd"}"#;
+        let mark_prices = r#"[{"timestamp":1622470378005,"mark_price":0.0333,"iv":0.9,"instrument_name":"BTC-2JUN21-37000-P"}]"#;
+        let perpetual = r#"{"timestamp":1571737742004,"interest":-0.0000025,"index_price":8234.2}"#;
+
+        let ticker: Ticker = serde_json::from_str(ticker).unwrap();
+        assert_eq!(ticker.open_interest, Decimal::from(502097590));
+        assert_eq!(ticker.funding_8h, Some(Decimal::from_str_exact("0.0000211").unwrap()));
+
+        let index: PriceIndex = serde_json::from_str(index).unwrap();
+        assert_eq!(index.index_name, "btc_usd");
+
+        let mark_prices: Vec<OptionMarkPrice> = serde_json::from_str(mark_prices).unwrap();
+        assert_eq!(mark_prices[0].instrument_name, "BTC-2JUN21-37000-P");
+
+        let perpetual: PerpetualUpdate = serde_json::from_str(perpetual).unwrap();
+        assert!(perpetual.interest.is_sign_negative());
+    }
+
+    #[test]
+    fn check_auth() {
+        let auth = Params::Auth { grant_type: "client_credentials".to_string(), client_id: "id".to_string(), client_secret: "secret".to_string(), scope: None };
+        assert_eq!(serde_json::to_string(&auth).unwrap(), r#"{"grant_type":"client_credentials","client_id":"id","client_secret":"secret"}"#);
+
+        let refresh = Params::RefreshToken { grant_type: "refresh_token".to_string(), refresh_token: "1582628593469.1GP4rQd0".to_string() };
+        assert_eq!(serde_json::to_string(&refresh).unwrap(), r#"{"grant_type":"refresh_token","refresh_token":"1582628593469.1GP4rQd0"}"#);
+
+        let result = r#"{"access_token":"1582628593469.1MbQ-J_4","expires_in":31536000,"refresh_token":"1582628593469.1GP4rQd0","scope":"connection mainaccount session:ct trade:read","token_type":"bearer"}"#;
+        let result: AuthResult = serde_json::from_str(result).unwrap();
+        assert_eq!(result.expires_in, 31536000);
+        assert_eq!(result.scope, "connection mainaccount session:ct trade:read");
+    }
+}
@@ -0,0 +1,212 @@
+use std::time::{Duration, Instant};
+
+use log::info;
+
+use crate::config::BucketConfig;
+use crate::core::entities::Command;
+
+// deribit meters matching engine requests (orders, cancels) separately from everything else
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum EndpointClass {
+    MatchingEngine,
+    NonMatchingEngine,
+}
+
+pub fn endpoint_class(method: &str) -> EndpointClass {
+    match method {
+        "private/buy" | "private/sell" | "private/edit" | "private/edit_by_label"
+        | "private/cancel" | "private/cancel_all" | "private/cancel_all_by_currency"
+        | "private/cancel_all_by_instrument" | "private/cancel_by_label"
+        | "private/close_position" | "private/mass_quote" | "private/cancel_quotes" => EndpointClass::MatchingEngine,
+        _ => EndpointClass::NonMatchingEngine,
+    }
+}
+
+pub fn command_class(command: &Command) -> EndpointClass {
+    match command {
+        Command::MakeOrder { .. } | Command::MakeQuotes { .. } | Command::CancelOrder { .. }
+        | Command::CancelByLabel { .. } | Command::CancelAll | Command::ClosePositions { .. } => EndpointClass::MatchingEngine,
+        _ => EndpointClass::NonMatchingEngine,
+    }
+}
+
+pub const ORDER_PRIORITY: u8 = 3;
+
+// lower is more urgent, cancels go before new orders
+pub fn command_priority(command: &Command) -> u8 {
+    match command {
+        Command::CancelAll | Command::CancelOrder { .. } | Command::CancelByLabel { .. } | Command::ClosePositions { .. } | Command::ResetMmp { .. } => 0,
+        Command::SendHeartBeat => 1,
+        Command::MakeOrder { .. } | Command::MakeQuotes { .. } => ORDER_PRIORITY,
+        _ => 2,
+    }
+}
+
+pub struct CreditBucket {
+    config: BucketConfig,
+    credits: f64,
+    refilled_at: Instant,
+    used: u64,
+    requests: u64,
+}
+
+impl CreditBucket {
+    pub fn new(config: BucketConfig, now: Instant) -> CreditBucket {
+        CreditBucket {
+            credits: config.capacity as f64,
+            config,
+            refilled_at: now,
+            used: 0,
+            requests: 0,
+        }
+    }
+
+    fn refill(&mut self, now: Instant) {
+        let elapsed = now.saturating_duration_since(self.refilled_at).as_secs_f64();
+        self.credits = (self.credits + elapsed * self.config.refill_rate as f64).min(self.config.capacity as f64);
+        self.refilled_at = now;
+    }
+
+    // time to wait until this many more requests fit, a batch larger than the
+    // bucket waits for a full one
+    pub fn wait_time(&mut self, now: Instant, requests: u64) -> Duration {
+        self.refill(now);
+
+        let needed = (self.config.cost * requests).min(self.config.capacity);
+        let missing = needed as f64 - self.credits;
+        if missing <= 0.0 || self.config.refill_rate == 0 {
+            Duration::ZERO
+        } else {
+            Duration::from_secs_f64(missing / self.config.refill_rate as f64)
+        }
+    }
+
+    // the request is counted even if it overdraws the bucket, session requests can't wait
+    pub fn consume(&mut self, now: Instant) {
+        self.refill(now);
+        self.credits -= self.config.cost as f64;
+        self.used += self.config.cost;
+        self.requests += 1;
+    }
+
+    // the exchange says we are over its limit, whatever we counted
+    pub fn drain(&mut self, now: Instant) {
+        self.refill(now);
+        self.credits = self.credits.min(0.0);
+    }
+
+    #[cfg(test)]
+    pub fn credits(&self) -> f64 {
+        self.credits
+    }
+}
+
+pub struct RateLimiter {
+    matching: CreditBucket,
+    non_matching: CreditBucket,
+    rejected: u64,
+    reported_at: Instant,
+}
+
+impl RateLimiter {
+    pub fn new(matching: BucketConfig, non_matching: BucketConfig) -> RateLimiter {
+        let now = Instant::now();
+
+        RateLimiter {
+            matching: CreditBucket::new(matching, now),
+            non_matching: CreditBucket::new(non_matching, now),
+            rejected: 0,
+            reported_at: now,
+        }
+    }
+
+    pub fn bucket(&mut self, class: EndpointClass) -> &mut CreditBucket {
+        match class {
+            EndpointClass::MatchingEngine => &mut self.matching,
+            EndpointClass::NonMatchingEngine => &mut self.non_matching,
+        }
+    }
+
+    pub fn wait_time(&mut self, class: EndpointClass, requests: u64, now: Instant) -> Duration {
+        self.bucket(class).wait_time(now, requests)
+    }
+
+    pub fn consume(&mut self, method: &str, now: Instant) {
+        self.bucket(endpoint_class(method)).consume(now);
+        self.report(now);
+    }
+
+    pub fn drain(&mut self, class: EndpointClass, now: Instant) {
+        self.bucket(class).drain(now);
+    }
+
+    pub fn reject(&mut self) {
+        self.rejected += 1;
+    }
+
+    fn report(&mut self, now: Instant) {
+        if now.duration_since(self.reported_at) < Duration::from_secs(60) {
+            return;
+        }
+
+        info!("Credits: matching engine {:.0} left, {} used by {} requests; non matching {:.0} left, {} used by {} requests; {} orders rejected",
+            self.matching.credits, self.matching.used, self.matching.requests,
+            self.non_matching.credits, self.non_matching.used, self.non_matching.requests,
+            self.rejected);
+
+        self.reported_at = now;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use uuid::Uuid;
+    use super::*;
+
+    #[test]
+    fn check_bucket_refill() {
+        let start = Instant::now();
+        let mut bucket = CreditBucket::new(BucketConfig { capacity: 2000, refill_rate: 1000, cost: 1000 }, start);
+
+        assert_eq!(bucket.wait_time(start, 1), Duration::ZERO);
+        bucket.consume(start);
+        bucket.consume(start);
+
+        assert_eq!(bucket.wait_time(start, 1), Duration::from_secs(1));
+        assert_eq!(bucket.wait_time(start + Duration::from_millis(500), 1), Duration::from_millis(500));
+        assert_eq!(bucket.wait_time(start + Duration::from_secs(1), 1), Duration::ZERO);
+
+        // never refills above the capacity
+        assert_eq!(bucket.wait_time(start + Duration::from_secs(100), 1), Duration::ZERO);
+        assert_eq!(bucket.credits(), 2000.0);
+
+        // a command sending several requests waits for all of them
+        let later = start + Duration::from_secs(100);
+        bucket.consume(later);
+        assert_eq!(bucket.wait_time(later, 1), Duration::ZERO);
+        assert_eq!(bucket.wait_time(later, 2), Duration::from_secs(1));
+        assert_eq!(bucket.wait_time(later, 5), Duration::from_secs(1));
+        assert_eq!(bucket.wait_time(later + Duration::from_secs(1), 2), Duration::ZERO);
+
+        // a too_many_requests from the exchange empties the bucket
+        bucket.drain(start + Duration::from_secs(101));
+        assert_eq!(bucket.wait_time(start + Duration::from_secs(101), 1), Duration::from_secs(1));
+    }
+
+    #[test]
+    fn check_endpoint_classes() {
+        assert_eq!(endpoint_class("private/buy"), EndpointClass::MatchingEngine);
+        assert_eq!(endpoint_class("private/cancel_all"), EndpointClass::MatchingEngine);
+        assert_eq!(endpoint_class("private/get_positions"), EndpointClass::NonMatchingEngine);
+        assert_eq!(endpoint_class("public/test"), EndpointClass::NonMatchingEngine);
+    }
+
+    #[test]
+    fn check_cancels_go_first() {
+        let cancel = Command::CancelOrder { id: "1".to_string() };
+        let order = Command::MakeQuotes { request_id: Uuid::new_v4(), instrument: "BTC-PERPETUAL".to_string(), legs: vec!() };
+
+        assert!(command_priority(&cancel) < command_priority(&order));
+        assert!(command_priority(&Command::SendHeartBeat) < command_priority(&order));
+    }
+}
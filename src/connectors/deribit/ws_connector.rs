@@ -1,250 +1,875 @@
 use std::net::TcpStream;
 use url::Url;
 use tungstenite::{connect, Message, WebSocket};
-use crossbeam_channel::{bounded, Sender, Receiver};
+use crossbeam_channel::{unbounded, Sender, Receiver, RecvTimeoutError, TryRecvError};
 use serde::{Serialize, Deserialize};
 use serde_json::Value;
 use tungstenite::stream::MaybeTlsStream;
-use std::error::Error;
+use std::io::ErrorKind;
+use std::collections::HashMap;
 use std::sync::{Arc, Mutex};
 use std::thread;
 use std::thread::Thread;
-use std::time::Duration;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 use rust_decimal::Decimal;
 use crossbeam_utils::thread as cbu_thread;
 
 use log::{error, info, warn};
 use uuid::Uuid;
+use crate::connectors::deribit::error_codes::{self, ErrorSpec};
 use crate::connectors::deribit::protocol::*;
+use crate::connectors::error::ConnectorError;
 use crate::strategy::order_manager;
-use crate::core::entities::{Command, OrderSide, PriceLevelAction, PriceLevelChange};
-use crate::strategy::order_manager::{Balance, OrderStatus};
-use crate::core::entities::OrderbookUpdate;
+use crate::core::domain::{self, ErrorAction, Funding, OrderbookUpdate, Side};
+use crate::core::entities::{Command, QuoteLeg};
+use crate::core::event_bus::{ConnectionEvent, Event, EventBus};
+use crate::core::latency::{Latency, LatencyKind};
+use crate::core::metrics::Metrics;
+use crate::core::supervisor::Supervisor;
+use crate::strategy::kill_switch::KillSwitch;
+use crate::strategy::trade_flow::TradeSeqTracker;
+use crate::config::Config;
+use crate::connectors::deribit::rate_limit::{command_class, command_priority, endpoint_class, EndpointClass, RateLimiter, ORDER_PRIORITY};
 
 
 pub struct DeribitConnector {
-    orderbook_sender: Sender<OrderbookUpdate>,
-    order_sender: Sender<order_manager::OrderEvent>,
-    raw_data_sender: Sender<String>,
+    events: EventBus,
     command_receiver: Receiver<Command>,
-    socket: Arc<Mutex<WebSocket<MaybeTlsStream<TcpStream>>>>,
-    portfolio_sender: Sender<Balance>,
+    // queue of the current connection, None while disconnected
+    outbound: Mutex<Option<Sender<Outbound>>>,
     command_sender: Sender<Command>,
+    pending_requests: Mutex<HashMap<Uuid, (PendingRequest, Instant)>>,
+    kill_switch: Arc<KillSwitch>,
+    config: Config,
+    // order id -> client order id, mass quote orders come without labels
+    quote_labels: Mutex<HashMap<String, String>>,
+    rate_limiter: Mutex<RateLimiter>,
+    trade_seqs: Mutex<TradeSeqTracker>,
+    pub(crate) latency: Arc<Latency>,
+    metrics: Arc<Metrics>,
+    // request id -> method and write time, for every written request until its response
+    sent_requests: Mutex<HashMap<Uuid, (String, Instant)>>,
+    pub(crate) supervisor: Arc<Supervisor>,
+    // token of the current connection, None until it is authorized
+    token: Mutex<Option<Token>>,
+    auth_retry: Mutex<AuthRetry>,
+}
+
+// how long a read waits before the queued frames are written
+const IO_POLL_INTERVAL: Duration = Duration::from_micros(200);
+const RECONNECT_INTERVAL: Duration = Duration::from_secs(1);
+pub(crate) const COMMAND_POLL_INTERVAL: Duration = Duration::from_millis(100);
+// how long to wait for the close frame of the exchange
+pub(crate) const CLOSE_TIMEOUT: Duration = Duration::from_secs(1);
+pub(crate) const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+// the exchange sends a heartbeat every 60 seconds
+pub(crate) const HEARTBEAT_TIMEOUT: Duration = Duration::from_secs(130);
+pub(crate) const CLOCK_SYNC_INTERVAL: Duration = Duration::from_secs(60);
+// a failed authorization is sent again after this, doubling up to AUTH_RETRY_MAX
+const AUTH_RETRY_MIN: Duration = Duration::from_secs(1);
+const AUTH_RETRY_MAX: Duration = Duration::from_secs(60);
+
+type Socket = WebSocket<MaybeTlsStream<TcpStream>>;
+
+pub(crate) struct Outbound {
+    pub(crate) request_id: Uuid,
+    pub(crate) method: String,
+    pub(crate) text: String,
+    pub(crate) queued_at: Instant,
+}
+
+pub(crate) enum Inbound {
+    Connected,
+    Disconnected,
+    Text(String),
+}
+
+#[derive(Debug)]
+struct Token {
+    refresh_token: String,
+    expires_at: Instant,
+    // a refresh request is on its way
+    refreshing: bool,
+}
+
+// the next attempt after a failed authorization
+#[derive(Default)]
+struct AuthRetry {
+    due: Option<(AuthReason, Instant)>,
+    delay: Duration,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum AuthReason {
+    // a new connection, the private part of the session follows the response
+    Connect,
+    Refresh,
+    // the token lapsed on a live connection
+    Reauthenticate,
+}
+
+// requests whose results are routed somewhere else than OrderSuccess
+#[derive(Debug)]
+enum PendingRequest {
+    OrderState { label: String },
+    // private/buy and private/sell
+    Order { label: String, method: &'static str },
+    OpenOrders,
+    Positions,
+    AccountSummary,
+    Instruments,
+    ClosePositions,
+    MassQuote { legs: Vec<QuoteLeg> },
+    // public/get_time, local milliseconds when it was sent
+    Time { sent_at: i64 },
+    // public/auth with the client credentials or the refresh token
+    Auth { reason: AuthReason },
+}
+
+// private commands wait until the session is authorized, orders are refused meanwhile
+fn waits_for_session(command: &Command) -> bool {
+    !matches!(command, Command::SendHeartBeat | Command::MakeOrder { .. } | Command::MakeQuotes { .. })
+}
+
+fn now_millis() -> i64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_millis() as i64
+}
+
+fn order_changed(deribit_order: Order) -> order_manager::OrderEvent {
+    order_manager::OrderEvent::OrderChanged {
+        mmp_cancelled: deribit_order.mmp_cancelled.unwrap_or(false),
+        order: deribit_order.into(),
+    }
 }
 
 impl DeribitConnector {
     pub fn run(&self) {
-        self.set_heartbeat_interval(60);
+        let (inbound_sender, inbound_receiver) = unbounded();
+
+        let supervisor = &self.supervisor;
+
+        // everything returns once the shutdown closes the connections
+        thread::scope(|s| {
+            s.spawn(|| supervisor.run("connector commands", || self.run_commands()));
+            s.spawn(move || supervisor.run("connector io", || self.run_io(inbound_sender.clone())));
+            s.spawn(|| while supervisor.shutdown().sleep(Duration::from_secs(1)) {
+                self.expire_requests();
+                self.refresh_token();
+                self.retry_auth();
+            });
+            s.spawn(|| while supervisor.shutdown().sleep(CLOCK_SYNC_INTERVAL) {
+                if let Err(e) = self.sync_clock() {
+                    warn!("Can't sync the clock: {}", e);
+                }
+            });
 
-        self.authorize();
+            supervisor.run("connector dispatch", || self.run_dispatch(&inbound_receiver));
+        });
+    }
 
-        self.subscribe_to_orders(vec!("user.orders.BTC-PERPETUAL.raw".into()));
-        self.subscribe_to_portfolio_channel(vec!("user.portfolio.btc".into()));
+    pub(crate) fn run_commands(&self) {
+        // commands wait here while there are no credits, cancels are picked first
+        let mut queue: Vec<Command> = vec!();
 
-        thread::sleep_ms(1000);
+        loop {
+            if self.supervisor.shutdown().is_closing() {
+                if !queue.is_empty() {
+                    warn!("Dropping {} queued commands on shutdown: {:?}", queue.len(), queue);
+                }
+                return;
+            }
 
-        self.subscribe_to_channels(vec!("book.BTC-PERPETUAL.raw".into()));
+            if queue.is_empty() {
+                self.receive_command(&mut queue, COMMAND_POLL_INTERVAL);
+                continue;
+            }
+            queue.extend(self.command_receiver.try_iter());
+
+            let authorized = self.is_authorized();
+            let next = match (0..queue.len()).filter(|i| authorized || !waits_for_session(&queue[*i])).min_by_key(|i| command_priority(&queue[*i])) {
+                Some(next) => next,
+                None => {
+                    self.receive_command(&mut queue, COMMAND_POLL_INTERVAL);
+                    continue;
+                }
+            };
+            let command = queue.remove(next);
+
+            let wait = self.rate_limiter.lock().unwrap().wait_time(command_class(&command), self.command_requests(&command), Instant::now());
+            if wait.is_zero() {
+                self.handle_command(command);
+            } else if command_priority(&command) == ORDER_PRIORITY && wait > Duration::from_millis(self.config.rate_limit.max_order_wait_ms) {
+                warn!("Not enough credits, rejecting {:?}, {:?} to wait", command, wait);
+                self.rate_limiter.lock().unwrap().reject();
+                self.reject_command(command, "not enough credits", ErrorAction::Backoff);
+            } else {
+                // the pick is made again after the wait, a cancel arriving meanwhile goes first
+                queue.insert(next, command);
+                self.receive_command(&mut queue, wait.min(COMMAND_POLL_INTERVAL));
+            }
+        }
+    }
 
-        let command_receiver_clone = crossbeam_channel::Receiver::clone(&self.command_receiver);
+    fn receive_command(&self, queue: &mut Vec<Command>, timeout: Duration) {
+        match self.command_receiver.recv_timeout(timeout) {
+            Ok(command) => queue.push(command),
+            Err(RecvTimeoutError::Timeout) => (),
+            Err(RecvTimeoutError::Disconnected) => panic!("Command channel is closed"),
+        }
+    }
 
-        thread::scope(|s| {
-            s.spawn(|| {
-                let mut command_iter = command_receiver_clone.iter();
-
-                loop {
-                    match command_iter.next().unwrap() {
-                        Command::MakeOrder { request_id, direction, instrument, price, amount, label } => {
-                            match direction {
-                                OrderSide::Ask => {
-                                    self.place_order(request_id, instrument.clone(), TradeDirection::Ask, price.clone(), amount.clone(), label.clone())
-                                }
-                                OrderSide::Bid => {
-                                    self.place_order(request_id, instrument.clone(), TradeDirection::Bid, price.clone(), amount.clone(), label.clone())
-                                }
-                            }.expect("TODO: panic message");
-                        }
+    // mass quotes need an mmp group and can't be reduce only
+    fn uses_mass_quote(&self, legs: &[QuoteLeg]) -> bool {
+        self.config.mass_quote.enabled && self.config.mmp.mmp_group.is_some() && !legs.iter().any(|leg| leg.reduce_only)
+    }
 
-                        Command::SendHeartBeat => self.heartbeat().expect("TODO: panic message"),
+    // requests a command sends, each one is paid with credits
+    fn command_requests(&self, command: &Command) -> u64 {
+        match command {
+            Command::MakeQuotes { legs, .. } if !self.uses_mass_quote(legs) => legs.len() as u64,
+            _ => 1,
+        }
+    }
 
-                        other => warn!("Unsupported command {:?}", other),
-                    };
+    // parsing and routing happens here so slow consumers don't hold up the socket
+    pub(crate) fn run_dispatch(&self, inbound_receiver: &Receiver<Inbound>) {
+        for inbound in inbound_receiver.iter() {
+            match inbound {
+                Inbound::Connected => {
+                    self.metrics.set("ct_connected", &[], 1.0);
+                    self.events.publish(Event::Connection(ConnectionEvent::Connected));
+                    if let Err(e) = self.start_session() {
+                        error!(target: "alerts", "Can't start the session: {}", e);
+                    }
                 }
-            });
+                Inbound::Disconnected => {
+                    self.metrics.set("ct_connected", &[], 0.0);
+                    self.metrics.inc("ct_reconnects_total", &[]);
+                    self.events.publish(Event::Connection(ConnectionEvent::Disconnected));
+                    self.abandon_requests();
+                    // the next connection authorizes on its own
+                    self.auth_retry.lock().unwrap().due = None;
+                }
+                Inbound::Text(text) => self.on_message(text),
+            }
+        }
+    }
+
+    // a bad frame is counted and logged with its raw text, the connection stays up
+    fn on_message(&self, s: String) {
+        if let Err(e) = self.handle_message(&s) {
+            error!("Dropping frame, {}: {}", e, s);
+            self.metrics.inc("ct_bad_frames_total", &[("kind", e.kind())]);
+        }
+    }
 
+    fn handle_message(&self, s: &str) -> Result<(), ConnectorError> {
+        let parsed_response: Response = serde_json::from_str(s)?;
 
-            loop {
-                // println!("Get socket lock on read");
-                thread::sleep(Duration::from_micros(1));
-                match self.socket.lock().unwrap().read_message() {
-                    Ok(msg) => {
-                        match msg {
-                            Message::Text(s) => {
-                                // println!("Got {}", s);
-
-                                let parsed_response: Response = serde_json::from_str(&s).unwrap();
-
-                                match parsed_response {
-                                    Response::Notification { jsonrpc, method, params } =>
-                                        {
-                                            match method.as_str() {
-                                                "subscription" => {
-                                                    let channel = params["channel"].as_str().unwrap();
-                                                    let data: serde_json::Value = params["data"].clone();
-
-                                                    match channel {
-                                                        x if x.starts_with("user.orders") => {
-                                                            let deribit_order: Order = serde_json::from_value(data).unwrap();
-                                                            let direction = match deribit_order.direction {
-                                                                Direction::Buy => order_manager::TradeDirection::Bid,
-                                                                Direction::Sell => order_manager::TradeDirection::Ask
-                                                            };
-
-                                                            let order_status = match deribit_order.order_state {
-                                                                OrderState::Open => OrderStatus::Open,
-                                                                OrderState::Filled => OrderStatus::Filled,
-                                                                OrderState::Rejected => OrderStatus::Rejected,
-                                                                OrderState::Cancelled => OrderStatus::Cancelled,
-                                                                OrderState::Untriggered => OrderStatus::Untriggered,
-                                                            };
-
-                                                            let order = order_manager::OrderEvent::OrderChanged {
-                                                                id: deribit_order.order_id,
-                                                                direction,
-                                                                price: deribit_order.price,
-                                                                amount: deribit_order.amount, // todo or filled_amount ?
-                                                                status: order_status,
-                                                                label: deribit_order.label,
-                                                            };
-
-                                                            self.order_sender.send(order).unwrap();
-                                                        }
-                                                        x if x.starts_with("book") => {
-                                                            let change = OrderbookChange::new(data);
-                                                            let update = OrderbookUpdate {
-                                                                timestamp: change.timestamp,
-                                                                instrument_name: change.instrument_name,
-                                                                change_id: change.change_id,
-                                                                bids: change.bids.iter().map(|bid| PriceLevelChange{
-                                                                    action: match bid.action {
-                                                                        Action::New    => PriceLevelAction::New,
-                                                                        Action::Change => PriceLevelAction::Change,
-                                                                        Action::Delete => PriceLevelAction::Delete
-                                                                    },
-                                                                    price: bid.price,
-                                                                    amount: bid.amount
-                                                                }).collect(),
-                                                                asks: change.asks.iter().map(|ask| PriceLevelChange{
-                                                                    action: match ask.action {
-                                                                        Action::New    => PriceLevelAction::New,
-                                                                        Action::Change => PriceLevelAction::Change,
-                                                                        Action::Delete => PriceLevelAction::Delete
-                                                                    },
-                                                                    price: ask.price,
-                                                                    amount: ask.amount
-                                                                }).collect(),
-                                                            };
-
-                                                            self.orderbook_sender.send(update).unwrap();
-                                                        }
-                                                        x if x.starts_with("user.portfolio") => {
-                                                            let portfolio_update: Portfolio = serde_json::from_value(data).unwrap();
-                                                            let balance = Balance {balance: portfolio_update.balance};
-                                                            self.portfolio_sender.send(balance).unwrap();
-                                                        }
-
-                                                        x if x.starts_with("trades.") => {
-                                                            let trade: Trade = serde_json::from_value(data).unwrap();
-                                                        }
-                                                        x => warn!("Unexpected channel {}", x)
-                                                    }
-                                                }
-                                                "heartbeat" => {
-                                                    self.command_sender.send(Command::SendHeartBeat);
-                                                    info!("Got heartbeat")
-                                                }
-                                                otherwise => warn!("Got smth else in Notification {}", otherwise)
-                                            }
-                                        }
-                                    Response::Result { jsonrpc, id, result, us_in, us_out, us_diff, testnet } =>
-                                        {
-                                            info!("Got Response::Result {}, id {}", result, id);
-
-                                            let response = order_manager::OrderEvent::OrderSuccess { uuid: id };
-                                            self.order_sender.send(response);
-
-                                            // match result.as_str() {
-                                            //     Some(x) if x.starts_with("user.orders") => {
-                                            //         let response = order_manager::OrderEvent::OrderSuccess { uuid: id };
-                                            //         self.order_sender.send(response);
-                                            //     }
-                                            //     _ => ()
-                                            // };
-                                            //
-                                            // let order:Result<Order, dyn Error> = serde_json::from_value(result);
-                                            // match order {
-                                            //     Ok(_) => {
-                                            //         let response = order_manager::OrderEvent::OrderSuccess { uuid: id };
-                                            //         self.order_sender.send(response);
-                                            //     }
-                                            //     _ => ()
-                                            // };
-                                        }
-                                    Response::Error { jsonrpc, id, error, us_in, us_out, us_diff, testnet } =>
-                                        {
-                                            error!("Got Response::Error {}, id {}", error.message, id);
-                                            // if result.starts_with("user.orders") {
-                                            let response = order_manager::OrderEvent::OrderSuccess { uuid: id };
-                                            self.order_sender.send(response);
-                                            // }
-                                        }
-                                }
-                            }
-                            Message::Close(_) => {
-                                warn!("Got Close frame. Reconnect");
-                                self.reconnect();
-                            }
-                            _ => {
-                                warn!("Got unexpected {:?}", msg);
-                            }
-                        };
+        match parsed_response {
+            Response::Notification { method, params, .. } =>
+                {
+                    match method.as_str() {
+                        "subscription" => {
+                            let channel = params["channel"].as_str()
+                                .ok_or_else(|| ConnectorError::Protocol("subscription without a channel".to_string()))?;
+                            self.metrics.inc("ct_messages_total", &[("channel", channel)]);
+
+                            self.on_subscription(channel, params["data"].clone())?;
+                        }
+                        "heartbeat" => {
+                            self.command_sender.send(Command::SendHeartBeat).unwrap();
+                            self.events.publish(Event::Connection(ConnectionEvent::Heartbeat));
+                            info!("Got heartbeat")
+                        }
+                        otherwise => return Err(ConnectorError::Protocol(format!("unexpected notification {}", otherwise))),
+                    }
+                }
+            Response::Result { id, result, us_diff, .. } =>
+                {
+                    info!("Got Response::Result {}, id {}", result, id);
+                    let method = self.record_response(&id, us_diff);
+
+                    if let Some(request) = self.take_request(&id) {
+                        return self.on_pending_result(id, request, result);
+                    }
+
+                    let response = order_manager::OrderEvent::OrderSuccess { uuid: id, method };
+                    self.events.publish(Event::Order(response));
+
+                    // match result.as_str() {
+                    //     Some(x) if x.starts_with("user.orders") => {
+                    //         let response = order_manager::OrderEvent::OrderSuccess { uuid: id };
+                    //         self.order_sender.send(response);
+                    //     }
+                    //     _ => ()
+                    // };
+                    //
+                    // let order:Result<Order, dyn Error> = serde_json::from_value(result);
+                    // match order {
+                    //     Ok(_) => {
+                    //         let response = order_manager::OrderEvent::OrderSuccess { uuid: id };
+                    //         self.order_sender.send(response);
+                    //     }
+                    //     _ => ()
+                    // };
+                }
+            Response::Error { id, error, us_diff, .. } =>
+                {
+                    let spec = error_codes::lookup(error.code, &error.message);
+                    error!("Got Response::Error {} {}, {:?}, id {}", error.code, error.message, spec.action, id);
+
+                    let method = self.record_response(&id, us_diff);
+                    self.on_exchange_error(&spec, method.as_deref());
+
+                    let exchange_error = ConnectorError::Exchange { code: error.code, message: error.message };
+                    if let Some(request) = self.take_request(&id) {
+                        self.fail_request(id, request, exchange_error);
+                        return Ok(());
+                    }
+                    if spec.action == ErrorAction::Alert {
+                        error!(target: "alerts", "Request {} failed: {}", id, exchange_error);
                     }
-                    Err(e) => {
-                        error!("Got error on reading from socket{:?}", e);
-                        self.reconnect();
+                    // if result.starts_with("user.orders") {
+                    let response = order_manager::OrderEvent::OrderSuccess { uuid: id, method };
+                    self.events.publish(Event::Order(response));
+                    // }
+                }
+        }
+
+        Ok(())
+    }
+
+    fn on_subscription(&self, channel: &str, data: Value) -> Result<(), ConnectorError> {
+        match channel {
+            x if x.starts_with("user.orders") => {
+                let mut deribit_order: Order = serde_json::from_value(data)?;
+                self.restore_quote_label(&mut deribit_order);
+
+                self.events.publish(Event::Order(order_changed(deribit_order)));
+            }
+            x if x.starts_with("book") => {
+                let update: OrderbookUpdate = OrderbookChange::new(data)?.into();
+
+                self.events.publish(Event::Book(update));
+            }
+            x if x.starts_with("user.portfolio") => {
+                let portfolio_update: Portfolio = serde_json::from_value(data)?;
+                self.events.publish(Event::Portfolio(portfolio_update.into()));
+            }
+
+            x if x.starts_with("user.mmp_trigger") => {
+                warn!("Got MMP trigger {}", data);
+                self.events.publish(Event::Order(order_manager::OrderEvent::MmpTriggered { index_name: self.config.mmp.index_name.clone() }));
+            }
+
+            x if x.starts_with("user.trades") => {
+                let trades: Vec<UserTrade> = serde_json::from_value(data)?;
+                for trade in trades {
+                    self.metrics.inc("ct_fills_total", &[("instrument", &trade.instrument_name)]);
+                    self.events.publish(Event::Fill(trade.into()));
+                }
+            }
+            x if x.starts_with("trades.") => {
+                let trades: Vec<Trade> = serde_json::from_value(data)?;
+                for trade in trades {
+                    let trade: domain::Trade = trade.into();
+
+                    let missed = self.trade_seqs.lock().unwrap().check(&trade);
+                    if missed > 0 {
+                        warn!("Missed {} trades on {} before trade_seq {}", missed, trade.instrument_name, trade.trade_seq);
                     }
+
+                    self.events.publish(Event::Trade(trade));
+                }
+            }
+            x if x.starts_with("ticker.") => {
+                let ticker: Ticker = serde_json::from_value(data)?;
+                self.events.publish(Event::Ticker(ticker.into()));
+            }
+            x if x.starts_with("deribit_price_index.") => {
+                let index: PriceIndex = serde_json::from_value(data)?;
+                self.events.publish(Event::IndexPrice(index.into()));
+            }
+            x if x.starts_with("markprice.options.") => {
+                let mark_prices: Vec<OptionMarkPrice> = serde_json::from_value(data)?;
+                for mark_price in mark_prices {
+                    self.events.publish(Event::MarkPrice(mark_price.into()));
+                }
+            }
+            x if x.starts_with("perpetual.") => {
+                let update: PerpetualUpdate = serde_json::from_value(data)?;
+                let instrument_name = x.split('.').nth(1)
+                    .ok_or_else(|| ConnectorError::Protocol(format!("no instrument in channel {}", x)))?;
+                let funding = Funding {
+                    timestamp: update.timestamp,
+                    instrument_name: instrument_name.to_string(),
+                    interest: update.interest,
+                    index_price: update.index_price,
                 };
-                // println!("Release socket lock on read");
+                self.events.publish(Event::Funding(funding));
             }
-        });
+            x => return Err(ConnectorError::Protocol(format!("unexpected channel {}", x))),
+        }
+
+        Ok(())
+    }
+
+    // returns the method of the request, None if it is older than REQUEST_TIMEOUT
+    fn record_response(&self, request_id: &Uuid, us_diff: u32) -> Option<String> {
+        let (method, sent_at) = self.sent_requests.lock().unwrap().remove(request_id)?;
+        self.latency.record(LatencyKind::SendToAck, &method, sent_at.elapsed());
+        self.latency.record(LatencyKind::Exchange, &method, Duration::from_micros(us_diff as u64));
+
+        Some(method)
+    }
+
+    // what the connector itself does about an error, order failures are up to the manager
+    fn on_exchange_error(&self, spec: &ErrorSpec, method: Option<&str>) {
+        self.metrics.inc("ct_exchange_errors_total", &[("error", spec.name), ("category", spec.category.name())]);
+
+        match (spec.action, method) {
+            (ErrorAction::Backoff, Some(method)) => {
+                warn!("Backing off {:?} requests after {}", endpoint_class(method), spec.name);
+                self.rate_limiter.lock().unwrap().drain(endpoint_class(method), Instant::now());
+            }
+            (ErrorAction::Halt, _) => self.kill_switch.engage(&format!("exchange error {} {}", spec.code, spec.name)),
+            // a failed auth request is handled with the request
+            (ErrorAction::Reauthenticate, Some("public/auth")) => (),
+            (ErrorAction::Reauthenticate, _) => self.reauthenticate(),
+            _ => (),
+        }
     }
 
-    pub fn new(orderbook_sender: Sender<OrderbookUpdate>,
-               order_sender: Sender<order_manager::OrderEvent>,
-               raw_data_sender: Sender<String>,
+    // orders failing with Backoff pause quoting until the drained bucket has credits again
+    fn retry_after(&self, action: ErrorAction, requests: u64) -> Duration {
+        match action {
+            ErrorAction::Backoff => self.rate_limiter.lock().unwrap().wait_time(EndpointClass::MatchingEngine, requests, Instant::now()),
+            _ => Duration::ZERO,
+        }
+    }
+
+    fn track_request(&self, request_id: Uuid, request: PendingRequest) {
+        self.pending_requests.lock().unwrap().insert(request_id, (request, Instant::now()));
+    }
+
+    // a request which can't be queued fails right away instead of waiting for REQUEST_TIMEOUT
+    fn send_tracked(&self, request: JsonRpcRequest, pending_request: PendingRequest) -> Result<(), ConnectorError> {
+        let request_id = request.id;
+        self.track_request(request_id, pending_request);
+
+        self.send_request(request).inspect_err(|_| {
+            if let Some(pending_request) = self.take_request(&request_id) {
+                self.abandon_request(request_id, pending_request, "not connected");
+            }
+        })
+    }
+
+    fn take_request(&self, request_id: &Uuid) -> Option<PendingRequest> {
+        self.pending_requests.lock().unwrap().remove(request_id).map(|(request, _)| request)
+    }
+
+    fn fail_request(&self, request_id: Uuid, request: PendingRequest, error: ConnectorError) {
+        let action = match &error {
+            ConnectorError::Exchange { code, message } => error_codes::lookup(*code, message).action,
+            // a lost response says nothing about the request itself
+            ConnectorError::Transport(_) => ErrorAction::Ignore,
+            _ => ErrorAction::Alert,
+        };
+
+        match request {
+            // the manager finds out about an order without an answer through its ack recovery
+            PendingRequest::Order { label, .. } if matches!(error, ConnectorError::Transport(_)) => {
+                warn!("Order request {} ({}) failed: {}", request_id, label, error);
+            }
+            PendingRequest::Order { label, .. } => {
+                let failed = order_manager::OrderEvent::OrderFailed { request_id, label, reason: error.to_string(), action, retry_after: self.retry_after(action, 1) };
+                self.events.publish(Event::Order(failed));
+            }
+            PendingRequest::MassQuote { legs } => {
+                for leg in legs {
+                    let failed = order_manager::OrderEvent::OrderFailed { request_id: leg.request_id, label: leg.client_order_id, reason: error.to_string(), action, retry_after: self.retry_after(action, 1) };
+                    self.events.publish(Event::Order(failed));
+                }
+            }
+            PendingRequest::Auth { reason: AuthReason::Refresh } => {
+                warn!("Can't refresh the access token, {}, authorizing with the client credentials", error);
+                self.token.lock().unwrap().take();
+
+                if let Err(e) = self.authorize(AuthReason::Reauthenticate) {
+                    error!(target: "alerts", "Can't authorize: {}", e);
+                }
+            }
+            PendingRequest::Auth { reason } => {
+                let error = match error {
+                    ConnectorError::Exchange { code, message } => ConnectorError::Auth(format!("{} {}", code, message)),
+                    other => other,
+                };
+                error!(target: "alerts", "Authorization failed: {}", error);
+                self.metrics.inc("ct_connector_errors_total", &[("kind", error.kind())]);
+                self.schedule_auth_retry(reason);
+            }
+            request if action == ErrorAction::Alert => error!(target: "alerts", "{:?} request {} failed: {}", request, request_id, error),
+            // the manager raises an alert when an order state query deadline passes
+            request => error!("{:?} request {} failed: {}", request, request_id, error),
+        }
+    }
+
+    // fails tracked requests which got no response in REQUEST_TIMEOUT
+    pub(crate) fn expire_requests(&self) {
+        let now = Instant::now();
+
+        let expired: Vec<(Uuid, PendingRequest)> = {
+            let mut pending_requests = self.pending_requests.lock().unwrap();
+
+            let ids: Vec<Uuid> = pending_requests.iter()
+                .filter(|(_, (_, sent_at))| now.duration_since(*sent_at) >= REQUEST_TIMEOUT)
+                .map(|(id, _)| *id)
+                .collect();
+
+            ids.into_iter().filter_map(|id| pending_requests.remove(&id).map(|(request, _)| (id, request))).collect()
+        };
+
+        for (request_id, request) in expired {
+            self.fail_request(request_id, request, ConnectorError::Transport(format!("no response in {:?}", REQUEST_TIMEOUT)));
+        }
+
+        self.sent_requests.lock().unwrap().retain(|_, (_, sent_at)| now.duration_since(*sent_at) < REQUEST_TIMEOUT);
+    }
+
+    // cancel on disconnect takes the orders of a lost connection down with it,
+    // so its unanswered order requests have failed
+    fn abandon_requests(&self) {
+        let requests: Vec<(Uuid, PendingRequest)> = self.pending_requests.lock().unwrap().drain()
+            .map(|(id, (request, _))| (id, request))
+            .collect();
+        self.sent_requests.lock().unwrap().clear();
+
+        for (request_id, request) in requests {
+            self.abandon_request(request_id, request, "connection lost");
+        }
+    }
+
+    fn abandon_request(&self, request_id: Uuid, request: PendingRequest, reason: &str) {
+        match request {
+            PendingRequest::Order { label, .. } => {
+                let failed = order_manager::OrderEvent::OrderFailed { request_id, label, reason: reason.to_string(), action: ErrorAction::Ignore, retry_after: Duration::ZERO };
+                self.events.publish(Event::Order(failed));
+            }
+            PendingRequest::MassQuote { legs } => {
+                for leg in legs {
+                    let failed = order_manager::OrderEvent::OrderFailed { request_id: leg.request_id, label: leg.client_order_id, reason: reason.to_string(), action: ErrorAction::Ignore, retry_after: Duration::ZERO };
+                    self.events.publish(Event::Order(failed));
+                }
+            }
+            request => warn!("Dropping {:?} request {}, {}", request, request_id, reason),
+        }
+    }
+
+    fn is_authorized(&self) -> bool {
+        self.token.lock().unwrap().is_some()
+    }
+
+    fn handle_command(&self, command: Command) {
+        let result = match command {
+            Command::MakeOrder { request_id, .. } | Command::MakeQuotes { request_id, .. } if self.kill_switch.is_engaged() => {
+                warn!("Kill switch is engaged, refusing order request {}", request_id);
+                self.reject_command(command, "kill switch is engaged", ErrorAction::Ignore);
+                Ok(())
+            }
+
+            Command::MakeOrder { request_id, .. } | Command::MakeQuotes { request_id, .. } if self.supervisor.shutdown().is_requested() => {
+                warn!("Shutting down, refusing order request {}", request_id);
+                self.reject_command(command, "shutting down", ErrorAction::Ignore);
+                Ok(())
+            }
+
+            Command::MakeOrder { request_id, .. } | Command::MakeQuotes { request_id, .. } if !self.is_authorized() => {
+                warn!("Session isn't authorized, refusing order request {}", request_id);
+                self.reject_command(command, "not authorized", ErrorAction::Ignore);
+                Ok(())
+            }
+
+            Command::MakeQuotes { request_id, instrument, legs } => {
+                match &self.config.mmp.mmp_group {
+                    Some(mmp_group) if self.uses_mass_quote(&legs) => self.mass_quote(request_id, instrument, legs, mmp_group.clone()),
+                    _ => self.place_legs(instrument, legs),
+                }
+            }
+
+            Command::MakeOrder { request_id, client_order_id, direction, instrument, price, amount } => {
+                self.place_order(instrument, QuoteLeg { request_id, client_order_id, direction, price, amount, reduce_only: false })
+            }
+
+            Command::GetOrderState { request_id, instrument, label, order_id } => {
+                self.get_order_state(request_id, instrument, order_id, label)
+            }
+
+            Command::CancelOrder { id } => self.cancel_order(id),
+
+            Command::CancelByLabel { currency, label } => self.cancel_by_label(currency, label),
+
+            Command::CancelAll => self.cancel_all(),
+
+            Command::ClosePositions { currency } => self.close_positions(currency),
+
+            Command::GetPositions { currency } => self.get_positions(currency),
+
+            Command::GetSnapshot { currency } => self.request_snapshot(currency),
+
+            Command::ResetMmp { index_name } => self.reset_mmp(index_name),
+
+            Command::SendHeartBeat => self.heartbeat(),
+
+            Command::SubscribeData { channel } => self.subscribe_to_channels(vec!(channel)),
+
+            Command::UnsubscribeData { channel } => self.unsubscribe_from_channels(vec!(channel)),
+        };
+
+        if let Err(e) = result {
+            error!("Can't send command: {}", e);
+            self.metrics.inc("ct_connector_errors_total", &[("kind", e.kind())]);
+        }
+    }
+
+    fn reject_command(&self, command: Command, reason: &str, action: ErrorAction) {
+        let retry_after = self.retry_after(action, self.command_requests(&command));
+        let failed: Vec<(Uuid, String)> = match command {
+            Command::MakeOrder { request_id, client_order_id, .. } => vec!((request_id, client_order_id)),
+            Command::MakeQuotes { legs, .. } => legs.into_iter().map(|leg| (leg.request_id, leg.client_order_id)).collect(),
+            _ => vec!(),
+        };
+
+        for (request_id, label) in failed {
+            self.events.publish(Event::Order(order_manager::OrderEvent::OrderFailed { request_id, label, reason: reason.to_string(), action, retry_after }));
+        }
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    pub fn new(events: EventBus,
                command_receiver: Receiver<Command>,
-               portfolio_sender: Sender<Balance>,
                command_sender: Sender<Command>,
+               kill_switch: Arc<KillSwitch>,
+               latency: Arc<Latency>,
+               metrics: Arc<Metrics>,
+               supervisor: Arc<Supervisor>,
+               config: Config,
     ) -> DeribitConnector {
-        let (socket1, response) = connect(
-            Url::parse("wss://test.deribit.com/ws/api/v2").unwrap()
-        ).expect("Can't connect");
+        DeribitConnector {
+            events,
+            command_receiver,
+            outbound: Mutex::new(None),
+            command_sender,
+            pending_requests: Mutex::new(HashMap::new()),
+            kill_switch,
+            quote_labels: Mutex::new(HashMap::new()),
+            rate_limiter: Mutex::new(RateLimiter::new(config.rate_limit.matching_engine.clone(), config.rate_limit.non_matching_engine.clone())),
+            trade_seqs: Mutex::new(TradeSeqTracker::new()),
+            latency,
+            metrics,
+            sent_requests: Mutex::new(HashMap::new()),
+            supervisor,
+            token: Mutex::new(None),
+            auth_retry: Mutex::new(AuthRetry::default()),
+            config,
+        }
+    }
 
-        let socket = Arc::new(Mutex::new(socket1));
+    // None once the shutdown closes the connections. The raw stream is a clone of the
+    // socket to set its timeouts
+    fn connect(&self) -> Option<(Socket, TcpStream)> {
+        while !self.supervisor.shutdown().is_closing() {
+            match connect(Url::parse("wss://test.deribit.com/ws/api/v2").unwrap()) {
+                Ok((socket, _)) => {
+                    let raw = match socket.get_ref() {
+                        MaybeTlsStream::Plain(stream) => stream.try_clone(),
+                        MaybeTlsStream::NativeTls(stream) => stream.get_ref().try_clone(),
+                        _ => Err(std::io::Error::new(ErrorKind::Unsupported, "unknown stream")),
+                    }.expect("Can't clone the socket");
+
+                    return Some((socket, raw));
+                }
+                Err(e) => {
+                    self.transport_error("Can't connect", e);
+                    thread::sleep(RECONNECT_INTERVAL);
+                }
+            }
+        }
 
-        DeribitConnector { orderbook_sender, order_sender, raw_data_sender, command_receiver, socket, portfolio_sender, command_sender }
+        None
     }
 
-    fn reconnect(&self) {
-        warn!("Trying to reconnect....");
+    // one connection at a time, a single loop owns the socket
+    fn run_io(&self, inbound_sender: Sender<Inbound>) {
+        while let Some((socket, raw)) = self.connect() {
+            let outbound_receiver = self.open_outbound();
+            inbound_sender.send(Inbound::Connected).unwrap();
+
+            self.exchange_frames(socket, &raw, &outbound_receiver, &inbound_sender);
+            self.close_outbound();
+            let _ = raw.shutdown(std::net::Shutdown::Both);
 
-        let (socket, response) = connect(
-            Url::parse("wss://test.deribit.com/ws/api/v2").unwrap()
-        ).expect("Can't connect");
+            inbound_sender.send(Inbound::Disconnected).unwrap();
+            if !self.supervisor.shutdown().is_closing() {
+                warn!("Trying to reconnect....");
+            }
+        }
+    }
 
-        let mut mutex = self.socket.lock().unwrap();
-        *mutex = socket;
+    // reads with a short timeout and writes the queued frames between reads, so neither
+    // direction waits for the other to go quiet
+    fn exchange_frames(&self, mut socket: Socket, raw: &TcpStream, outbound_receiver: &Receiver<Outbound>, inbound_sender: &Sender<Inbound>) {
+        raw.set_read_timeout(Some(IO_POLL_INTERVAL)).expect("Can't set read timeout");
+        let mut received_at = Instant::now();
+        let mut checked_at = Instant::now();
+        // set once the close frame is sent
+        let mut closing_at: Option<Instant> = None;
+
+        loop {
+            match closing_at {
+                None => {
+                    // ending the queue makes the loop close the socket after what is queued
+                    if checked_at.elapsed() >= COMMAND_POLL_INTERVAL {
+                        checked_at = Instant::now();
+                        if self.supervisor.shutdown().is_closing() {
+                            self.close_outbound();
+                        }
+                    }
+
+                    match self.write_queued(&mut socket, outbound_receiver) {
+                        Ok(true) => (),
+                        Ok(false) => {
+                            info!("Closing the socket");
+                            if let Err(e) = socket.close(None) {
+                                warn!("Can't close the socket: {:?}", e);
+                                return;
+                            }
+                            closing_at = Some(Instant::now());
+                        }
+                        Err(e) => {
+                            self.transport_error("Got error on writing to socket", e);
+                            return;
+                        }
+                    }
+                }
+                Some(closing_at) if closing_at.elapsed() >= CLOSE_TIMEOUT => {
+                    warn!("No close frame from the exchange in {:?}", CLOSE_TIMEOUT);
+                    return;
+                }
+                Some(_) => (),
+            }
 
-        self.set_heartbeat_interval(60);
+            match socket.read_message() {
+                Ok(Message::Text(s)) => {
+                    received_at = Instant::now();
+                    inbound_sender.send(Inbound::Text(s)).unwrap();
+                }
+                // tungstenite answers it, the next read ends the connection
+                Ok(Message::Close(_)) => info!("Got Close frame"),
+                Ok(msg) => {
+                    received_at = Instant::now();
+                    warn!("Got unexpected {:?}", msg);
+                }
+                Err(tungstenite::Error::Io(e)) if e.kind() == ErrorKind::WouldBlock || e.kind() == ErrorKind::TimedOut => {
+                    if received_at.elapsed() >= HEARTBEAT_TIMEOUT {
+                        error!("Nothing received for {:?}, heartbeats are missing", HEARTBEAT_TIMEOUT);
+                        return;
+                    }
+                }
+                Err(tungstenite::Error::ConnectionClosed) | Err(tungstenite::Error::AlreadyClosed) => return,
+                Err(e) => {
+                    self.transport_error("Got error on reading from socket", e);
+                    return;
+                }
+            }
+        }
+    }
+
+    // writes everything queued, false once the queue has ended
+    fn write_queued(&self, socket: &mut Socket, outbound_receiver: &Receiver<Outbound>) -> Result<bool, ConnectorError> {
+        loop {
+            let outbound = match outbound_receiver.try_recv() {
+                Ok(outbound) => outbound,
+                Err(TryRecvError::Empty) => return Ok(true),
+                Err(TryRecvError::Disconnected) => return Ok(false),
+            };
+
+            self.mark_written(&outbound);
+            match socket.write_message(Message::Text(outbound.text)) {
+                Ok(()) => (),
+                // the frame stays queued in the socket, the next read flushes it
+                Err(tungstenite::Error::Io(e)) if e.kind() == ErrorKind::WouldBlock || e.kind() == ErrorKind::TimedOut => (),
+                Err(e) => return Err(e.into()),
+            }
+            self.latency.record(LatencyKind::Queue, &outbound.method, outbound.queued_at.elapsed());
+        }
+    }
+
+    // send -> ack starts here, taken before the write so the response can't come first
+    pub(crate) fn mark_written(&self, outbound: &Outbound) {
+        self.sent_requests.lock().unwrap().insert(outbound.request_id, (outbound.method.clone(), Instant::now()));
+    }
+
+    pub(crate) fn transport_error(&self, context: &str, e: impl Into<ConnectorError>) {
+        let e = e.into();
+        error!("{}: {}", context, e);
+        self.metrics.inc("ct_connector_errors_total", &[("kind", e.kind())]);
+    }
+
+    // the public part, the private one starts with the auth response
+    fn start_session(&self) -> Result<(), ConnectorError> {
+        self.set_heartbeat_interval(60)?;
+        self.sync_clock()?;
+        self.get_instruments("BTC".to_string())?;
+
+        self.authorize(AuthReason::Connect)?;
+
+        let market_data = &self.config.market_data;
+        let mut channels: Vec<String> = vec!(
+            "book.BTC-PERPETUAL.raw".into(),
+            "trades.BTC-PERPETUAL.raw".into(),
+            format!("ticker.BTC-PERPETUAL.{}", market_data.interval),
+            format!("perpetual.BTC-PERPETUAL.{}", market_data.interval),
+        );
+        channels.extend(market_data.price_indexes.iter().map(|index| format!("deribit_price_index.{}", index)));
+        channels.extend(market_data.option_mark_prices.iter().map(|index| format!("markprice.options.{}", index)));
+
+        self.subscribe_to_channels(channels)
+    }
+
+    fn start_private_session(&self) -> Result<(), ConnectorError> {
+        self.enable_cancel_on_disconnect()?;
+
+        self.subscribe_to_orders(vec!("user.orders.BTC-PERPETUAL.raw".into()))?;
+        self.subscribe_to_orders(vec!("user.trades.BTC-PERPETUAL.raw".into()))?;
+        self.subscribe_to_portfolio_channel(vec!("user.portfolio.btc".into()))?;
+
+        if self.config.mmp.enabled {
+            self.set_mmp_config()?;
+            self.subscribe_to_orders(vec!(format!("user.mmp_trigger.{}", self.config.mmp.index_name)))?;
+        }
+
+        self.request_snapshot("BTC".to_string())
+    }
+
+    // open orders, positions and balance to reconcile the manager state with the exchange
+    fn request_snapshot(&self, currency: String) -> Result<(), ConnectorError> {
+        let requests = vec!(
+            ("private/get_open_orders_by_currency", PendingRequest::OpenOrders),
+            ("private/get_positions", PendingRequest::Positions),
+            ("private/get_account_summary", PendingRequest::AccountSummary),
+        );
+
+        for (method, pending_request) in requests {
+            let request_id = Uuid::new_v4();
+            let request = JsonRpcRequest::new(method.to_string(), request_id, Some(Params::Currency { currency: currency.clone() }));
+
+            info!("Sending snapshot request {:?}", request);
+
+            self.send_tracked(request, pending_request)?;
+        }
+
+        Ok(())
     }
 
     // fn create_socket(&self) -> WebSocket<MaybeTlsStream<TcpStream>> {
@@ -253,7 +878,7 @@ impl DeribitConnector {
     //     }
     // }
 
-    fn subscribe_to_channels(&self, channels: Vec<String>) -> Result<(), Box<dyn Error>> {
+    fn subscribe_to_channels(&self, channels: Vec<String>) -> Result<(), ConnectorError> {
         let to_subscribe = Params::Channels { channels };
 
         let subscribe_request = JsonRpcRequest::new("public/subscribe".to_string(), Uuid::new_v4(), Some(to_subscribe));
@@ -263,7 +888,17 @@ impl DeribitConnector {
         self.send_request(subscribe_request)
     }
 
-    fn subscribe_to_orders(&self, channels: Vec<String>) -> Result<(), Box<dyn Error>> {
+    fn unsubscribe_from_channels(&self, channels: Vec<String>) -> Result<(), ConnectorError> {
+        let to_unsubscribe = Params::Channels { channels };
+
+        let unsubscribe_request = JsonRpcRequest::new("public/unsubscribe".to_string(), Uuid::new_v4(), Some(to_unsubscribe));
+
+        info!("Sending channel unsubscribing request {:?}", unsubscribe_request);
+
+        self.send_request(unsubscribe_request)
+    }
+
+    fn subscribe_to_orders(&self, channels: Vec<String>) -> Result<(), ConnectorError> {
         let to_subscribe = Params::Channels { channels };
 
         let subscribe_request = JsonRpcRequest::new("private/subscribe".to_string(), Uuid::new_v4(), Some(to_subscribe));
@@ -273,7 +908,7 @@ impl DeribitConnector {
         self.send_request(subscribe_request)
     }
 
-    fn subscribe_to_portfolio_channel(&self, channels: Vec<String>) -> Result<(), Box<dyn Error>> {
+    fn subscribe_to_portfolio_channel(&self, channels: Vec<String>) -> Result<(), ConnectorError> {
         let to_subscribe = Params::Channels { channels };
 
         let subscribe_request = JsonRpcRequest::new("private/subscribe".to_string(), Uuid::new_v4(), Some(to_subscribe));
@@ -283,7 +918,7 @@ impl DeribitConnector {
         self.send_request(subscribe_request)
     }
 
-    fn set_heartbeat_interval(&self, interval: u32) -> Result<(), Box<dyn Error>> {
+    fn set_heartbeat_interval(&self, interval: u32) -> Result<(), ConnectorError> {
         let heartbeat_interval = Params::Interval { interval };
 
         let set_heartbeat_request = JsonRpcRequest::new("public/set_heartbeat".to_string(), Uuid::new_v4(), Some(heartbeat_interval));
@@ -293,7 +928,7 @@ impl DeribitConnector {
         self.send_request(set_heartbeat_request)
     }
 
-    fn heartbeat(&self) -> Result<(), Box<dyn Error>> {
+    fn heartbeat(&self) -> Result<(), ConnectorError> {
         let heartbeat = JsonRpcRequest::new("public/test".to_string(), Uuid::new_v4(), None);
 
         info!("Sending heartbeat {:?}", heartbeat);
@@ -301,86 +936,464 @@ impl DeribitConnector {
         self.send_request(heartbeat)
     }
 
-    fn authorize(&self) -> Result<(), Box<dyn Error>> {
+    // the futures only, the options aren't quoted
+    fn get_instruments(&self, currency: String) -> Result<(), ConnectorError> {
+        let request = JsonRpcRequest::new("public/get_instruments".to_string(), Uuid::new_v4(), Some(Params::Instruments { currency, kind: "future".to_string() }));
+
+        info!("Sending instruments request {:?}", request);
+
+        self.send_tracked(request, PendingRequest::Instruments)
+    }
+
+    // the offset is estimated from the exchange time and the local times around the request
+    pub(crate) fn sync_clock(&self) -> Result<(), ConnectorError> {
+        let request_id = Uuid::new_v4();
+        let request = JsonRpcRequest::new("public/get_time".to_string(), request_id, None);
+
+        self.send_tracked(request, PendingRequest::Time { sent_at: now_millis() })
+    }
+
+    fn authorize(&self, reason: AuthReason) -> Result<(), ConnectorError> {
+        let auth_config = &self.config.auth;
+        let mut scopes = auth_config.scopes.clone();
+        if let Some(session) = &auth_config.session {
+            scopes.push(format!("session:{}", session));
+        }
+
         let auth = Params::Auth {
             grant_type: "client_credentials".to_string(),
             client_id: env!("client_id").to_string(),
             client_secret: env!("client_secret").to_string(),
+            scope: if scopes.is_empty() { None } else { Some(scopes.join(" ")) },
         };
 
+        let request_id = Uuid::new_v4();
+        let auth_request = JsonRpcRequest::new("public/auth".to_string(), request_id, Some(auth));
 
-        let auth_request = JsonRpcRequest::new("public/auth".to_string(), Uuid::new_v4(), Some(auth));
+        // the request carries the secret
+        info!("Sending auth request {}, scopes {:?}", request_id, scopes);
 
-        info!("Sending auth request {:?}", auth_request);
+        self.send_tracked(auth_request, PendingRequest::Auth { reason })
+    }
+
+    // called every second, refreshes once the token is about to lapse
+    pub(crate) fn refresh_token(&self) {
+        let refresh_before = Duration::from_secs(self.config.auth.refresh_before_secs);
+
+        let refresh_token = match self.token.lock().unwrap().as_mut() {
+            Some(token) if !token.refreshing && token.expires_at.saturating_duration_since(Instant::now()) <= refresh_before => {
+                token.refreshing = true;
+                token.refresh_token.clone()
+            }
+            _ => return,
+        };
 
-        self.send_request(auth_request)
+        let request_id = Uuid::new_v4();
+        let refresh = Params::RefreshToken { grant_type: "refresh_token".to_string(), refresh_token };
+        let request = JsonRpcRequest::new("public/auth".to_string(), request_id, Some(refresh));
+
+        info!("Refreshing the access token, request {}", request_id);
+
+        if let Err(e) = self.send_tracked(request, PendingRequest::Auth { reason: AuthReason::Refresh }) {
+            error!("Can't refresh the access token: {}", e);
+        }
+    }
+
+    fn schedule_auth_retry(&self, reason: AuthReason) {
+        let mut retry = self.auth_retry.lock().unwrap();
+        retry.delay = (retry.delay * 2).clamp(AUTH_RETRY_MIN, AUTH_RETRY_MAX);
+        retry.due = Some((reason, Instant::now() + retry.delay));
+
+        warn!("Authorizing again in {:?}", retry.delay);
     }
 
-    fn place_order(&self, request_id: Uuid, instrument: String, direction: TradeDirection, price: Decimal, amount: Decimal, label: String) -> Result<(), Box<dyn Error>> {
-        let method = match direction {
-            TradeDirection::Ask => "private/sell",
-            TradeDirection::Bid => "private/buy"
+    // called every second, sends the authorization which failed last once its delay passed
+    pub(crate) fn retry_auth(&self) {
+        let reason = {
+            let mut retry = self.auth_retry.lock().unwrap();
+            match retry.due {
+                Some((reason, due)) if Instant::now() >= due => {
+                    retry.due = None;
+                    reason
+                }
+                _ => return,
+            }
+        };
+
+        if let Err(e) = self.authorize(reason) {
+            error!("Can't authorize: {}", e);
+        }
+    }
+
+    // requests failing while the new auth request is out don't send more of them
+    fn reauthenticate(&self) {
+        let authorizing = self.pending_requests.lock().unwrap().values()
+            .any(|(request, _)| matches!(request, PendingRequest::Auth { .. }));
+        if authorizing {
+            return;
+        }
+
+        warn!("The session is unauthorized, authorizing again");
+        self.token.lock().unwrap().take();
+
+        if let Err(e) = self.authorize(AuthReason::Reauthenticate) {
+            error!(target: "alerts", "Can't authorize: {}", e);
+        }
+    }
+
+    fn on_auth(&self, reason: AuthReason, result: Value) -> Result<(), ConnectorError> {
+        let auth: AuthResult = serde_json::from_value(result)?;
+        info!("Authorized ({:?}), scope {}, expires in {}s", reason, auth.scope, auth.expires_in);
+
+        if reason != AuthReason::Refresh && !auth.scope.split(' ').any(|scope| scope == "trade:read_write") {
+            warn!("The session can't trade with scope {}", auth.scope);
+        }
+
+        // queued before the token unblocks the private commands, so the orders go after cancel on disconnect
+        if reason == AuthReason::Connect {
+            if let Err(e) = self.start_private_session() {
+                error!(target: "alerts", "Can't start the session: {}", e);
+            }
+        }
+
+        *self.auth_retry.lock().unwrap() = AuthRetry::default();
+        *self.token.lock().unwrap() = Some(Token {
+            refresh_token: auth.refresh_token,
+            expires_at: Instant::now() + Duration::from_secs(auth.expires_in),
+            refreshing: false,
+        });
+
+        Ok(())
+    }
+
+    // the client order id travels as the deribit label
+    fn place_order(&self, instrument: String, leg: QuoteLeg) -> Result<(), ConnectorError> {
+        let method = match leg.direction {
+            Side::Ask => "private/sell",
+            Side::Bid => "private/buy"
         };
 
         let order = Params::Order {
             instrument_name: instrument,
-            price,
-            amount,
+            price: leg.price,
+            amount: leg.amount,
             post_only: true,
-            label,
+            reduce_only: leg.reduce_only,
+            label: leg.client_order_id.clone(),
+            mmp: self.config.mmp.enabled,
         };
 
-        let request = JsonRpcRequest::new(method.to_string(), request_id, Some(order));
+        let request = JsonRpcRequest::new(method.to_string(), leg.request_id, Some(order));
 
         // info!("Sending order making request {:?}", request);
+        self.metrics.inc("ct_orders_sent_total", &[("method", method)]);
+
+        self.send_tracked(request, PendingRequest::Order { label: leg.client_order_id, method })
+    }
+
+    // fallback for MakeQuotes without mass quoting: one buy/sell per leg
+    fn place_legs(&self, instrument: String, legs: Vec<QuoteLeg>) -> Result<(), ConnectorError> {
+        for leg in legs {
+            self.place_order(instrument.clone(), leg)?;
+        }
+
+        Ok(())
+    }
+
+    fn mass_quote(&self, request_id: Uuid, instrument: String, legs: Vec<QuoteLeg>, mmp_group: String) -> Result<(), ConnectorError> {
+        let side = |direction: Side| legs.iter()
+            .find(|leg| leg.direction == direction)
+            .map(|leg| QuoteSide { price: leg.price, amount: leg.amount, post_only: true });
+
+        let quotes = Params::MassQuote {
+            quote_id: request_id.to_string(),
+            mmp_group,
+            detailed: true,
+            quotes: vec!(MassQuote { instrument_name: instrument, bid: side(Side::Bid), ask: side(Side::Ask) }),
+        };
+
+        let request = JsonRpcRequest::new("private/mass_quote".to_string(), request_id, Some(quotes));
+        self.metrics.add("ct_orders_sent_total", &[("method", "private/mass_quote")], legs.len() as u64);
+
+        self.send_tracked(request, PendingRequest::MassQuote { legs })
+    }
+
+    // maps the mass quote result back to the legs: orders and errors are matched by side
+    fn on_mass_quote(&self, legs: Vec<QuoteLeg>, result: Value) -> Result<(), ConnectorError> {
+        let result: MassQuoteResult = match serde_json::from_value(result) {
+            Ok(result) => result,
+            Err(e) => {
+                let e = ConnectorError::from(e);
+                for leg in legs {
+                    let failed = order_manager::OrderEvent::OrderFailed { request_id: leg.request_id, label: leg.client_order_id, reason: e.to_string(), action: ErrorAction::Alert, retry_after: Duration::ZERO };
+                    self.events.publish(Event::Order(failed));
+                }
+                return Err(e);
+            }
+        };
+
+        for leg in legs.iter() {
+            let success = order_manager::OrderEvent::OrderSuccess { uuid: leg.request_id, method: Some("private/mass_quote".to_string()) };
+            self.events.publish(Event::Order(success));
+        }
+
+        for mut order in result.orders {
+            if let Some(leg) = legs.iter().find(|leg| Direction::from(leg.direction) == order.direction) {
+                self.quote_labels.lock().unwrap().insert(order.order_id.clone(), leg.client_order_id.clone());
+                order.label = leg.client_order_id.clone();
+            }
+
+            self.events.publish(Event::Order(order_changed(order)));
+        }
+
+        for quote_error in result.errors {
+            warn!("Mass quote leg failed: {:?}", quote_error);
+
+            if let Some(leg) = legs.iter().find(|leg| Direction::from(leg.direction) == quote_error.side) {
+                let reason = quote_error.message.unwrap_or_default();
+                let spec = error_codes::lookup(quote_error.code.unwrap_or_default(), &reason);
+                self.on_exchange_error(&spec, Some("private/mass_quote"));
+
+                let failed = order_manager::OrderEvent::OrderFailed {
+                    request_id: leg.request_id,
+                    label: leg.client_order_id.clone(),
+                    reason,
+                    action: spec.action,
+                    retry_after: self.retry_after(spec.action, 1),
+                };
+                self.events.publish(Event::Order(failed));
+            }
+        }
+
+        Ok(())
+    }
+
+    fn restore_quote_label(&self, order: &mut Order) {
+        if order.label.is_empty() {
+            if let Some(label) = self.quote_labels.lock().unwrap().get(&order.order_id) {
+                order.label = label.clone();
+            }
+        }
+
+        match order.order_state {
+            OrderState::Open | OrderState::Untriggered => (),
+            _ => { self.quote_labels.lock().unwrap().remove(&order.order_id); }
+        }
+    }
+
+    fn get_order_state(&self, request_id: Uuid, instrument: String, order_id: Option<String>, label: String) -> Result<(), ConnectorError> {
+        let request = match order_id {
+            Some(order_id) => JsonRpcRequest::new("private/get_order_state".to_string(), request_id, Some(Params::OrderId { order_id })),
+            // closed orders are returned too, so a fill which wasn't seen still resolves
+            None => {
+                let params = Params::Label { currency: domain::instrument_currency(&instrument).to_string(), label: label.clone() };
+                JsonRpcRequest::new("private/get_order_state_by_label".to_string(), request_id, Some(params))
+            }
+        };
+
+        info!("Sending order state request {:?}", request);
+
+        self.send_tracked(request, PendingRequest::OrderState { label })
+    }
+
+    // resting orders are cancelled by the exchange if this connection drops
+    fn enable_cancel_on_disconnect(&self) -> Result<(), ConnectorError> {
+        let scope = Params::Scope { scope: "connection".to_string() };
+
+        let request = JsonRpcRequest::new("private/enable_cancel_on_disconnect".to_string(), Uuid::new_v4(), Some(scope));
+
+        info!("Sending cancel on disconnect request {:?}", request);
 
         self.send_request(request)
     }
 
-    fn make_order(&self, instrument: String, direction: TradeDirection, price: Decimal, amount: Decimal, label: String) -> JsonRpcRequest {
-        let method = match direction {
-            TradeDirection::Ask => "private/sell",
-            TradeDirection::Bid => "private/buy"
+    fn set_mmp_config(&self) -> Result<(), ConnectorError> {
+        let config = Params::MmpConfig {
+            index_name: self.config.mmp.index_name.clone(),
+            interval: self.config.mmp.interval,
+            frozen_time: self.config.mmp.frozen_time,
+            quantity_limit: self.config.mmp.quantity_limit,
+            delta_limit: self.config.mmp.delta_limit,
+            mmp_group: self.config.mmp.mmp_group.clone(),
         };
 
-        let order = Params::Order {
-            instrument_name: instrument,
-            price,
-            amount,
-            post_only: true,
-            label,
+        let request = JsonRpcRequest::new("private/set_mmp_config".to_string(), Uuid::new_v4(), Some(config));
+
+        info!("Sending MMP config {:?}", request);
+
+        self.send_request(request)
+    }
+
+    fn reset_mmp(&self, index_name: String) -> Result<(), ConnectorError> {
+        let request = JsonRpcRequest::new("private/reset_mmp".to_string(), Uuid::new_v4(), Some(Params::IndexName { index_name }));
+
+        info!("Sending MMP reset {:?}", request);
+
+        self.send_request(request)
+    }
+
+    // the result replaces the order manager positions
+    fn get_positions(&self, currency: String) -> Result<(), ConnectorError> {
+        let request = JsonRpcRequest::new("private/get_positions".to_string(), Uuid::new_v4(), Some(Params::Currency { currency }));
+
+        info!("Sending positions request {:?}", request);
+
+        self.send_tracked(request, PendingRequest::Positions)
+    }
+
+    // positions are fetched first, the closing orders are sent from the result
+    fn close_positions(&self, currency: String) -> Result<(), ConnectorError> {
+        let request_id = Uuid::new_v4();
+        let request = JsonRpcRequest::new("private/get_positions".to_string(), request_id, Some(Params::Currency { currency }));
+
+        info!("Sending positions request to close them {:?}", request);
+
+        self.send_tracked(request, PendingRequest::ClosePositions)
+    }
+
+    fn close_position(&self, position: Position) -> Result<(), ConnectorError> {
+        let method = if position.size.is_sign_positive() { "private/sell" } else { "private/buy" };
+
+        let order = Params::ReduceOnlyOrder {
+            instrument_name: position.instrument_name,
+            amount: position.size.abs(),
+            order_type: OrderType::Market,
+            reduce_only: true,
         };
 
-        JsonRpcRequest::new(method.to_string(), Uuid::new_v4(), Some(order))
+        let request = JsonRpcRequest::new(method.to_string(), Uuid::new_v4(), Some(order));
+
+        warn!("Closing position {:?}", request);
+
+        self.send_request(request)
     }
 
+    fn cancel_order(&self, order_id: String) -> Result<(), ConnectorError> {
+        let request = JsonRpcRequest::new("private/cancel".to_string(), Uuid::new_v4(), Some(Params::OrderId { order_id }));
 
-    fn send_request(&self, request: JsonRpcRequest) -> Result<(), Box<dyn Error>> {
+        info!("Sending cancel request {:?}", request);
+
+        self.send_request(request)
+    }
+
+    fn cancel_by_label(&self, currency: String, label: String) -> Result<(), ConnectorError> {
+        let request = JsonRpcRequest::new("private/cancel_by_label".to_string(), Uuid::new_v4(), Some(Params::Label { currency, label }));
+
+        info!("Sending cancel by label request {:?}", request);
+
+        self.send_request(request)
+    }
+
+    fn cancel_all(&self) -> Result<(), ConnectorError> {
+        let request = JsonRpcRequest::new("private/cancel_all".to_string(), Uuid::new_v4(), None);
+
+        info!("Sending cancel all request {:?}", request);
+
+        self.send_request(request)
+    }
+
+    fn on_pending_result(&self, request_id: Uuid, request: PendingRequest, result: Value) -> Result<(), ConnectorError> {
+        match request {
+            PendingRequest::OrderState { label } => self.on_order_state(request_id, label, result)?,
+            PendingRequest::OpenOrders => {
+                let orders: Vec<Order> = serde_json::from_value(result)?;
+                let orders = orders.into_iter().map(domain::Order::from).collect();
+
+                self.events.publish(Event::Order(order_manager::OrderEvent::OpenOrdersSnapshot { orders }));
+            }
+            PendingRequest::Positions => {
+                let positions: Vec<Position> = serde_json::from_value(result)?;
+                let positions = positions.into_iter()
+                    .filter(|position| position.kind == "future")
+                    .map(|position| (position.instrument_name, position.size))
+                    .collect();
+
+                self.events.publish(Event::Order(order_manager::OrderEvent::PositionsSnapshot { positions }));
+            }
+            PendingRequest::Order { method, .. } => {
+                let success = order_manager::OrderEvent::OrderSuccess { uuid: request_id, method: Some(method.to_string()) };
+                self.events.publish(Event::Order(success));
+            }
+            PendingRequest::MassQuote { legs } => self.on_mass_quote(legs, result)?,
+            PendingRequest::ClosePositions => {
+                let positions: Vec<Position> = serde_json::from_value(result)?;
+
+                for position in positions.into_iter().filter(|position| !position.size.is_zero()) {
+                    self.close_position(position)?;
+                }
+            }
+            PendingRequest::Instruments => {
+                let instruments: Vec<Instrument> = serde_json::from_value(result)?;
+                info!("Got {} instruments", instruments.len());
+
+                self.events.publish(Event::Instruments(instruments.into_iter().map(Into::into).collect()));
+            }
+            PendingRequest::AccountSummary => {
+                let summary: AccountSummary = serde_json::from_value(result)?;
+                info!("Got account summary for {}: balance {}", summary.currency, summary.balance);
+
+                self.events.publish(Event::Portfolio(summary.into()));
+            }
+            PendingRequest::Time { sent_at } => {
+                let exchange_time = result.as_i64()
+                    .ok_or_else(|| ConnectorError::Parse(format!("get_time result {} isn't a timestamp", result)))?;
+                let sample = self.latency.record_clock(sent_at, now_millis(), exchange_time);
+
+                info!("Exchange clock offset {}ms, round trip {}ms, using {}ms",
+                    sample.offset_ms, sample.round_trip_ms, self.latency.clock_offset_ms());
+            }
+            PendingRequest::Auth { reason } => self.on_auth(reason, result)?,
+        }
+
+        Ok(())
+    }
+
+    // get_order_state returns a single order, get_order_state_by_label a list
+    fn on_order_state(&self, request_id: Uuid, label: String, result: Value) -> Result<(), ConnectorError> {
+        let orders: Vec<Order> = match result {
+            Value::Array(_) => serde_json::from_value(result)?,
+            _ => vec!(serde_json::from_value(result)?),
+        };
+
+        let order = orders.into_iter().find(|order| order.label == label);
+        let found = order.is_some();
+
+        if let Some(order) = order {
+            self.events.publish(Event::Order(order_changed(order)));
+        }
+
+        self.events.publish(Event::Order(order_manager::OrderEvent::OrderStateResolved { request_id, label, found }));
+
+        Ok(())
+    }
+
+
+    fn send_request(&self, request: JsonRpcRequest) -> Result<(), ConnectorError> {
         let s = serde_json::to_string(&request)?;
 
+        let outbound_sender = self.outbound.lock().unwrap().clone()
+            .ok_or_else(|| ConnectorError::Transport("not connected".to_string()))?;
+
         info!("Sending request: {:?}", s);
-        // println!("lock write");
 
+        self.rate_limiter.lock().unwrap().consume(&request.method, Instant::now());
+        outbound_sender.send(Outbound { request_id: request.id, method: request.method, text: s, queued_at: Instant::now() })?;
 
-        // let mut lock = self.socket.try_lock();
-        //
-        // if let Ok(ref mut mutex) = lock {
-        //     mutex.write_message(Message::Text(s)).unwrap();
-        //     info!("Sent request");
-        // }  else {
-        //     println!("try_lock failed");
-        // };
-        //
-        // Ok(())
+        Ok(())
+    }
 
+    // a fresh queue per connection, nothing queued for a lost one is written to the next
+    pub(crate) fn open_outbound(&self) -> Receiver<Outbound> {
+        let (outbound_sender, outbound_receiver) = unbounded();
+        *self.outbound.lock().unwrap() = Some(outbound_sender);
 
-        // println!("Get socket lock on write");
-        let r = match self.socket.lock().unwrap().write_message(Message::Text(s)) {
-            Ok(_) => Ok(()),
-            Err(e) => Err(Box::try_from(format!("Error {:?}", e)).unwrap()),
-        };
-        // println!("Release socket lock on write");
-        r
+        outbound_receiver
+    }
+
+    // the connection is closed once the queue ends, private commands wait for the next auth
+    pub(crate) fn close_outbound(&self) {
+        self.outbound.lock().unwrap().take();
+        self.token.lock().unwrap().take();
     }
 
 
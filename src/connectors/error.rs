@@ -0,0 +1,65 @@
+use std::error::Error;
+use std::fmt;
+
+use crossbeam_channel::SendError;
+
+// What can go wrong between the connector and the exchange. Parse and protocol
+// errors concern a single frame, it is counted and dropped and the connection stays up.
+#[derive(Debug)]
+pub enum ConnectorError {
+    // the payload doesn't match the type the channel or the request result promises
+    Parse(String),
+    // valid JSON, but not a message the protocol has
+    Protocol(String),
+    // the socket or the outbound queue failed
+    Transport(String),
+    // the exchange refused the credentials or the token
+    Auth(String),
+    // the exchange answered a request with an error
+    Exchange { code: i32, message: String },
+}
+
+impl ConnectorError {
+    // label for the metrics
+    pub fn kind(&self) -> &'static str {
+        match self {
+            ConnectorError::Parse(_) => "parse",
+            ConnectorError::Protocol(_) => "protocol",
+            ConnectorError::Transport(_) => "transport",
+            ConnectorError::Auth(_) => "auth",
+            ConnectorError::Exchange { .. } => "exchange",
+        }
+    }
+}
+
+impl fmt::Display for ConnectorError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ConnectorError::Parse(message) => write!(f, "parse error: {}", message),
+            ConnectorError::Protocol(message) => write!(f, "protocol error: {}", message),
+            ConnectorError::Transport(message) => write!(f, "transport error: {}", message),
+            ConnectorError::Auth(message) => write!(f, "auth error: {}", message),
+            ConnectorError::Exchange { code, message } => write!(f, "exchange error {}: {}", code, message),
+        }
+    }
+}
+
+impl Error for ConnectorError {}
+
+impl From<serde_json::Error> for ConnectorError {
+    fn from(e: serde_json::Error) -> ConnectorError {
+        ConnectorError::Parse(e.to_string())
+    }
+}
+
+impl From<tungstenite::Error> for ConnectorError {
+    fn from(e: tungstenite::Error) -> ConnectorError {
+        ConnectorError::Transport(e.to_string())
+    }
+}
+
+impl<T> From<SendError<T>> for ConnectorError {
+    fn from(_: SendError<T>) -> ConnectorError {
+        ConnectorError::Transport("outbound queue is closed".to_string())
+    }
+}
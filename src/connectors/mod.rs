@@ -1 +1,2 @@
-pub mod deribit;
\ No newline at end of file
+pub mod deribit;
+pub mod error;
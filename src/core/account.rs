@@ -0,0 +1,77 @@
+use std::collections::HashMap;
+use std::sync::{Arc, RwLock};
+use std::thread;
+use log::info;
+use crate::core::domain::AccountState;
+use crate::core::event_bus::{Event, EventReader};
+
+// Latest account state per currency, from portfolio updates and account summaries.
+// Risk checks and monitoring read it instead of subscribing to the bus themselves.
+#[derive(Default)]
+pub struct Accounts {
+    accounts: RwLock<HashMap<String, AccountState>>,
+}
+
+impl Accounts {
+    pub fn new() -> Accounts {
+        Accounts::default()
+    }
+
+    pub fn apply(&self, event: &Event) {
+        if let Event::Portfolio(account) = event {
+            self.accounts.write().unwrap().insert(account.currency.clone(), account.clone());
+        }
+    }
+
+    pub fn all(&self) -> Vec<AccountState> {
+        self.accounts.read().unwrap().values().cloned().collect()
+    }
+}
+
+pub fn track(accounts: Arc<Accounts>, events: EventReader) {
+    thread::spawn(move || {
+        for event in events {
+            if let Event::Portfolio(account) = &event {
+                info!("Account {}: equity {}, available {}, margin ratio {:?}, delta {}",
+                    account.currency, account.equity, account.available_funds, account.margin_ratio(), account.delta_total);
+            }
+            accounts.apply(&event);
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use rust_decimal::Decimal;
+    use super::*;
+
+    fn account(currency: &str, equity: i64, maintenance_margin: i64) -> Event {
+        Event::Portfolio(AccountState {
+            currency: currency.to_string(),
+            equity: Decimal::from(equity),
+            maintenance_margin: Decimal::from(maintenance_margin),
+            ..Default::default()
+        })
+    }
+
+    #[test]
+    fn check_accounts_per_currency() {
+        let accounts = Accounts::new();
+
+        accounts.apply(&account("BTC", 10, 1));
+        accounts.apply(&account("ETH", 100, 0));
+        accounts.apply(&account("BTC", 8, 2));
+
+        let margin_ratio = |currency: &str| accounts.all().into_iter()
+            .find(|account| account.currency == currency)
+            .map(|account| account.margin_ratio());
+
+        assert_eq!(margin_ratio("BTC"), Some(Some(Decimal::new(25, 2))));
+        assert_eq!(margin_ratio("ETH"), Some(Some(Decimal::ZERO)));
+        assert_eq!(margin_ratio("USDC"), None);
+        assert_eq!(accounts.all().len(), 2);
+
+        accounts.apply(&account("ETH", 0, 0));
+        assert_eq!(margin_ratio("ETH"), Some(None));
+    }
+}
@@ -0,0 +1,241 @@
+use serde::{Serialize, Deserialize};
+use rust_decimal::Decimal;
+
+// Venue independent model, connectors convert their protocol types into these with From
+
+#[derive(Serialize, Deserialize)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Side {
+    Bid,
+    Ask,
+}
+
+#[derive(Serialize, Deserialize)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OrderStatus {
+    Open,
+    Filled,
+    Rejected,
+    Cancelled,
+    Untriggered,
+}
+
+// how an exchange error is treated, connectors map their error codes onto these
+#[derive(Serialize)]
+#[serde(rename_all = "snake_case")]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ErrorCategory {
+    // temporary, the same request can go again later
+    Retryable,
+    // the exchange refused this particular order or request
+    Rejected,
+    // the request can't succeed with the current settings or account permissions
+    Config,
+    // the session can't trade anymore
+    Fatal,
+}
+
+impl ErrorCategory {
+    pub fn name(&self) -> &'static str {
+        match self {
+            ErrorCategory::Retryable => "retryable",
+            ErrorCategory::Rejected => "rejected",
+            ErrorCategory::Config => "config",
+            ErrorCategory::Fatal => "fatal",
+        }
+    }
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "snake_case")]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ErrorAction {
+    // hold requests back for a while
+    Backoff,
+    // quote again with a fresh price
+    Reprice,
+    // nothing to do, e.g. cancelling an order which is already gone
+    Ignore,
+    Alert,
+    // engage the kill switch
+    Halt,
+    // the token lapsed, authorize the session again
+    Reauthenticate,
+}
+
+#[derive(Serialize)]
+#[derive(Debug, Clone)]
+pub struct Order {
+    pub(crate) id: String,
+    pub(crate) instrument: String,
+    pub(crate) direction: Side,
+    pub(crate) price: Decimal,
+    pub(crate) amount: Decimal,
+    pub(crate) filled_amount: Decimal,
+    pub(crate) status: OrderStatus,
+    pub(crate) label: String,
+}
+
+// execution of one of our orders
+#[derive(Debug, Clone)]
+pub struct Fill {
+    pub(crate) timestamp: i64,
+    pub(crate) instrument_name: String,
+    pub(crate) trade_id: String,
+    pub(crate) order_id: String,
+    pub(crate) label: String,
+    pub(crate) direction: Side,
+    pub(crate) price: Decimal,
+    pub(crate) amount: Decimal,
+}
+
+// public trade, direction is the aggressor side
+// the market data types carry the whole feed payload, not every field is consumed yet
+#[allow(dead_code)]
+#[derive(Debug, Clone)]
+pub struct Trade {
+    pub(crate) timestamp: i64,
+    pub(crate) instrument_name: String,
+    pub(crate) trade_id: String,
+    pub(crate) trade_seq: i64,
+    pub(crate) direction: Side,
+    pub(crate) price: Decimal,
+    pub(crate) amount: Decimal,
+    pub(crate) mark_price: Decimal,
+    pub(crate) index_price: Decimal,
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+#[derive(Debug, Clone, PartialEq)]
+pub enum PriceLevelAction {
+    New,
+    Change,
+    Delete,
+}
+
+#[derive(Serialize, Deserialize)]
+#[derive(Debug, Clone)]
+pub struct PriceLevelChange {
+    pub(crate) action: PriceLevelAction,
+    pub(crate) price: Decimal,
+    pub(crate) amount: Decimal,
+}
+
+// incremental book update, the levels of a snapshot come as New
+#[derive(Serialize, Deserialize)]
+#[derive(Debug, Clone)]
+pub struct OrderbookUpdate {
+    pub(crate) timestamp: i64,
+    pub(crate) instrument_name: String,
+    pub(crate) change_id: i64,
+    pub(crate) bids: Vec<PriceLevelChange>,
+    pub(crate) asks: Vec<PriceLevelChange>,
+}
+
+#[allow(dead_code)]
+#[derive(Debug, Clone)]
+pub struct Ticker {
+    pub(crate) timestamp: i64,
+    pub(crate) instrument_name: String,
+    pub(crate) best_bid_price: Option<Decimal>,
+    pub(crate) best_bid_amount: Decimal,
+    pub(crate) best_ask_price: Option<Decimal>,
+    pub(crate) best_ask_amount: Decimal,
+    pub(crate) last_price: Option<Decimal>,
+    pub(crate) mark_price: Decimal,
+    pub(crate) index_price: Decimal,
+    pub(crate) open_interest: Decimal,
+    // perpetuals only
+    pub(crate) current_funding: Option<Decimal>,
+    pub(crate) funding_8h: Option<Decimal>,
+}
+
+#[allow(dead_code)]
+#[derive(Debug, Clone)]
+pub struct IndexPrice {
+    pub(crate) timestamp: i64,
+    pub(crate) index_name: String,
+    pub(crate) price: Decimal,
+}
+
+#[allow(dead_code)]
+#[derive(Debug, Clone)]
+pub struct MarkPrice {
+    pub(crate) timestamp: i64,
+    pub(crate) instrument_name: String,
+    pub(crate) mark_price: Decimal,
+    // options only
+    pub(crate) iv: Option<Decimal>,
+}
+
+#[allow(dead_code)]
+#[derive(Debug, Clone)]
+pub struct Funding {
+    pub(crate) timestamp: i64,
+    pub(crate) instrument_name: String,
+    // current funding rate
+    pub(crate) interest: Decimal,
+    pub(crate) index_price: Decimal,
+}
+
+// base currency of a deribit instrument name, "BTC-PERPETUAL" -> "BTC"
+pub fn instrument_currency(instrument: &str) -> &str {
+    instrument.split('-').next().unwrap_or(instrument)
+}
+
+#[derive(Serialize, Deserialize)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum InstrumentKind {
+    Future,
+    Option,
+    Spot,
+    Combo,
+}
+
+#[derive(Serialize)]
+#[derive(Debug, Clone)]
+pub struct Instrument {
+    pub(crate) name: String,
+    pub(crate) kind: InstrumentKind,
+    pub(crate) base_currency: String,
+    pub(crate) quote_currency: String,
+    pub(crate) tick_size: Decimal,
+    pub(crate) min_trade_amount: Decimal,
+    pub(crate) contract_size: Decimal,
+    // milliseconds
+    pub(crate) expiration_timestamp: i64,
+}
+
+// margin and risk figures of one currency sub-account
+#[derive(Serialize)]
+#[derive(Debug, Clone, Default)]
+pub struct AccountState {
+    pub(crate) currency: String,
+    pub(crate) balance: Decimal,
+    pub(crate) equity: Decimal,
+    pub(crate) available_funds: Decimal,
+    pub(crate) margin_balance: Decimal,
+    pub(crate) initial_margin: Decimal,
+    pub(crate) maintenance_margin: Decimal,
+    pub(crate) estimated_liquidation_ratio: Option<Decimal>,
+    pub(crate) delta_total: Decimal,
+    pub(crate) options_delta: Decimal,
+    pub(crate) options_gamma: Decimal,
+    pub(crate) options_vega: Decimal,
+    pub(crate) options_theta: Decimal,
+    pub(crate) session_rpl: Decimal,
+    pub(crate) session_upl: Decimal,
+    pub(crate) total_pl: Decimal,
+}
+
+impl AccountState {
+    // maintenance margin / equity, liquidation starts at 1
+    pub fn margin_ratio(&self) -> Option<Decimal> {
+        if self.equity > Decimal::ZERO {
+            Some(self.maintenance_margin / self.equity)
+        } else {
+            None
+        }
+    }
+}
@@ -1,50 +1,36 @@
-use serde::{Serialize, Deserialize};
 use serde_json::Value;
 use rust_decimal::Decimal;
 use uuid::Uuid;
+use crate::core::domain::Side;
 
+// one side of a quote sent with Command::MakeQuotes
 #[derive(Debug)]
-#[derive(Serialize, Deserialize)]
-pub enum OrderSide {
-    Ask, Bid
+pub struct QuoteLeg {
+    pub request_id: Uuid,
+    pub client_order_id: String,
+    pub direction: Side,
+    pub price: Decimal,
+    pub amount: Decimal,
+    // can only shrink the position, the exchange cuts it down to the position size
+    pub reduce_only: bool,
 }
 
 #[derive(Debug)]
 pub enum Command {
     SubscribeData { channel: String },
     UnsubscribeData { channel: String },
-    MakeOrder { request_id: Uuid, direction: OrderSide, instrument: String, price: Decimal, amount: Decimal, label: String },
+    // single orders aren't placed by the quoter, the connector still supports them
+    #[allow(dead_code)]
+    MakeOrder { request_id: Uuid, client_order_id: String, direction: Side, instrument: String, price: Decimal, amount: Decimal },
+    MakeQuotes { request_id: Uuid, instrument: String, legs: Vec<QuoteLeg> },
     CancelOrder { id: String },
+    CancelByLabel { currency: String, label: String },
+    GetOrderState { request_id: Uuid, instrument: String, label: String, order_id: Option<String> },
     CancelAll,
+    ClosePositions { currency: String },
+    GetPositions { currency: String },
+    // open orders, positions and balance
+    GetSnapshot { currency: String },
+    ResetMmp { index_name: String },
     SendHeartBeat,
 }
-
-#[derive(Serialize, Deserialize)]
-#[serde(rename_all = "camelCase")]
-#[derive(Debug)]
-#[derive(PartialEq)]
-pub enum PriceLevelAction {
-    New,
-    Change,
-    Delete,
-}
-
-#[derive(Serialize, Deserialize)]
-#[derive(Debug)]
-pub struct PriceLevelChange {
-    pub(crate) action: PriceLevelAction,
-    pub(crate) price: Decimal,
-    pub(crate) amount: Decimal,
-}
-
-
-#[derive(Serialize, Deserialize)]
-#[derive(Debug)]
-pub struct
-    OrderbookUpdate {
-        pub(crate) timestamp: i64,
-        pub(crate) instrument_name: String,
-        pub(crate) change_id: i64,
-        pub(crate) bids: Vec<PriceLevelChange>,
-        pub(crate) asks: Vec<PriceLevelChange>,
-    }
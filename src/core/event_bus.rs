@@ -0,0 +1,432 @@
+use std::hint;
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+use crossbeam_channel::{bounded, Receiver, Sender};
+use disrustor::internal::{BlockingWaitStrategy, ProcessingSequenceBarrier, RingBuffer, SingleProducerSequencer};
+use disrustor::{AtomicSequence, DataProvider, Sequence, SequenceBarrier, Sequencer, WaitStrategy};
+use log::{error, warn};
+use crate::core::domain::{AccountState, Fill, Funding, IndexPrice, Instrument, MarkPrice, OrderbookUpdate, Ticker, Trade};
+use crate::strategy::order_manager::OrderEvent;
+
+// readers which are gone shouldn't hold the publisher back, half of the range so the
+// sequencer doesn't overflow adding the buffer size
+const DETACHED: Sequence = Sequence::MAX / 2;
+
+// the publisher spins, then yields, then sleeps while the ring is full
+const SPIN_TRIES: u32 = 100;
+const YIELD_TRIES: u32 = 200;
+const IDLE_SLEEP: Duration = Duration::from_micros(50);
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum ConnectionEvent {
+    Connected,
+    Disconnected,
+    Heartbeat,
+}
+
+#[derive(Debug, Clone)]
+pub enum Event {
+    Book(OrderbookUpdate),
+    Trade(Trade),
+    Order(OrderEvent),
+    Fill(Fill),
+    Portfolio(AccountState),
+    Connection(ConnectionEvent),
+    Ticker(Ticker),
+    IndexPrice(IndexPrice),
+    MarkPrice(MarkPrice),
+    Funding(Funding),
+    // the instruments of a currency, fetched on every connect
+    Instruments(Vec<Instrument>),
+    // only seen by a reader which was detached for stalling, it goes on from the
+    // newest event and the ones in between are lost
+    Gap { missed: i64 },
+}
+
+type Slots = RingBuffer<Option<Event>>;
+type Readers = Arc<Vec<(String, Arc<AtomicSequence>)>>;
+
+// Disruptor ring buffer shared by all consumers. Every reader sees every event in
+// publishing order and moves at its own pace. Publishers hand events to the single
+// thread which owns the sequencer through a queue as large as the ring, they block
+// once both are full. That thread waits for the slowest reader when the ring is full,
+// and detaches a reader which doesn't move for reader_timeout.
+#[derive(Clone)]
+pub struct EventBus {
+    sender: Sender<Event>,
+    // last published sequence
+    cursor: Arc<AtomicSequence>,
+    readers: Readers,
+}
+
+pub struct EventBusBuilder {
+    slots: Arc<Slots>,
+    sequencer: SingleProducerSequencer<BlockingWaitStrategy>,
+    readers: Vec<(String, Arc<AtomicSequence>)>,
+    reader_timeout: Duration,
+}
+
+pub struct EventReader {
+    name: String,
+    slots: Arc<Slots>,
+    barrier: ProcessingSequenceBarrier<BlockingWaitStrategy>,
+    cursor: Arc<AtomicSequence>,
+    published: Arc<AtomicSequence>,
+    // highest published sequence seen by the last wait
+    available: Sequence,
+    // last sequence handed out
+    read: Sequence,
+}
+
+struct Publisher {
+    slots: Arc<Slots>,
+    sequencer: SingleProducerSequencer<BlockingWaitStrategy>,
+    readers: Readers,
+    reader_timeout: Duration,
+}
+
+impl EventBus {
+    pub fn builder(capacity: usize, reader_timeout: Duration) -> EventBusBuilder {
+        let slots = Arc::new(RingBuffer::new(capacity));
+        let sequencer = SingleProducerSequencer::new(capacity, BlockingWaitStrategy::new());
+
+        EventBusBuilder { slots, sequencer, readers: vec!(), reader_timeout }
+    }
+
+    // blocks while the publisher is behind, a stalled reader holds it for reader_timeout at most
+    pub fn publish(&self, event: Event) {
+        if self.sender.send(event).is_err() {
+            error!("Event bus publisher is gone, dropping the event");
+        }
+    }
+
+    // events published but not yet read, per reader
+    pub fn lag(&self) -> Vec<(String, i64)> {
+        let published = self.cursor.get();
+
+        self.readers.iter()
+            .filter(|(_, cursor)| cursor.get() != DETACHED)
+            .map(|(name, cursor)| (name.clone(), published - cursor.get()))
+            .collect()
+    }
+}
+
+impl EventBusBuilder {
+    // readers have to be registered before the bus is built, each one gates the publisher
+    pub fn subscribe(&mut self, name: &str) -> EventReader {
+        let cursor = Arc::new(AtomicSequence::default());
+        let barrier = self.sequencer.create_barrier(&[self.sequencer.get_cursor()]);
+
+        self.sequencer.add_gating_sequence(&cursor);
+        self.readers.push((name.to_string(), Arc::clone(&cursor)));
+
+        EventReader {
+            name: name.to_string(),
+            slots: Arc::clone(&self.slots),
+            barrier,
+            cursor,
+            published: self.sequencer.get_cursor(),
+            available: -1,
+            read: -1,
+        }
+    }
+
+    pub fn build(self) -> EventBus {
+        assert!(!self.readers.is_empty(), "Event bus needs at least one reader");
+
+        let (sender, receiver) = bounded(self.slots.buffer_size());
+        let cursor = self.sequencer.get_cursor();
+        let readers = Arc::new(self.readers);
+        let publisher = Publisher {
+            slots: self.slots,
+            sequencer: self.sequencer,
+            readers: Arc::clone(&readers),
+            reader_timeout: self.reader_timeout,
+        };
+
+        thread::Builder::new()
+            .name("event bus".to_string())
+            .spawn(move || publisher.run(receiver))
+            .expect("Can't start the event bus publisher");
+
+        EventBus { sender, cursor, readers }
+    }
+}
+
+impl Publisher {
+    // runs until every bus handle is dropped, dropping the sequencer then closes the readers
+    fn run(self, receiver: Receiver<Event>) {
+        for event in receiver.iter() {
+            // the sequencer gates on the readers too, but its first lap starts one slot ahead
+            let sequence = self.sequencer.get_cursor().get() + 1;
+            self.wait_for_readers(sequence);
+
+            let (sequence, _) = self.sequencer.next(1);
+            // the slot is free, every reader has moved past its previous event
+            unsafe {
+                *self.slots.get_mut(sequence) = Some(event);
+            }
+            self.sequencer.publish(sequence, sequence);
+        }
+    }
+
+    fn wait_for_readers(&self, sequence: Sequence) {
+        let capacity = self.slots.buffer_size() as Sequence;
+        let mut tries = 0;
+        let mut waiting_since = None;
+
+        loop {
+            let (name, cursor) = self.slowest_reader();
+            let position = cursor.get();
+            if sequence - position <= capacity {
+                return;
+            }
+
+            let since = *waiting_since.get_or_insert_with(|| {
+                warn!("Event bus is full, waiting for {}", name);
+                Instant::now()
+            });
+            if since.elapsed() >= self.reader_timeout {
+                error!(target: "alerts", "Event bus reader {} is stalled at {}, detaching it", name, position);
+                // the reader may have just moved, it's detached only if it didn't
+                cursor.compare_exchange(position, DETACHED);
+                waiting_since = None;
+                continue;
+            }
+
+            tries += 1;
+            if tries <= SPIN_TRIES {
+                hint::spin_loop();
+            } else if tries <= SPIN_TRIES + YIELD_TRIES {
+                thread::yield_now();
+            } else {
+                thread::sleep(IDLE_SLEEP);
+            }
+        }
+    }
+
+    fn slowest_reader(&self) -> (&str, &Arc<AtomicSequence>) {
+        self.readers.iter()
+            .map(|(name, cursor)| (name.as_str(), cursor))
+            .min_by_key(|(_, cursor)| cursor.get())
+            .unwrap()
+    }
+}
+
+impl EventReader {
+    // blocks until the next event is published, None once the bus is dropped. A reader
+    // detached for stalling gets Event::Gap and reads on from the newest event
+    pub fn next_event(&mut self) -> Option<Event> {
+        let position = self.cursor.get();
+        if position == DETACHED {
+            return Some(self.reattach());
+        }
+        let sequence = position + 1;
+
+        if sequence > self.available {
+            self.available = match self.barrier.wait_for(sequence) {
+                Some(available) => available,
+                // the bus is closed, events published before that are still delivered
+                None if self.published.get() >= sequence => self.published.get(),
+                None => return None,
+            };
+        }
+
+        // the slot can't be overwritten until the cursor below moves past it
+        let event = unsafe { self.slots.get(sequence) }.clone();
+        // detached meanwhile, the slot may hold a newer event already
+        if !self.cursor.compare_exchange(position, sequence) {
+            return Some(self.reattach());
+        }
+        self.read = sequence;
+
+        event
+    }
+
+    fn reattach(&mut self) -> Event {
+        let capacity = self.slots.buffer_size() as Sequence;
+        let mut head = self.published.get();
+
+        // the publisher ignored the reader until the cursor was set, whatever it published
+        // meanwhile must not have lapped the cursor
+        loop {
+            self.cursor.set(head);
+            let published = self.published.get();
+            if published - head < capacity {
+                break;
+            }
+            head = published;
+        }
+
+        let missed = head - self.read;
+        warn!("Event bus reader {} was detached, {} events are lost", self.name, missed);
+        self.available = head;
+        self.read = head;
+
+        Event::Gap { missed }
+    }
+}
+impl Iterator for EventReader {
+    type Item = Event;
+
+    fn next(&mut self) -> Option<Event> {
+        self.next_event()
+    }
+}
+
+impl Drop for EventReader {
+    fn drop(&mut self) {
+        self.cursor.set(DETACHED);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, Instant};
+    use crossbeam_channel::bounded;
+    use rust_decimal::Decimal;
+    use super::*;
+
+    fn balance(value: i64) -> Event {
+        Event::Portfolio(AccountState { balance: Decimal::from(value), ..Default::default() })
+    }
+
+    fn value(event: Event) -> i64 {
+        match event {
+            Event::Portfolio(account) => account.balance.try_into().unwrap(),
+            other => panic!("Unexpected event {:?}", other),
+        }
+    }
+
+    #[test]
+    fn check_readers_see_all_events() {
+        let mut builder = EventBus::builder(4, Duration::from_secs(10));
+        let first = builder.subscribe("first");
+        let second = builder.subscribe("second");
+        let bus = builder.build();
+
+        let handles: Vec<_> = vec!(first, second).into_iter()
+            .map(|reader| thread::spawn(move || reader.map(value).collect::<Vec<i64>>()))
+            .collect();
+
+        for i in 0..100 {
+            bus.publish(balance(i));
+        }
+        drop(bus);
+
+        for handle in handles {
+            assert_eq!(handle.join().unwrap(), (0..100).collect::<Vec<i64>>());
+        }
+    }
+
+    // the publisher thread is asynchronous, give it a moment to catch up
+    fn wait_for_lag(bus: &EventBus, expected: Vec<(String, i64)>) {
+        let started = Instant::now();
+        while bus.lag() != expected && started.elapsed() < Duration::from_secs(1) {
+            thread::sleep(Duration::from_millis(1));
+        }
+        assert_eq!(bus.lag(), expected);
+    }
+
+    #[test]
+    fn check_slow_reader_holds_back_ring() {
+        let mut builder = EventBus::builder(4, Duration::from_secs(10));
+        let mut reader = builder.subscribe("slow");
+        let bus = builder.build();
+
+        // the fifth event waits in the queue for a free slot
+        for i in 0..5 {
+            bus.publish(balance(i));
+        }
+        wait_for_lag(&bus, vec!(("slow".to_string(), 4)));
+        thread::sleep(Duration::from_millis(50));
+        assert_eq!(bus.lag(), vec!(("slow".to_string(), 4)));
+
+        assert_eq!(value(reader.next_event().unwrap()), 0);
+        wait_for_lag(&bus, vec!(("slow".to_string(), 4)));
+        assert_eq!((1..5).map(|_| value(reader.next_event().unwrap())).collect::<Vec<i64>>(), vec!(1, 2, 3, 4));
+    }
+
+    #[test]
+    fn check_stalled_reader_is_reattached() {
+        let mut builder = EventBus::builder(4, Duration::from_millis(50));
+        let mut stalled = builder.subscribe("stalled");
+        let active = builder.subscribe("active");
+        let bus = builder.build();
+
+        let handle = thread::spawn(move || active.map(value).collect::<Vec<i64>>());
+        for i in 0..10 {
+            bus.publish(balance(i));
+        }
+        wait_for_lag(&bus, vec!(("active".to_string(), 0)));
+
+        assert!(matches!(stalled.next_event(), Some(Event::Gap { missed: 10 })));
+        bus.publish(balance(10));
+        assert_eq!(value(stalled.next_event().unwrap()), 10);
+        drop(bus);
+
+        assert_eq!(handle.join().unwrap(), (0..11).collect::<Vec<i64>>());
+        assert!(stalled.next_event().is_none());
+    }
+
+    #[test]
+    fn check_dropped_reader_detaches() {
+        let mut builder = EventBus::builder(4, Duration::from_secs(10));
+        let reader = builder.subscribe("gone");
+        let mut active = builder.subscribe("active");
+        let bus = builder.build();
+        drop(reader);
+
+        for i in 0..3 {
+            bus.publish(balance(i));
+        }
+
+        assert_eq!(value(active.next_event().unwrap()), 0);
+        wait_for_lag(&bus, vec!(("active".to_string(), 2)));
+    }
+
+    // cargo test --release bench_bus_against_channels -- --ignored --nocapture
+    #[test]
+    #[ignore]
+    fn bench_bus_against_channels() {
+        const EVENTS: i64 = 1_000_000;
+        const READERS: usize = 3;
+
+        let mut builder = EventBus::builder(4096, Duration::from_secs(10));
+        let readers: Vec<EventReader> = (0..READERS).map(|i| builder.subscribe(&format!("reader {}", i))).collect();
+        let bus = builder.build();
+
+        let started = Instant::now();
+        let handles: Vec<_> = readers.into_iter()
+            .map(|reader| thread::spawn(move || reader.count()))
+            .collect();
+        for i in 0..EVENTS {
+            bus.publish(balance(i));
+        }
+        drop(bus);
+        handles.into_iter().for_each(|handle| assert_eq!(handle.join().unwrap(), EVENTS as usize));
+        let bus_elapsed = started.elapsed();
+
+        // one bounded(10) channel per consumer, as the components were wired before
+        let (senders, receivers): (Vec<_>, Vec<_>) = (0..READERS).map(|_| bounded::<Event>(10)).unzip();
+
+        let started = Instant::now();
+        let handles: Vec<_> = receivers.into_iter()
+            .map(|receiver| thread::spawn(move || receiver.iter().count()))
+            .collect();
+        for i in 0..EVENTS {
+            for sender in &senders {
+                sender.send(balance(i)).unwrap();
+            }
+        }
+        drop(senders);
+        handles.into_iter().for_each(|handle| assert_eq!(handle.join().unwrap(), EVENTS as usize));
+        let channels_elapsed = started.elapsed();
+
+        println!("{} events to {} readers: event bus {:?} ({:.0} events/s), channels {:?} ({:.0} events/s)",
+            EVENTS, READERS,
+            bus_elapsed, EVENTS as f64 / bus_elapsed.as_secs_f64(),
+            channels_elapsed, EVENTS as f64 / channels_elapsed.as_secs_f64());
+    }
+}
@@ -0,0 +1,232 @@
+use std::collections::{BTreeMap, VecDeque};
+use std::sync::atomic::{AtomicI64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+use log::info;
+
+const REPORT_INTERVAL: Duration = Duration::from_secs(60);
+// clock samples the offset is picked from, the one with the shortest round trip wins
+const CLOCK_SAMPLES: usize = 10;
+
+// upper bounds in microseconds, samples above the last one go to an overflow bucket
+pub const BUCKETS: [u64; 18] = [
+    50, 100, 250, 500,
+    1_000, 2_500, 5_000, 10_000, 25_000, 50_000, 100_000, 250_000, 500_000,
+    1_000_000, 2_500_000, 5_000_000, 10_000_000, 30_000_000,
+];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum LatencyKind {
+    // waiting in the outbound queue until written to the socket
+    Queue,
+    // request written -> response received
+    SendToAck,
+    // response -> the order shows up in user.orders
+    AckToUpdate,
+    // request written -> the order shows up in user.orders
+    SendToUpdate,
+    // usDiff, time the request spent inside the exchange
+    Exchange,
+}
+
+impl LatencyKind {
+    pub fn name(&self) -> &'static str {
+        match self {
+            LatencyKind::Queue => "queue",
+            LatencyKind::SendToAck => "send_to_ack",
+            LatencyKind::AckToUpdate => "ack_to_update",
+            LatencyKind::SendToUpdate => "send_to_update",
+            LatencyKind::Exchange => "exchange",
+        }
+    }
+}
+
+// Fixed bucket histogram, percentiles are the upper bound of the bucket they fall in
+#[derive(Debug, Clone)]
+pub struct Histogram {
+    counts: [u64; BUCKETS.len() + 1],
+    count: u64,
+    // microseconds
+    sum: u64,
+    max: u64,
+}
+
+impl Histogram {
+    pub fn new() -> Histogram {
+        Histogram { counts: [0; BUCKETS.len() + 1], count: 0, sum: 0, max: 0 }
+    }
+
+    pub fn record(&mut self, latency: Duration) {
+        let micros = latency.as_micros() as u64;
+        let bucket = BUCKETS.iter().position(|bound| micros <= *bound).unwrap_or(BUCKETS.len());
+
+        self.counts[bucket] += 1;
+        self.count += 1;
+        self.sum += micros;
+        self.max = self.max.max(micros);
+    }
+
+    pub fn count(&self) -> u64 {
+        self.count
+    }
+
+    pub fn sum(&self) -> Duration {
+        Duration::from_micros(self.sum)
+    }
+
+    pub fn max(&self) -> Duration {
+        Duration::from_micros(self.max)
+    }
+
+    // p in 0..=100
+    pub fn percentile(&self, p: u64) -> Duration {
+        let rank = (self.count * p).div_ceil(100);
+        let mut seen = 0;
+
+        for (bucket, count) in self.counts.iter().enumerate() {
+            seen += count;
+            if seen >= rank.max(1) {
+                let bound = BUCKETS.get(bucket).cloned().unwrap_or(self.max);
+                return Duration::from_micros(bound.min(self.max));
+            }
+        }
+
+        Duration::ZERO
+    }
+
+    // (upper bound in microseconds, samples at or below it), cumulative like prometheus buckets
+    pub fn cumulative_buckets(&self) -> Vec<(u64, u64)> {
+        let mut seen = 0;
+
+        BUCKETS.iter().zip(self.counts.iter())
+            .map(|(bound, count)| {
+                seen += count;
+                (*bound, seen)
+            })
+            .collect()
+    }
+}
+
+impl Default for Histogram {
+    fn default() -> Histogram {
+        Histogram::new()
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ClockSample {
+    // exchange time minus local time
+    pub offset_ms: i64,
+    pub round_trip_ms: i64,
+}
+
+// Latency histograms per kind and request method, and the exchange clock offset
+// estimated from public/get_time.
+#[derive(Default)]
+pub struct Latency {
+    histograms: Mutex<BTreeMap<(LatencyKind, String), Histogram>>,
+    clock_samples: Mutex<VecDeque<ClockSample>>,
+    offset_ms: AtomicI64,
+}
+
+impl Latency {
+    pub fn new() -> Latency {
+        Latency::default()
+    }
+
+    pub fn record(&self, kind: LatencyKind, method: &str, latency: Duration) {
+        self.histograms.lock().unwrap()
+            .entry((kind, method.to_string()))
+            .or_default()
+            .record(latency);
+    }
+
+    pub fn histograms(&self) -> Vec<(LatencyKind, String, Histogram)> {
+        self.histograms.lock().unwrap().iter()
+            .map(|((kind, method), histogram)| (*kind, method.clone(), histogram.clone()))
+            .collect()
+    }
+
+    // local times in milliseconds around a public/get_time request
+    pub fn record_clock(&self, sent_at: i64, received_at: i64, exchange_time: i64) -> ClockSample {
+        let sample = ClockSample {
+            offset_ms: exchange_time - (sent_at + received_at) / 2,
+            round_trip_ms: received_at - sent_at,
+        };
+
+        let mut samples = self.clock_samples.lock().unwrap();
+        samples.push_back(sample);
+        if samples.len() > CLOCK_SAMPLES {
+            samples.pop_front();
+        }
+
+        let best = samples.iter().min_by_key(|sample| sample.round_trip_ms).unwrap();
+        self.offset_ms.store(best.offset_ms, Ordering::SeqCst);
+
+        sample
+    }
+
+    pub fn clock_offset_ms(&self) -> i64 {
+        self.offset_ms.load(Ordering::SeqCst)
+    }
+
+    pub fn report(&self) {
+        info!("Exchange clock offset {}ms", self.clock_offset_ms());
+
+        for (kind, method, histogram) in self.histograms() {
+            info!("Latency {} {} over {} samples: p50 {:?}, p90 {:?}, p99 {:?}, max {:?}",
+                kind.name(), method, histogram.count(),
+                histogram.percentile(50), histogram.percentile(90), histogram.percentile(99), histogram.max());
+        }
+    }
+}
+
+pub fn report_periodically(latency: Arc<Latency>) {
+    thread::spawn(move || {
+        loop {
+            thread::sleep(REPORT_INTERVAL);
+            latency.report();
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn check_histogram_percentiles() {
+        let mut histogram = Histogram::new();
+
+        for micros in 1..=100 {
+            histogram.record(Duration::from_micros(micros * 10));
+        }
+        histogram.record(Duration::from_secs(60));
+
+        assert_eq!(histogram.count(), 101);
+        assert_eq!(histogram.percentile(9), Duration::from_micros(100));
+        assert_eq!(histogram.percentile(10), Duration::from_micros(250));
+        assert_eq!(histogram.percentile(49), Duration::from_micros(500));
+        assert_eq!(histogram.percentile(99), Duration::from_micros(1_000));
+        assert_eq!(histogram.percentile(100), Duration::from_secs(60));
+        assert_eq!(histogram.cumulative_buckets()[1], (100, 10));
+        assert_eq!(histogram.cumulative_buckets().last(), Some(&(30_000_000, 100)));
+    }
+
+    #[test]
+    fn check_clock_offset_from_shortest_round_trip() {
+        let latency = Latency::new();
+
+        assert_eq!(latency.record_clock(1_000, 1_100, 1_250), ClockSample { offset_ms: 200, round_trip_ms: 100 });
+        assert_eq!(latency.clock_offset_ms(), 200);
+
+        latency.record_clock(2_000, 2_010, 2_215);
+        assert_eq!(latency.clock_offset_ms(), 210);
+
+        // a slow round trip doesn't replace a better estimate
+        latency.record_clock(3_000, 3_500, 3_100);
+        assert_eq!(latency.clock_offset_ms(), 210);
+    }
+}
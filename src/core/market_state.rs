@@ -0,0 +1,126 @@
+use std::collections::HashMap;
+use std::sync::{Arc, RwLock};
+use std::thread;
+use rust_decimal::Decimal;
+use crate::core::domain::{Funding, IndexPrice, Instrument, MarkPrice, Ticker};
+use crate::core::event_bus::{Event, EventReader};
+
+// Latest market data per instrument or index, filled from the event bus and read by
+// strategies and risk checks whenever they need it.
+#[derive(Default)]
+pub struct MarketState {
+    tickers: RwLock<HashMap<String, Ticker>>,
+    // instrument -> mark price, from tickers and option mark prices
+    mark_prices: RwLock<HashMap<String, MarkPrice>>,
+    index_prices: RwLock<HashMap<String, IndexPrice>>,
+    funding: RwLock<HashMap<String, Funding>>,
+    instruments: RwLock<HashMap<String, Instrument>>,
+}
+
+impl MarketState {
+    pub fn new() -> MarketState {
+        MarketState::default()
+    }
+
+    pub fn apply(&self, event: &Event) {
+        match event {
+            Event::Ticker(ticker) => {
+                let mark_price = MarkPrice {
+                    timestamp: ticker.timestamp,
+                    instrument_name: ticker.instrument_name.clone(),
+                    mark_price: ticker.mark_price,
+                    iv: None,
+                };
+                self.mark_prices.write().unwrap().insert(ticker.instrument_name.clone(), mark_price);
+                self.tickers.write().unwrap().insert(ticker.instrument_name.clone(), ticker.clone());
+            }
+            Event::MarkPrice(mark_price) => {
+                self.mark_prices.write().unwrap().insert(mark_price.instrument_name.clone(), mark_price.clone());
+            }
+            Event::IndexPrice(index_price) => {
+                self.index_prices.write().unwrap().insert(index_price.index_name.clone(), index_price.clone());
+            }
+            Event::Funding(funding) => {
+                self.funding.write().unwrap().insert(funding.instrument_name.clone(), funding.clone());
+            }
+            Event::Instruments(instruments) => {
+                let mut known = self.instruments.write().unwrap();
+                for instrument in instruments {
+                    known.insert(instrument.name.clone(), instrument.clone());
+                }
+            }
+            _ => (),
+        }
+    }
+
+    pub fn instruments(&self) -> Vec<Instrument> {
+        self.instruments.read().unwrap().values().cloned().collect()
+    }
+
+    pub fn tickers(&self) -> Vec<Ticker> {
+        self.tickers.read().unwrap().values().cloned().collect()
+    }
+
+    pub fn mark_price(&self, instrument: &str) -> Option<Decimal> {
+        self.mark_prices.read().unwrap().get(instrument).map(|mark_price| mark_price.mark_price)
+    }
+
+    pub fn index_price(&self, index_name: &str) -> Option<Decimal> {
+        self.index_prices.read().unwrap().get(index_name).map(|index_price| index_price.price)
+    }
+
+    pub fn funding(&self, instrument: &str) -> Option<Funding> {
+        self.funding.read().unwrap().get(instrument).cloned()
+    }
+
+    pub fn open_interest(&self, instrument: &str) -> Option<Decimal> {
+        self.tickers.read().unwrap().get(instrument).map(|ticker| ticker.open_interest)
+    }
+}
+
+pub fn track(state: Arc<MarketState>, events: EventReader) {
+    thread::spawn(move || {
+        for event in events {
+            state.apply(&event);
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn check_latest_values() {
+        let state = MarketState::new();
+
+        state.apply(&Event::IndexPrice(IndexPrice { timestamp: 1, index_name: "btc_usd".to_string(), price: Decimal::from(20000) }));
+        state.apply(&Event::IndexPrice(IndexPrice { timestamp: 2, index_name: "btc_usd".to_string(), price: Decimal::from(20010) }));
+        state.apply(&Event::MarkPrice(MarkPrice { timestamp: 2, instrument_name: "BTC-2JUN23-30000-C".to_string(), mark_price: Decimal::new(5, 2), iv: Some(Decimal::new(6, 1)) }));
+        state.apply(&Event::Funding(Funding { timestamp: 2, instrument_name: "BTC-PERPETUAL".to_string(), interest: Decimal::new(1, 5), index_price: Decimal::from(20010) }));
+
+        assert_eq!(state.index_price("btc_usd"), Some(Decimal::from(20010)));
+        assert_eq!(state.index_price("eth_usd"), None);
+        assert_eq!(state.mark_price("BTC-2JUN23-30000-C"), Some(Decimal::new(5, 2)));
+        assert_eq!(state.funding("BTC-PERPETUAL").unwrap().interest, Decimal::new(1, 5));
+        assert_eq!(state.mark_price("BTC-PERPETUAL"), None);
+
+        state.apply(&Event::Ticker(Ticker {
+            timestamp: 3,
+            instrument_name: "BTC-PERPETUAL".to_string(),
+            best_bid_price: Some(Decimal::from(20005)),
+            best_bid_amount: Decimal::from(100),
+            best_ask_price: Some(Decimal::from(20006)),
+            best_ask_amount: Decimal::from(100),
+            last_price: None,
+            mark_price: Decimal::from(20004),
+            index_price: Decimal::from(20010),
+            open_interest: Decimal::from(5000000),
+            current_funding: Some(Decimal::ZERO),
+            funding_8h: Some(Decimal::ZERO),
+        }));
+
+        assert_eq!(state.mark_price("BTC-PERPETUAL"), Some(Decimal::from(20004)));
+        assert_eq!(state.open_interest("BTC-PERPETUAL"), Some(Decimal::from(5000000)));
+    }
+}
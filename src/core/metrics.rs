@@ -0,0 +1,137 @@
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::sync::Mutex;
+
+use crate::core::latency::Histogram;
+
+type Labels = Vec<(String, String)>;
+
+// Counters and gauges by name and labels, rendered in the prometheus text format.
+// Values which are already kept elsewhere (latency, account, bus lag) are read at
+// scrape time by the metrics server instead of being copied in here.
+#[derive(Default)]
+pub struct Metrics {
+    counters: Mutex<BTreeMap<String, BTreeMap<Labels, u64>>>,
+    gauges: Mutex<BTreeMap<String, BTreeMap<Labels, f64>>>,
+}
+
+fn labels(pairs: &[(&str, &str)]) -> Labels {
+    pairs.iter().map(|(name, value)| (name.to_string(), value.to_string())).collect()
+}
+
+impl Metrics {
+    pub fn new() -> Metrics {
+        Metrics::default()
+    }
+
+    pub fn inc(&self, name: &str, pairs: &[(&str, &str)]) {
+        self.add(name, pairs, 1);
+    }
+
+    pub fn add(&self, name: &str, pairs: &[(&str, &str)], value: u64) {
+        *self.counters.lock().unwrap()
+            .entry(name.to_string()).or_default()
+            .entry(labels(pairs)).or_default() += value;
+    }
+
+    pub fn set(&self, name: &str, pairs: &[(&str, &str)], value: f64) {
+        self.gauges.lock().unwrap()
+            .entry(name.to_string()).or_default()
+            .insert(labels(pairs), value);
+    }
+
+    #[cfg(test)]
+    pub fn counter(&self, name: &str, pairs: &[(&str, &str)]) -> u64 {
+        self.counters.lock().unwrap().get(name)
+            .and_then(|series| series.get(&labels(pairs)).cloned())
+            .unwrap_or(0)
+    }
+
+    pub fn gauge(&self, name: &str, pairs: &[(&str, &str)]) -> Option<f64> {
+        self.gauges.lock().unwrap().get(name)
+            .and_then(|series| series.get(&labels(pairs)).cloned())
+    }
+
+    pub fn render(&self, out: &mut String) {
+        for (name, series) in self.counters.lock().unwrap().iter() {
+            writeln!(out, "# TYPE {} counter", name).unwrap();
+            for (labels, value) in series {
+                writeln!(out, "{}{} {}", name, format_labels(labels), value).unwrap();
+            }
+        }
+
+        for (name, series) in self.gauges.lock().unwrap().iter() {
+            render_gauge(out, name, series.iter().map(|(labels, value)| (labels.clone(), *value)).collect());
+        }
+    }
+}
+
+pub fn render_gauge(out: &mut String, name: &str, series: Vec<(Labels, f64)>) {
+    writeln!(out, "# TYPE {} gauge", name).unwrap();
+    for (labels, value) in series {
+        writeln!(out, "{}{} {}", name, format_labels(&labels), value).unwrap();
+    }
+}
+
+// latency histograms in seconds, the usual prometheus unit
+pub fn render_histograms(out: &mut String, name: &str, series: Vec<(Labels, Histogram)>) {
+    writeln!(out, "# TYPE {} histogram", name).unwrap();
+
+    for (labels, histogram) in series {
+        for (bound, count) in histogram.cumulative_buckets() {
+            let mut bucket_labels = labels.clone();
+            bucket_labels.push(("le".to_string(), (bound as f64 / 1_000_000.0).to_string()));
+            writeln!(out, "{}_bucket{} {}", name, format_labels(&bucket_labels), count).unwrap();
+        }
+
+        let mut bucket_labels = labels.clone();
+        bucket_labels.push(("le".to_string(), "+Inf".to_string()));
+        writeln!(out, "{}_bucket{} {}", name, format_labels(&bucket_labels), histogram.count()).unwrap();
+        writeln!(out, "{}_sum{} {}", name, format_labels(&labels), histogram.sum().as_secs_f64()).unwrap();
+        writeln!(out, "{}_count{} {}", name, format_labels(&labels), histogram.count()).unwrap();
+    }
+}
+
+fn format_labels(labels: &Labels) -> String {
+    if labels.is_empty() {
+        return String::new();
+    }
+
+    let pairs: Vec<String> = labels.iter()
+        .map(|(name, value)| format!("{}=\"{}\"", name, value.replace('\\', "\\\\").replace('"', "\\\"")))
+        .collect();
+
+    format!("{{{}}}", pairs.join(","))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+    use super::*;
+
+    #[test]
+    fn check_text_format() {
+        let metrics = Metrics::new();
+
+        metrics.inc("ct_messages_total", &[("channel", "book.BTC-PERPETUAL.raw")]);
+        metrics.add("ct_messages_total", &[("channel", "book.BTC-PERPETUAL.raw")], 2);
+        metrics.inc("ct_reconnects_total", &[]);
+        metrics.set("ct_position", &[("instrument", "BTC-PERPETUAL")], -20.0);
+
+        let mut histogram = Histogram::new();
+        histogram.record(Duration::from_micros(80));
+
+        let mut out = String::new();
+        metrics.render(&mut out);
+        render_histograms(&mut out, "ct_latency_seconds", vec!((labels(&[("kind", "queue")]), histogram)));
+
+        assert_eq!(metrics.counter("ct_messages_total", &[("channel", "book.BTC-PERPETUAL.raw")]), 3);
+        assert!(out.contains("# TYPE ct_messages_total counter\nct_messages_total{channel=\"book.BTC-PERPETUAL.raw\"} 3\n"));
+        assert!(out.contains("ct_reconnects_total 1\n"));
+        assert!(out.contains("# TYPE ct_position gauge\nct_position{instrument=\"BTC-PERPETUAL\"} -20\n"));
+        assert!(out.contains("ct_latency_seconds_bucket{kind=\"queue\",le=\"0.00005\"} 0\n"));
+        assert!(out.contains("ct_latency_seconds_bucket{kind=\"queue\",le=\"0.0001\"} 1\n"));
+        assert!(out.contains("ct_latency_seconds_bucket{kind=\"queue\",le=\"+Inf\"} 1\n"));
+        assert!(out.contains("ct_latency_seconds_count{kind=\"queue\"} 1\n"));
+    }
+}
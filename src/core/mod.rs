@@ -1 +1,9 @@
-pub mod entities;
\ No newline at end of file
+pub mod entities;
+pub mod event_bus;
+pub mod domain;
+pub mod market_state;
+pub mod account;
+pub mod latency;
+pub mod metrics;
+pub mod shutdown;
+pub mod supervisor;
@@ -0,0 +1,164 @@
+use std::process;
+use std::sync::{Arc, Condvar, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use log::{error, info};
+use signal_hook::consts::{SIGINT, SIGTERM};
+use signal_hook::iterator::Signals;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Phase {
+    Running,
+    // strategies stop quoting, open orders are cancelled
+    Stopping,
+    // the connector writes what is queued and closes the socket
+    Closing,
+}
+
+#[derive(Debug)]
+struct State {
+    phase: Phase,
+    reason: Option<String>,
+    failed: bool,
+}
+
+// Coordinates the shutdown sequence. A signal or an escalated component failure
+// requests it, main waits for the request, cancels the orders and then closes
+// the connections. Long running loops check the phase they care about.
+pub struct Shutdown {
+    state: Mutex<State>,
+    changed: Condvar,
+}
+
+impl Shutdown {
+    pub fn new() -> Shutdown {
+        Shutdown {
+            state: Mutex::new(State { phase: Phase::Running, reason: None, failed: false }),
+            changed: Condvar::new(),
+        }
+    }
+
+    // the first reason wins, later requests only add the failure flag
+    pub fn request(&self, reason: &str) {
+        self.advance(Phase::Stopping, reason, false);
+    }
+
+    // unrecoverable failure, the process exits with a nonzero code
+    pub fn fail(&self, reason: &str) {
+        self.advance(Phase::Stopping, reason, true);
+    }
+
+    pub fn close(&self) {
+        self.advance(Phase::Closing, "closing", false);
+    }
+
+    fn advance(&self, phase: Phase, reason: &str, failed: bool) {
+        let mut state = self.state.lock().unwrap();
+
+        if state.reason.is_none() {
+            info!("Shutdown requested: {}", reason);
+            state.reason = Some(reason.to_string());
+        }
+        state.phase = state.phase.max(phase);
+        state.failed |= failed;
+
+        self.changed.notify_all();
+    }
+
+    pub fn phase(&self) -> Phase {
+        self.state.lock().unwrap().phase
+    }
+
+    pub fn is_requested(&self) -> bool {
+        self.phase() >= Phase::Stopping
+    }
+
+    pub fn is_closing(&self) -> bool {
+        self.phase() == Phase::Closing
+    }
+
+    pub fn failed(&self) -> bool {
+        self.state.lock().unwrap().failed
+    }
+
+    // blocks until shutdown is requested, returns the reason
+    pub fn wait(&self) -> String {
+        let state = self.changed.wait_while(self.state.lock().unwrap(), |state| state.phase == Phase::Running).unwrap();
+
+        state.reason.clone().unwrap_or_default()
+    }
+
+    // sleep for periodic loops, false once the connections are closing
+    pub fn sleep(&self, duration: Duration) -> bool {
+        let (state, _) = self.changed.wait_timeout_while(self.state.lock().unwrap(), duration, |state| state.phase != Phase::Closing).unwrap();
+
+        state.phase != Phase::Closing
+    }
+}
+
+impl Default for Shutdown {
+    fn default() -> Shutdown {
+        Shutdown::new()
+    }
+}
+
+// polls the condition until it holds or the timeout passes
+pub fn wait_until(timeout: Duration, condition: impl Fn() -> bool) -> bool {
+    let deadline = Instant::now() + timeout;
+
+    while !condition() {
+        if Instant::now() >= deadline {
+            return false;
+        }
+        thread::sleep(Duration::from_millis(50));
+    }
+
+    true
+}
+
+// SIGINT or SIGTERM starts the shutdown, a second one exits right away
+pub fn listen_signals(shutdown: Arc<Shutdown>) {
+    let mut signals = Signals::new([SIGINT, SIGTERM]).expect("Can't register signal handler");
+
+    thread::spawn(move || {
+        for signal in signals.forever() {
+            info!("Got signal {}", signal);
+
+            if shutdown.is_requested() {
+                error!(target: "alerts", "Got signal {} again during shutdown, exiting now", signal);
+                log::logger().flush();
+                process::exit(1);
+            }
+
+            shutdown.request(if signal == SIGINT { "SIGINT" } else { "SIGTERM" });
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn check_phases() {
+        let shutdown = Arc::new(Shutdown::new());
+        assert_eq!(shutdown.phase(), Phase::Running);
+        assert!(shutdown.sleep(Duration::from_millis(1)));
+
+        let waiting = Arc::clone(&shutdown);
+        let waiter = thread::spawn(move || waiting.wait());
+
+        shutdown.request("SIGTERM");
+        shutdown.fail("strategy failed");
+        assert_eq!(waiter.join().unwrap(), "SIGTERM");
+        assert!(shutdown.is_requested());
+        assert!(shutdown.failed());
+        assert!(!shutdown.is_closing());
+
+        shutdown.close();
+        shutdown.request("again");
+        assert_eq!(shutdown.phase(), Phase::Closing);
+        assert!(!shutdown.sleep(Duration::from_secs(60)));
+    }
+}
@@ -0,0 +1,152 @@
+use std::any::Any;
+use std::collections::VecDeque;
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use log::{error, warn};
+
+use crate::config::{RestartPolicy, SupervisorConfig};
+use crate::core::metrics::Metrics;
+use crate::core::shutdown::Shutdown;
+
+// restarts inside the window, older ones are forgotten
+struct Restarts {
+    at: VecDeque<Instant>,
+    max: usize,
+    window: Duration,
+}
+
+impl Restarts {
+    fn new(max: usize, window: Duration) -> Restarts {
+        Restarts { at: VecDeque::new(), max, window }
+    }
+
+    fn allow(&mut self, now: Instant) -> bool {
+        while self.at.front().is_some_and(|at| now.duration_since(*at) >= self.window) {
+            self.at.pop_front();
+        }
+
+        if self.at.len() >= self.max {
+            return false;
+        }
+
+        self.at.push_back(now);
+        true
+    }
+}
+
+fn panic_message(payload: &(dyn Any + Send)) -> String {
+    payload.downcast_ref::<&str>().map(|message| message.to_string())
+        .or_else(|| payload.downcast_ref::<String>().cloned())
+        .unwrap_or_else(|| "unknown panic".to_string())
+}
+
+// Runs the long living loops of the connector, the order manager and the strategy.
+// A component which panics or returns while the bot is running is restarted, unless
+// its policy says to escalate or it keeps failing, then the whole bot shuts down
+// with a failure.
+pub struct Supervisor {
+    shutdown: Arc<Shutdown>,
+    metrics: Arc<Metrics>,
+    config: SupervisorConfig,
+}
+
+impl Supervisor {
+    pub fn new(shutdown: Arc<Shutdown>, metrics: Arc<Metrics>, config: SupervisorConfig) -> Supervisor {
+        Supervisor { shutdown, metrics, config }
+    }
+
+    pub fn shutdown(&self) -> &Shutdown {
+        &self.shutdown
+    }
+
+    pub fn policy(&self, name: &str) -> RestartPolicy {
+        self.config.policies.get(name).cloned().unwrap_or_default()
+    }
+
+    // runs the component in the current thread, returns once it is done for good
+    pub fn run(&self, name: &str, mut component: impl FnMut()) {
+        let mut restarts = Restarts::new(self.config.max_restarts, Duration::from_secs(self.config.restart_window_secs));
+
+        loop {
+            let outcome = panic::catch_unwind(AssertUnwindSafe(&mut component));
+
+            let failure = match outcome {
+                Ok(()) if self.shutdown.is_requested() => return,
+                Ok(()) => "stopped".to_string(),
+                Err(payload) => format!("panicked: {}", panic_message(&*payload)),
+            };
+
+            error!(target: "alerts", "Component {} {}", name, failure);
+            self.metrics.inc("ct_component_failures_total", &[("component", name)]);
+
+            if self.shutdown.is_requested() {
+                return;
+            }
+
+            if self.policy(name) == RestartPolicy::Escalate {
+                self.shutdown.fail(&format!("{} {}", name, failure));
+                return;
+            }
+
+            if !restarts.allow(Instant::now()) {
+                self.shutdown.fail(&format!("{} {}, {} restarts in {}s", name, failure, self.config.max_restarts, self.config.restart_window_secs));
+                return;
+            }
+
+            warn!("Restarting {}", name);
+            thread::sleep(Duration::from_millis(self.config.restart_delay_ms));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+    use super::*;
+
+    fn new_supervisor(policies: HashMap<String, RestartPolicy>) -> Supervisor {
+        let config = SupervisorConfig { max_restarts: 2, restart_window_secs: 60, restart_delay_ms: 0, policies };
+
+        Supervisor::new(Arc::new(Shutdown::new()), Arc::new(Metrics::new()), config)
+    }
+
+    #[test]
+    fn check_restart_then_escalate() {
+        let supervisor = new_supervisor(HashMap::new());
+        let mut runs = 0;
+
+        supervisor.run("strategy", || {
+            runs += 1;
+            panic!("no book");
+        });
+
+        assert_eq!(runs, 3);
+        assert!(supervisor.shutdown().failed());
+        assert_eq!(supervisor.metrics.counter("ct_component_failures_total", &[("component", "strategy")]), 3);
+
+        let mut restarts = Restarts::new(1, Duration::from_secs(10));
+        let start = Instant::now();
+        assert!(restarts.allow(start));
+        assert!(!restarts.allow(start + Duration::from_secs(5)));
+        assert!(restarts.allow(start + Duration::from_secs(10)));
+    }
+
+    #[test]
+    fn check_escalate_policy_and_shutdown() {
+        let supervisor = new_supervisor(HashMap::from([("order signals".to_string(), RestartPolicy::Escalate)]));
+
+        let mut runs = 0;
+        supervisor.run("order signals", || runs += 1);
+        assert_eq!(runs, 1);
+        assert!(supervisor.shutdown().failed());
+
+        // returning during shutdown is the normal way out
+        let supervisor = new_supervisor(HashMap::new());
+        supervisor.shutdown().request("SIGTERM");
+        supervisor.run("strategy", || ());
+        assert!(!supervisor.shutdown().failed());
+    }
+}
@@ -2,55 +2,189 @@ mod orderbook;
 mod strategy;
 mod core;
 mod connectors;
+mod config;
+mod server;
+mod monitor;
 
 
 use url::Url;
 use tungstenite::{connect, Message};
 
+use std::sync::Arc;
 use std::thread;
+use std::time::Duration;
 
 use log::{error, info, warn};
 use log4rs;
 
 use crossbeam_channel::{bounded, Sender};
 use crate::connectors::deribit::ws_connector::DeribitConnector;
-
-use crate::strategy::risk;
+use crate::core::event_bus::EventBus;
+use crate::core::market_state::{self, MarketState};
+use crate::core::account::{self, Accounts};
+use crate::core::latency::{self, Latency};
+use crate::core::metrics::Metrics;
+use crate::core::shutdown::{self, Shutdown};
+use crate::core::supervisor::Supervisor;
+use crate::core::entities::Command;
+use crate::server::metrics::{self as metrics_server, MetricsServer};
+use crate::server::admin::{self, Admin, AdminHandles};
+use crate::strategy::control::StrategyControl;
+use crate::connectors::deribit::async_connector::AsyncDeribitConnector;
+use crate::config::ConnectorRuntime;
+
+use crate::strategy::risk::{self, RiskManager};
+use crate::strategy::kill_switch::{self, KillSwitch};
+use crate::strategy::mmp::{self, MmpGuard};
+use crate::strategy::margin_guard::{self, MarginGuard};
+use crate::strategy::stale_data::{self, StaleDataGuard};
 
 
 fn main() {
+    // `ct monitor` only talks to the admin API of a running bot
+    if std::env::args().nth(1).as_deref() == Some("monitor") {
+        let config = config::Config::load("config/ct.yaml");
+        if let Err(e) = monitor::run(&config) {
+            eprintln!("Monitor failed: {}", e);
+            std::process::exit(1);
+        }
+        return;
+    }
+
     log4rs::init_file("config/log4rs.yaml", Default::default()).unwrap();
 
     info!("Starting bot...");
 
-    let (orderbook_sender, orderbook_receiver) = bounded(10);
-    let (order_sender, order_receiver) = bounded(10);
-    let (raw_data_sender, raw_data_receiver) = bounded(10);
+    let config = config::Config::load("config/ct.yaml");
+
+    let mut event_bus = EventBus::builder(config.event_bus.capacity, Duration::from_millis(config.event_bus.reader_timeout_ms));
+    let strategy_events = event_bus.subscribe("strategy");
+    let order_events = event_bus.subscribe("order manager");
+    let portfolio_events = event_bus.subscribe("portfolio");
+    let market_state_events = event_bus.subscribe("market state");
+    let account_events = event_bus.subscribe("account");
+    let margin_events = event_bus.subscribe("margin guard");
+    let risk_events = event_bus.subscribe("risk");
+    let event_bus = event_bus.build();
+
+    let market_state = Arc::new(MarketState::new());
+    market_state::track(Arc::clone(&market_state), market_state_events);
+
+    let accounts = Arc::new(Accounts::new());
+    account::track(Arc::clone(&accounts), account_events);
+
+    let latency = Arc::new(Latency::new());
+    latency::report_periodically(Arc::clone(&latency));
+
+    let metrics = Arc::new(Metrics::new());
+
+    let shutdown = Arc::new(Shutdown::new());
+    shutdown::listen_signals(Arc::clone(&shutdown));
+
+    let supervisor = Arc::new(Supervisor::new(Arc::clone(&shutdown), Arc::clone(&metrics), config.supervisor.clone()));
+
     let (signal_sender, signal_receiver) = bounded(10);
     let (command_sender, command_receiver) = bounded(10);
-    let (portfolio_sender, portfolio_receiver) = bounded(10);
 
 
     let command_sender_2 = command_sender.clone();
 
+    let kill_switch = Arc::new(KillSwitch::new(command_sender.clone(), config.kill_switch.flatten_positions, "BTC".to_string()));
+    kill_switch::listen_signals(Arc::clone(&kill_switch));
+
+    let kill_switch_1 = Arc::clone(&kill_switch);
+    let kill_switch_2 = Arc::clone(&kill_switch);
+
+    let mmp_guard = Arc::new(MmpGuard::new(command_sender.clone(), config.mmp.index_name.clone(), Duration::from_secs(config.mmp.cool_down)));
+    mmp::watch_cool_down(Arc::clone(&mmp_guard));
+
+    let mmp_guard_1 = Arc::clone(&mmp_guard);
+
+    let margin_guard = Arc::new(MarginGuard::new(command_sender.clone(), Arc::clone(&kill_switch), config.margin_guard.clone()));
+    margin_guard::watch(Arc::clone(&margin_guard), margin_events);
+
+    let risk_manager = Arc::new(RiskManager::new(command_sender.clone(), config.risk.clone()));
+    risk::watch(Arc::clone(&risk_manager), risk_events);
+    risk::listen_signals(Arc::clone(&risk_manager));
+
+    let stale_data_guard = Arc::new(StaleDataGuard::new(command_sender.clone(), &config.stale_data, Arc::clone(&latency)));
+    stale_data::watch(Arc::clone(&stale_data_guard));
+
+    let control = Arc::new(StrategyControl::new(config.strategy.clone(), config.admin.max_quote_depth));
+
+    let connector_config = config.clone();
+    let connector_latency = Arc::clone(&latency);
+    let connector_metrics = Arc::clone(&metrics);
+
+    if config.metrics.enabled {
+        let server = MetricsServer::new(Arc::clone(&metrics), Arc::clone(&latency), Arc::clone(&accounts), event_bus.clone(), command_sender.clone());
+        metrics_server::serve(server, &config.metrics.address);
+    }
+
+    let manager = strategy::order_manager::Manager::new(signal_receiver, order_events, portfolio_events, command_sender.clone(), config.reconciliation.orphan_orders, kill_switch_1, Arc::clone(&mmp_guard), Arc::clone(&latency), metrics.clone(), Arc::clone(&control), Arc::clone(&supervisor));
+    let active_orders = manager.active_orders();
+
+    if config.admin.enabled {
+        let token = std::env::var(&config.admin.token_env)
+            .unwrap_or_else(|_| panic!("Admin API needs a token in {}", config.admin.token_env));
+
+        let handles = AdminHandles {
+            control: Arc::clone(&control),
+            kill_switch: Arc::clone(&kill_switch),
+            risk: Arc::clone(&risk_manager),
+            margin_guard: Arc::clone(&margin_guard),
+            mmp: Arc::clone(&mmp_guard),
+            stale_data: Arc::clone(&stale_data_guard),
+            market_state: Arc::clone(&market_state),
+            accounts: Arc::clone(&accounts),
+            metrics: Arc::clone(&metrics),
+            latency: Arc::clone(&latency),
+            events: event_bus.clone(),
+            command_sender: command_sender.clone(),
+            active_orders: manager.active_orders(),
+            positions: manager.positions(),
+        };
+        admin::serve(Arc::new(Admin::new(handles, token)), &config.admin.address, &config.admin.ws_address);
+    }
+
+    let connector_supervisor = Arc::clone(&supervisor);
     let connector_handle = thread::spawn(move || {
-        let r = DeribitConnector::new(orderbook_sender, order_sender, raw_data_sender, command_receiver,  portfolio_sender, command_sender_2);
-        r.run();
+        let runtime = connector_config.runtime;
+        let r = DeribitConnector::new(event_bus, command_receiver, command_sender_2, kill_switch, connector_latency, connector_metrics, connector_supervisor, connector_config);
+        match runtime {
+            ConnectorRuntime::Threaded => r.run(),
+            ConnectorRuntime::Tokio => AsyncDeribitConnector::new(r).run(),
+        }
     });
 
-    let manager_handle = thread::spawn(move || {
-        let manager = strategy::order_manager::Manager::new(signal_receiver, order_receiver, portfolio_receiver, command_sender);
-        manager.run();
-    });
+    // the manager runs its loops on threads of its own
+    manager.run();
 
-    let strategy_handle = thread::spawn(move || {
-        let strategy = strategy::mm::MarketMaker::new(orderbook_receiver, signal_sender);
-        strategy.run();
+    let strategy_supervisor = Arc::clone(&supervisor);
+    let strategy_shutdown = Arc::clone(&shutdown);
+    let strategy_commands = command_sender.clone();
+    thread::spawn(move || {
+        let mut strategy = strategy::mm::MarketMaker::new(strategy_events, signal_sender, strategy_commands, kill_switch_2, mmp_guard_1, market_state, margin_guard, risk_manager, stale_data_guard, control, strategy_shutdown);
+        strategy_supervisor.run("strategy", || strategy.run());
     });
 
-
-    strategy_handle.join();
-    manager_handle.join();
-    connector_handle.join();
-
-}
\ No newline at end of file
+    let reason = shutdown.wait();
+    info!("Shutting down: {}", reason);
+
+    // the strategy and the manager stop quoting, the connector refuses new orders
+    command_sender.send(Command::CancelAll).unwrap();
+    let cancelled = shutdown::wait_until(Duration::from_millis(config.shutdown.cancel_timeout_ms), || active_orders.lock().unwrap().is_empty());
+    if !cancelled {
+        error!(target: "alerts", "Orders still open after cancel all: {:?}", active_orders.lock().unwrap().keys().collect::<Vec<_>>());
+    }
+
+    shutdown.close();
+    if !shutdown::wait_until(Duration::from_millis(config.shutdown.close_timeout_ms), || connector_handle.is_finished()) {
+        warn!("Connector didn't stop in {}ms", config.shutdown.close_timeout_ms);
+    }
+
+    let code = if shutdown.failed() || !cancelled { 1 } else { 0 };
+    info!("Stopped with exit code {}", code);
+    log::logger().flush();
+    std::process::exit(code);
+}
@@ -0,0 +1,66 @@
+use std::io::{Read, Write};
+use std::net::TcpStream;
+use std::time::Duration;
+
+use serde_json::Value;
+
+const TIMEOUT: Duration = Duration::from_secs(2);
+
+// Talks to the admin API of the running bot
+pub struct AdminClient {
+    address: String,
+    token: String,
+}
+
+impl AdminClient {
+    pub fn new(address: String, token: String) -> AdminClient {
+        AdminClient { address, token }
+    }
+
+    pub fn status(&self) -> Result<Value, String> {
+        self.send("GET", "/status")
+    }
+
+    pub fn post(&self, path: &str) -> Result<Value, String> {
+        self.send("POST", path)
+    }
+
+    fn send(&self, method: &str, path: &str) -> Result<Value, String> {
+        let mut stream = TcpStream::connect(&self.address).map_err(|e| format!("can't connect to {}: {}", self.address, e))?;
+        stream.set_read_timeout(Some(TIMEOUT)).map_err(|e| e.to_string())?;
+
+        write!(stream, "{} {} HTTP/1.1\r\nHost: {}\r\nAuthorization: Bearer {}\r\nContent-Length: 0\r\n\r\n",
+            method, path, self.address, self.token).map_err(|e| e.to_string())?;
+
+        // the server closes the connection after the response
+        let mut response = String::new();
+        stream.read_to_string(&mut response).map_err(|e| e.to_string())?;
+
+        parse_response(&response)
+    }
+}
+
+fn parse_response(response: &str) -> Result<Value, String> {
+    let (head, body) = response.split_once("\r\n\r\n").ok_or("malformed response")?;
+    let status = head.lines().next().unwrap_or_default();
+
+    if !status.contains(" 200 ") {
+        return Err(format!("{}: {}", status, body.trim()));
+    }
+
+    serde_json::from_str(body).map_err(|e| format!("bad status json: {}", e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn check_parse_response() {
+        let ok = "HTTP/1.1 200 OK\r\nContent-Type: application/json\r\nContent-Length: 15\r\n\r\n{\"paused\":true}";
+        assert_eq!(parse_response(ok).unwrap()["paused"], Value::Bool(true));
+
+        let refused = "HTTP/1.1 401 Unauthorized\r\nContent-Type: text/plain\r\nContent-Length: 13\r\n\r\nunauthorized\n";
+        assert_eq!(parse_response(refused), Err("HTTP/1.1 401 Unauthorized: unauthorized".to_string()));
+    }
+}
@@ -0,0 +1,139 @@
+pub mod client;
+pub mod ui;
+
+use std::fs::File;
+use std::io::{self, Read, Seek, SeekFrom};
+use std::time::{Duration, Instant};
+
+use crossterm::event::{self, Event, KeyCode, KeyEventKind};
+use serde_json::Value;
+
+use crate::config::Config;
+use crate::monitor::client::AdminClient;
+
+// What the dashboard shows, refreshed from the admin API and the log file
+pub struct MonitorState {
+    pub status: Option<Value>,
+    pub error: Option<String>,
+    // result of the last hotkey
+    pub message: Option<String>,
+    pub log_lines: Vec<String>,
+    pub refreshed_at: Option<Instant>,
+}
+
+// `ct monitor`: a terminal dashboard for the running bot, through its admin API
+pub fn run(config: &Config) -> io::Result<()> {
+    let token = std::env::var(&config.admin.token_env).unwrap_or_default();
+    let client = AdminClient::new(config.admin.address.clone(), token);
+    let refresh = Duration::from_millis(config.monitor.refresh_ms);
+
+    let mut state = MonitorState { status: None, error: None, message: None, log_lines: vec!(), refreshed_at: None };
+    let mut terminal = ratatui::init();
+
+    let result = loop {
+        if state.refreshed_at.is_none_or(|at| at.elapsed() >= refresh) {
+            match client.status() {
+                Ok(status) => {
+                    state.status = Some(status);
+                    state.error = None;
+                }
+                Err(e) => state.error = Some(e),
+            }
+            state.log_lines = tail(&config.monitor.log_file, config.monitor.log_lines);
+            state.refreshed_at = Some(Instant::now());
+        }
+
+        if let Err(e) = terminal.draw(|frame| ui::draw(frame, &state)) {
+            break Err(e);
+        }
+
+        match event::poll(Duration::from_millis(100)) {
+            Ok(true) => (),
+            Ok(false) => continue,
+            Err(e) => break Err(e),
+        }
+
+        let key = match event::read() {
+            Ok(Event::Key(key)) if key.kind == KeyEventKind::Press => key,
+            Ok(_) => continue,
+            Err(e) => break Err(e),
+        };
+
+        let action = match key.code {
+            KeyCode::Char('q') | KeyCode::Esc => break Ok(()),
+            KeyCode::Char('p') => {
+                let paused = state.status.as_ref().is_some_and(|status| status["paused"] == Value::Bool(true));
+                Some(if paused { "/strategy/resume" } else { "/strategy/pause" })
+            }
+            KeyCode::Char('c') => Some("/orders/cancel_all"),
+            _ => None,
+        };
+
+        if let Some(path) = action {
+            state.message = Some(match client.post(path) {
+                Ok(result) => format!("{}: {}", path, result),
+                Err(e) => format!("{} failed: {}", path, e),
+            });
+            state.refreshed_at = None;
+        }
+    };
+
+    ratatui::restore();
+    result
+}
+
+// the log is read backwards in blocks until there are enough lines, the refresh
+// doesn't go through the whole file
+const TAIL_BLOCK: u64 = 64 * 1024;
+
+fn tail(path: &str, lines: usize) -> Vec<String> {
+    match read_tail(path, lines) {
+        Ok(tail) => tail,
+        Err(e) => vec!(format!("can't read {}: {}", path, e)),
+    }
+}
+
+fn read_tail(path: &str, lines: usize) -> io::Result<Vec<String>> {
+    let mut file = File::open(path)?;
+    let mut start = file.metadata()?.len();
+    let mut buffer = vec!();
+
+    // one line more than asked for, the first one is usually cut by the block
+    while start > 0 && buffer.iter().filter(|byte| **byte == b'\n').count() <= lines {
+        let block = TAIL_BLOCK.min(start);
+        start -= block;
+
+        let mut chunk = vec![0; block as usize];
+        file.seek(SeekFrom::Start(start))?;
+        file.read_exact(&mut chunk)?;
+        chunk.extend_from_slice(&buffer);
+        buffer = chunk;
+    }
+
+    let content = String::from_utf8_lossy(&buffer);
+    let all: Vec<&str> = content.lines().skip(if start > 0 { 1 } else { 0 }).collect();
+
+    Ok(all[all.len().saturating_sub(lines)..].iter().map(|line| line.to_string()).collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use super::*;
+
+    #[test]
+    fn check_tail_reads_last_lines() {
+        let path = std::env::temp_dir().join(format!("ct-tail-{}.log", std::process::id()));
+        // longer than a block, the lines are cut at block boundaries
+        let content: String = (0..20_000).map(|i| format!("line {}\n", i)).collect();
+        fs::write(&path, content).unwrap();
+
+        let path = path.to_str().unwrap();
+        assert_eq!(tail(path, 3), vec!("line 19997", "line 19998", "line 19999"));
+        assert_eq!(tail(path, 20_001).len(), 20_000);
+        assert_eq!(tail(path, 20_001)[0], "line 0");
+
+        fs::remove_file(path).unwrap();
+        assert!(tail(path, 3)[0].starts_with("can't read"));
+    }
+}
@@ -0,0 +1,170 @@
+use ratatui::layout::{Constraint, Layout, Rect};
+use ratatui::style::{Color, Style, Stylize};
+use ratatui::text::{Line, Span};
+use ratatui::widgets::{Block, Paragraph, Row, Table};
+use ratatui::Frame;
+use serde_json::Value;
+
+use crate::monitor::MonitorState;
+
+// decimals come as strings, everything else as json numbers
+fn number(value: &Value) -> Option<f64> {
+    match value {
+        Value::Number(number) => number.as_f64(),
+        Value::String(text) => text.parse().ok(),
+        _ => None,
+    }
+}
+
+fn text(value: &Value) -> String {
+    match value {
+        Value::String(text) => text.clone(),
+        Value::Null => "-".to_string(),
+        other => other.to_string(),
+    }
+}
+
+fn items(value: &Value) -> &[Value] {
+    value.as_array().map(|items| items.as_slice()).unwrap_or(&[])
+}
+
+fn flag(name: &str, on: bool, bad: bool) -> Span<'static> {
+    let color = if on == bad { Color::Red } else { Color::Green };
+    Span::styled(format!(" {}: {} ", name, if on { "yes" } else { "no" }), Style::default().fg(color))
+}
+
+pub fn draw(frame: &mut Frame, state: &MonitorState) {
+    let [header, middle, bottom, logs, footer] = Layout::vertical([
+        Constraint::Length(3),
+        Constraint::Min(8),
+        Constraint::Min(8),
+        Constraint::Length(12),
+        Constraint::Length(1),
+    ]).areas(frame.area());
+
+    let null = Value::Null;
+    let status = state.status.as_ref().unwrap_or(&null);
+
+    draw_health(frame, header, status, state.error.as_deref());
+
+    let [books, quotes] = Layout::horizontal([Constraint::Percentage(45), Constraint::Percentage(55)]).areas(middle);
+    draw_books(frame, books, status);
+    draw_quotes(frame, quotes, status);
+
+    let [account, latency] = Layout::horizontal([Constraint::Percentage(45), Constraint::Percentage(55)]).areas(bottom);
+    draw_account(frame, account, status);
+    draw_latency(frame, latency, status);
+
+    let lines: Vec<Line> = state.log_lines.iter().map(|line| Line::from(line.as_str())).collect();
+    frame.render_widget(Paragraph::new(lines).block(Block::bordered().title("Log")), logs);
+
+    let help = format!(" p pause/resume  c cancel all  q quit   {}", state.message.as_deref().unwrap_or(""));
+    frame.render_widget(Paragraph::new(help).reversed(), footer);
+}
+
+fn draw_health(frame: &mut Frame, area: Rect, status: &Value, error: Option<&str>) {
+    let line = match error {
+        Some(error) => Line::from(Span::styled(format!(" admin API: {}", error), Style::default().fg(Color::Red))),
+        None => {
+            let max_lag = items(&status["event_bus_lag"]).iter().filter_map(|lag| lag[1].as_i64()).max().unwrap_or(0);
+
+            Line::from(vec!(
+                flag("connected", status["connected"] == Value::Bool(true), false),
+                flag("paused", status["paused"] == Value::Bool(true), true),
+                flag("kill switch", status["kill_switch"]["engaged"] == Value::Bool(true), true),
+                flag("breaker", !status["circuit_breaker"]["tripped"].is_null(), true),
+                flag("mmp frozen", status["mmp_frozen"] == Value::Bool(true), true),
+                flag("quotes pulled", status["quotes_pulled"] == Value::Bool(true), true),
+                Span::raw(format!(" margin: {}  clock offset: {}ms  bus lag: {}",
+                    text(&status["margin_tier"]), text(&status["clock_offset_ms"]), max_lag)),
+            ))
+        }
+    };
+
+    frame.render_widget(Paragraph::new(line).block(Block::bordered().title("ct monitor")), area);
+}
+
+fn draw_books(frame: &mut Frame, area: Rect, status: &Value) {
+    let rows = items(&status["books"]).iter().map(|book| Row::new(vec!(
+        text(&book["instrument"]),
+        text(&book["best_bid"]),
+        text(&book["best_ask"]),
+        text(&book["mark_price"]),
+    )));
+
+    let table = Table::new(rows, [Constraint::Fill(2), Constraint::Fill(1), Constraint::Fill(1), Constraint::Fill(1)])
+        .header(Row::new(vec!("instrument", "bid", "ask", "mark")).bold())
+        .block(Block::bordered().title("Top of book"));
+    frame.render_widget(table, area);
+}
+
+// how far each quote sits behind the best price on its side, 0 is at the top
+fn draw_quotes(frame: &mut Frame, area: Rect, status: &Value) {
+    let books = items(&status["books"]);
+
+    let rows = items(&status["orders"]).iter().map(|order| {
+        let book = books.iter().find(|book| book["instrument"] == order["instrument"]);
+        let price = number(&order["price"]);
+        let behind = match (text(&order["direction"]).as_str(), book, price) {
+            ("Bid", Some(book), Some(price)) => number(&book["best_bid"]).map(|best| best - price),
+            ("Ask", Some(book), Some(price)) => number(&book["best_ask"]).map(|best| price - best),
+            _ => None,
+        };
+
+        Row::new(vec!(
+            text(&order["instrument"]),
+            text(&order["direction"]),
+            text(&order["price"]),
+            format!("{}/{}", text(&order["filled_amount"]), text(&order["amount"])),
+            behind.map_or("-".to_string(), |behind| format!("{:.1}", behind)),
+        ))
+    });
+
+    let table = Table::new(rows, [Constraint::Fill(2), Constraint::Fill(1), Constraint::Fill(1), Constraint::Fill(1), Constraint::Fill(1)])
+        .header(Row::new(vec!("instrument", "side", "price", "filled", "behind best")).bold())
+        .block(Block::bordered().title("Our quotes"));
+    frame.render_widget(table, area);
+}
+
+fn draw_account(frame: &mut Frame, area: Rect, status: &Value) {
+    let mut lines: Vec<Line> = vec!();
+
+    if let Some(positions) = status["positions"].as_object() {
+        for (instrument, size) in positions {
+            lines.push(Line::from(format!("{} position {}", instrument, text(size))));
+        }
+    }
+
+    for account in items(&status["accounts"]) {
+        let session_pnl = number(&account["session_rpl"]).unwrap_or_default() + number(&account["session_upl"]).unwrap_or_default();
+        let margin_ratio = match (number(&account["maintenance_margin"]), number(&account["equity"])) {
+            (Some(margin), Some(equity)) if equity > 0.0 => format!("{:.1}%", margin / equity * 100.0),
+            _ => "-".to_string(),
+        };
+
+        lines.push(Line::from(format!("{} equity {}  available {}", text(&account["currency"]), text(&account["equity"]), text(&account["available_funds"]))));
+        lines.push(Line::from(format!("   session pnl {:.6}  total pnl {}  margin {}", session_pnl, text(&account["total_pl"]), margin_ratio)));
+        lines.push(Line::from(format!("   delta {}  gamma {}  vega {}  theta {}",
+            text(&account["delta_total"]), text(&account["options_gamma"]), text(&account["options_vega"]), text(&account["options_theta"]))));
+    }
+
+    frame.render_widget(Paragraph::new(lines).block(Block::bordered().title("Position and PnL")), area);
+}
+
+fn draw_latency(frame: &mut Frame, area: Rect, status: &Value) {
+    let micros = |value: &Value| value.as_u64().map_or("-".to_string(), |us| format!("{:.1}ms", us as f64 / 1000.0));
+
+    let rows = items(&status["latency"]).iter().map(|latency| Row::new(vec!(
+        text(&latency["kind"]),
+        text(&latency["method"]),
+        micros(&latency["p50_us"]),
+        micros(&latency["p90_us"]),
+        micros(&latency["p99_us"]),
+        text(&latency["count"]),
+    )));
+
+    let table = Table::new(rows, [Constraint::Fill(2), Constraint::Fill(3), Constraint::Fill(1), Constraint::Fill(1), Constraint::Fill(1), Constraint::Fill(1)])
+        .header(Row::new(vec!("kind", "method", "p50", "p90", "p99", "count")).bold())
+        .block(Block::bordered().title("Latency"));
+    frame.render_widget(table, area);
+}
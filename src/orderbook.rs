@@ -3,8 +3,8 @@
 use std::collections::btree_map::BTreeMap;
 use std::ops::Index;
 use rust_decimal::Decimal;
-use crate::core::entities::PriceLevelChange;
-use crate::core::entities::PriceLevelAction;
+use crate::core::domain::PriceLevelChange;
+use crate::core::domain::PriceLevelAction;
 
 // bid - buys
 // ask - sells
@@ -0,0 +1,345 @@
+use std::collections::HashMap;
+use std::net::{SocketAddr, TcpListener, TcpStream};
+use std::sync::{Arc, Mutex};
+
+use crossbeam_channel::Sender;
+use log::{info, warn};
+use rust_decimal::Decimal;
+use serde::Deserialize;
+use serde_json::{json, Value};
+use tungstenite::handshake::server::{ErrorResponse, Request as WsRequest, Response as WsResponse};
+use tungstenite::Message;
+
+use crate::core::account::Accounts;
+use crate::core::domain::Order;
+use crate::core::entities::Command;
+use crate::core::event_bus::EventBus;
+use crate::core::latency::Latency;
+use crate::core::market_state::MarketState;
+use crate::core::metrics::Metrics;
+use crate::server::http::{read_request, serve_connections, write_response, Request};
+use crate::strategy::control::{StrategyControl, StrategyParams};
+use crate::strategy::kill_switch::KillSwitch;
+use crate::strategy::margin_guard::MarginGuard;
+use crate::strategy::mmp::MmpGuard;
+use crate::strategy::risk::RiskManager;
+use crate::strategy::stale_data::StaleDataGuard;
+
+#[derive(Deserialize)]
+#[serde(tag = "command", rename_all = "snake_case")]
+#[derive(Debug, Clone)]
+pub enum AdminCommand {
+    Status,
+    Pause,
+    Resume,
+    SetParams(StrategyParams),
+    CancelAll,
+    KillSwitch { reason: Option<String> },
+    RearmKillSwitch,
+    ResetCircuitBreaker,
+}
+
+// everything the admin API reads or controls
+pub struct AdminHandles {
+    pub control: Arc<StrategyControl>,
+    pub kill_switch: Arc<KillSwitch>,
+    pub risk: Arc<RiskManager>,
+    pub margin_guard: Arc<MarginGuard>,
+    pub mmp: Arc<MmpGuard>,
+    pub stale_data: Arc<StaleDataGuard>,
+    pub market_state: Arc<MarketState>,
+    pub accounts: Arc<Accounts>,
+    pub metrics: Arc<Metrics>,
+    pub latency: Arc<Latency>,
+    pub events: EventBus,
+    pub command_sender: Sender<Command>,
+    pub active_orders: Arc<Mutex<HashMap<String, Order>>>,
+    pub positions: Arc<Mutex<HashMap<String, Decimal>>>,
+}
+
+// Status and control over HTTP and WebSocket on localhost. Every request needs the
+// bearer token and every command is written to the audit log, refused ones too.
+pub struct Admin {
+    handles: AdminHandles,
+    token: String,
+}
+
+impl Admin {
+    pub fn new(handles: AdminHandles, token: String) -> Admin {
+        Admin { handles, token }
+    }
+
+    pub fn execute(&self, command: AdminCommand, source: &str) -> Result<Value, String> {
+        let handles = &self.handles;
+
+        let result = match &command {
+            AdminCommand::Status => return Ok(self.status()),
+            AdminCommand::Pause => {
+                handles.control.pause();
+                handles.command_sender.send(Command::CancelAll).unwrap();
+                Ok(json!({ "paused": true }))
+            }
+            AdminCommand::Resume => {
+                handles.control.resume();
+                Ok(json!({ "paused": false }))
+            }
+            AdminCommand::SetParams(params) => handles.control.set_params(params.clone())
+                .map(|_| json!({ "params": handles.control.params() })),
+            AdminCommand::CancelAll => {
+                handles.command_sender.send(Command::CancelAll).unwrap();
+                Ok(json!({ "cancel_all": "sent" }))
+            }
+            AdminCommand::KillSwitch { reason } => {
+                handles.kill_switch.engage(&format!("admin: {}", reason.as_deref().unwrap_or("no reason given")));
+                Ok(json!({ "kill_switch": handles.kill_switch.reason() }))
+            }
+            AdminCommand::RearmKillSwitch => {
+                handles.kill_switch.rearm();
+                Ok(json!({ "kill_switch": null }))
+            }
+            AdminCommand::ResetCircuitBreaker => {
+                handles.risk.reset();
+                Ok(json!({ "circuit_breaker": handles.risk.state() }))
+            }
+        };
+
+        match &result {
+            Ok(_) => info!(target: "audit", "{} {:?}: ok", source, command),
+            Err(e) => warn!(target: "audit", "{} {:?}: failed: {}", source, command, e),
+        }
+
+        result
+    }
+
+    pub fn status(&self) -> Value {
+        let handles = &self.handles;
+
+        let books: Vec<Value> = handles.market_state.tickers().iter()
+            .map(|ticker| json!({
+                "instrument": ticker.instrument_name,
+                "timestamp": ticker.timestamp,
+                "best_bid": ticker.best_bid_price,
+                "best_ask": ticker.best_ask_price,
+                "mark_price": ticker.mark_price,
+            }))
+            .collect();
+        let freshness: Vec<Value> = handles.stale_data.books().iter()
+            .map(|(instrument, freshness)| json!({
+                "instrument": instrument,
+                "exchange_timestamp": freshness.exchange_timestamp,
+                "exchange_lag_ms": freshness.exchange_lag_ms,
+                "age_ms": freshness.received_at.elapsed().as_millis() as u64,
+            }))
+            .collect();
+        let orders: Vec<Order> = handles.active_orders.lock().unwrap().values().cloned().collect();
+        let positions = handles.positions.lock().unwrap().clone();
+        let latency: Vec<Value> = handles.latency.histograms().iter()
+            .map(|(kind, method, histogram)| json!({
+                "kind": kind.name(),
+                "method": method,
+                "count": histogram.count(),
+                "p50_us": histogram.percentile(50).as_micros() as u64,
+                "p90_us": histogram.percentile(90).as_micros() as u64,
+                "p99_us": histogram.percentile(99).as_micros() as u64,
+                "max_us": histogram.max().as_micros() as u64,
+            }))
+            .collect();
+
+        json!({
+            "connected": handles.metrics.gauge("ct_connected", &[]) == Some(1.0),
+            "paused": handles.control.is_paused(),
+            "params": handles.control.params(),
+            "kill_switch": { "engaged": handles.kill_switch.is_engaged(), "reason": handles.kill_switch.reason() },
+            "circuit_breaker": handles.risk.state(),
+            "margin_tier": handles.margin_guard.tier(),
+            "mmp_frozen": handles.mmp.is_frozen(),
+            "quotes_pulled": handles.stale_data.is_pulled(),
+            "book_freshness": freshness,
+            "books": books,
+            "instruments": handles.market_state.instruments(),
+            "orders": orders,
+            "positions": positions,
+            "accounts": handles.accounts.all(),
+            "event_bus_lag": handles.events.lag(),
+            "clock_offset_ms": handles.latency.clock_offset_ms(),
+            "latency": latency,
+        })
+    }
+
+    fn authorized(&self, token: Option<&str>) -> bool {
+        match token {
+            Some(token) => constant_time_eq(token.as_bytes(), self.token.as_bytes()),
+            None => false,
+        }
+    }
+
+    fn route(request: &Request) -> Result<AdminCommand, (&'static str, String)> {
+        let not_found = || ("404 Not Found", format!("no route for {} {}", request.method, request.path));
+
+        match (request.method.as_str(), request.path.as_str()) {
+            ("GET", "/status") => Ok(AdminCommand::Status),
+            ("POST", "/strategy/pause") => Ok(AdminCommand::Pause),
+            ("POST", "/strategy/resume") => Ok(AdminCommand::Resume),
+            ("POST", "/strategy/params") | ("PUT", "/strategy/params") => serde_json::from_str(&request.body)
+                .map(AdminCommand::SetParams)
+                .map_err(|e| ("400 Bad Request", format!("bad params: {}", e))),
+            ("POST", "/orders/cancel_all") => Ok(AdminCommand::CancelAll),
+            ("POST", "/kill_switch") => {
+                let reason = serde_json::from_str::<Value>(&request.body).ok()
+                    .and_then(|body| body["reason"].as_str().map(str::to_string));
+                Ok(AdminCommand::KillSwitch { reason })
+            }
+            ("POST", "/kill_switch/rearm") => Ok(AdminCommand::RearmKillSwitch),
+            ("POST", "/circuit_breaker/reset") => Ok(AdminCommand::ResetCircuitBreaker),
+            _ => Err(not_found()),
+        }
+    }
+
+    fn handle_http(&self, stream: TcpStream) {
+        let source = format!("http {}", stream.peer_addr().map(|address| address.to_string()).unwrap_or_default());
+
+        let request = match read_request(&stream) {
+            Ok(request) => request,
+            Err(e) => {
+                warn!(target: "audit", "{}: refused, {:?}", source, e);
+                write_response(&stream, e.status(), "text/plain", "bad request\n").ok();
+                return;
+            }
+        };
+
+        let token = request.header("Authorization").and_then(|value| value.strip_prefix("Bearer "));
+        if !self.authorized(token) {
+            warn!(target: "audit", "{} {} {}: unauthorized", source, request.method, request.path);
+            write_response(&stream, "401 Unauthorized", "text/plain", "unauthorized\n").ok();
+            return;
+        }
+
+        let (status, body) = match Admin::route(&request) {
+            Ok(command) => match self.execute(command, &source) {
+                Ok(result) => ("200 OK", result),
+                Err(e) => ("400 Bad Request", json!({ "error": e })),
+            },
+            Err((status, e)) => {
+                warn!(target: "audit", "{} {} {}: {}", source, request.method, request.path, e);
+                (status, json!({ "error": e }))
+            }
+        };
+
+        if let Err(e) = write_response(&stream, status, "application/json", &body.to_string()) {
+            warn!("Can't write admin response: {:?}", e);
+        }
+    }
+
+    // the token comes as a bearer header or a token query parameter, commands are JSON
+    // text frames like {"command": "pause"}, each one is answered with a JSON frame
+    fn handle_ws(&self, stream: TcpStream) {
+        let source = format!("ws {}", stream.peer_addr().map(|address| address.to_string()).unwrap_or_default());
+
+        // the handshake callback type is fixed by tungstenite
+        #[allow(clippy::result_large_err)]
+        let check_token = |request: &WsRequest, response: WsResponse| -> Result<WsResponse, ErrorResponse> {
+            let header = request.headers().get("Authorization")
+                .and_then(|value| value.to_str().ok())
+                .and_then(|value| value.strip_prefix("Bearer "));
+            let query = request.uri().query()
+                .and_then(|query| query.split('&').find_map(|pair| pair.strip_prefix("token=")));
+
+            if self.authorized(header.or(query)) {
+                Ok(response)
+            } else {
+                warn!(target: "audit", "{} {}: unauthorized", source, request.uri());
+                let mut refused = ErrorResponse::new(Some("unauthorized".to_string()));
+                *refused.status_mut() = tungstenite::http::StatusCode::UNAUTHORIZED;
+                Err(refused)
+            }
+        };
+
+        let mut socket = match tungstenite::accept_hdr(stream, check_token) {
+            Ok(socket) => socket,
+            Err(e) => {
+                warn!("Admin websocket handshake failed: {:?}", e);
+                return;
+            }
+        };
+        info!("Admin websocket connected from {}", source);
+
+        // the timeout covers the handshake, the connection itself may stay quiet
+        if let Err(e) = socket.get_ref().set_read_timeout(None) {
+            warn!("Can't clear the admin websocket read timeout: {:?}", e);
+            return;
+        }
+
+        loop {
+            let reply = match socket.read_message() {
+                Ok(Message::Text(text)) => match serde_json::from_str::<AdminCommand>(&text) {
+                    Ok(command) => match self.execute(command, &source) {
+                        Ok(result) => result,
+                        Err(e) => json!({ "error": e }),
+                    },
+                    Err(e) => {
+                        warn!(target: "audit", "{} {}: bad command: {}", source, text, e);
+                        json!({ "error": format!("bad command: {}", e) })
+                    }
+                },
+                Ok(Message::Close(_)) | Err(_) => break,
+                Ok(_) => continue,
+            };
+
+            if socket.write_message(Message::text(reply.to_string())).is_err() {
+                break;
+            }
+        }
+
+        info!("Admin websocket from {} closed", source);
+    }
+}
+
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |diff, (x, y)| diff | (x ^ y)) == 0
+}
+
+fn bind_local(address: &str) -> TcpListener {
+    let address: SocketAddr = address.parse().expect("Can't parse admin address");
+    assert!(address.ip().is_loopback(), "Admin API only listens on localhost, got {}", address);
+
+    TcpListener::bind(address).expect("Can't bind admin endpoint")
+}
+
+pub fn serve(admin: Arc<Admin>, http_address: &str, ws_address: &str) {
+    let http = bind_local(http_address);
+    let ws = bind_local(ws_address);
+    info!("Serving admin API on http://{} and ws://{}", http_address, ws_address);
+
+    let http_admin = Arc::clone(&admin);
+    serve_connections(http, "Admin", move |stream| http_admin.handle_http(stream));
+    serve_connections(ws, "Admin websocket", move |stream| admin.handle_ws(stream));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn request(method: &str, path: &str, body: &str) -> Request {
+        Request { method: method.to_string(), path: path.to_string(), headers: vec!(), body: body.to_string() }
+    }
+
+    #[test]
+    fn check_routes_and_commands() {
+        assert!(matches!(Admin::route(&request("GET", "/status", "")), Ok(AdminCommand::Status)));
+        assert!(matches!(Admin::route(&request("POST", "/strategy/params", r#"{"order_amount": 20, "quote_depth": 1}"#)),
+            Ok(AdminCommand::SetParams(StrategyParams { quote_depth: 1, .. }))));
+        assert!(matches!(Admin::route(&request("POST", "/strategy/params", "{}")), Err(("400 Bad Request", _))));
+        assert!(matches!(Admin::route(&request("POST", "/kill_switch", r#"{"reason": "manual"}"#)),
+            Ok(AdminCommand::KillSwitch { reason: Some(reason) }) if reason == "manual"));
+        assert!(matches!(Admin::route(&request("GET", "/strategy/pause", "")), Err(("404 Not Found", _))));
+
+        let command: AdminCommand = serde_json::from_str(r#"{"command": "set_params", "order_amount": 5, "quote_depth": 0}"#).unwrap();
+        assert!(matches!(command, AdminCommand::SetParams(StrategyParams { quote_depth: 0, .. })));
+        let command: AdminCommand = serde_json::from_str(r#"{"command": "reset_circuit_breaker"}"#).unwrap();
+        assert!(matches!(command, AdminCommand::ResetCircuitBreaker));
+
+        assert!(constant_time_eq(b"secret", b"secret"));
+        assert!(!constant_time_eq(b"secret", b"secreT"));
+        assert!(!constant_time_eq(b"secret", b"secret2"));
+    }
+}
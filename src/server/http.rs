@@ -0,0 +1,199 @@
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+use log::warn;
+
+// how long a client may take to send its request or to read the response
+pub const CONNECTION_TIMEOUT: Duration = Duration::from_secs(5);
+// the endpoints only take small json bodies, anything larger is refused before it's read
+const MAX_BODY_LENGTH: usize = 16 * 1024;
+const MAX_LINE_LENGTH: u64 = 8 * 1024;
+const MAX_HEADERS: usize = 32;
+
+// Just enough HTTP/1.1 for localhost endpoints: one request per connection,
+// no chunked bodies.
+#[derive(Debug, PartialEq)]
+pub struct Request {
+    pub method: String,
+    pub path: String,
+    pub headers: Vec<(String, String)>,
+    pub body: String,
+}
+
+#[derive(Debug, PartialEq)]
+pub enum RequestError {
+    Malformed,
+    HeadersTooLarge,
+    BodyTooLarge,
+}
+
+impl RequestError {
+    pub fn status(&self) -> &'static str {
+        match self {
+            RequestError::Malformed => "400 Bad Request",
+            RequestError::HeadersTooLarge => "431 Request Header Fields Too Large",
+            RequestError::BodyTooLarge => "413 Payload Too Large",
+        }
+    }
+}
+
+impl Request {
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers.iter()
+            .find(|(header, _)| header.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.as_str())
+    }
+}
+
+pub fn read_request(stream: &TcpStream) -> Result<Request, RequestError> {
+    let mut reader = BufReader::new(stream);
+
+    let line = read_line(&mut reader)?;
+    let mut parts = line.split_whitespace();
+    let method = parts.next().ok_or(RequestError::Malformed)?.to_string();
+    let path = parts.next().ok_or(RequestError::Malformed)?.to_string();
+
+    let mut headers = vec!();
+    loop {
+        let line = read_line(&mut reader)?;
+        let line = line.trim_end();
+        if line.is_empty() {
+            break;
+        }
+        if headers.len() == MAX_HEADERS {
+            return Err(RequestError::HeadersTooLarge);
+        }
+        let (name, value) = line.split_once(':').ok_or(RequestError::Malformed)?;
+        headers.push((name.trim().to_string(), value.trim().to_string()));
+    }
+
+    let mut request = Request { method, path, headers, body: String::new() };
+
+    let length: usize = match request.header("Content-Length") {
+        Some(length) => length.parse().map_err(|_| RequestError::Malformed)?,
+        None => 0,
+    };
+    if length > MAX_BODY_LENGTH {
+        return Err(RequestError::BodyTooLarge);
+    }
+    if length > 0 {
+        let mut body = vec![0; length];
+        reader.read_exact(&mut body).map_err(|_| RequestError::Malformed)?;
+        request.body = String::from_utf8(body).map_err(|_| RequestError::Malformed)?;
+    }
+
+    Ok(request)
+}
+
+// a line without its end within the limit is refused
+fn read_line(reader: &mut impl BufRead) -> Result<String, RequestError> {
+    let mut line = String::new();
+    reader.take(MAX_LINE_LENGTH).read_line(&mut line).map_err(|_| RequestError::Malformed)?;
+
+    match line.ends_with('\n') {
+        true => Ok(line),
+        false if line.len() as u64 == MAX_LINE_LENGTH => Err(RequestError::HeadersTooLarge),
+        false => Err(RequestError::Malformed),
+    }
+}
+
+// each connection gets its own thread and timeouts, a stuck client only holds up itself
+pub fn serve_connections(listener: TcpListener, name: &'static str, handler: impl Fn(TcpStream) + Send + Sync + 'static) {
+    let handler = Arc::new(handler);
+
+    thread::spawn(move || {
+        for stream in listener.incoming() {
+            let stream = match stream {
+                Ok(stream) => stream,
+                Err(e) => {
+                    warn!("{} connection failed: {:?}", name, e);
+                    continue;
+                }
+            };
+
+            let timeouts = stream.set_read_timeout(Some(CONNECTION_TIMEOUT))
+                .and_then(|_| stream.set_write_timeout(Some(CONNECTION_TIMEOUT)));
+            if let Err(e) = timeouts {
+                warn!("Can't set {} connection timeouts: {:?}", name, e);
+                continue;
+            }
+
+            let handler = Arc::clone(&handler);
+            thread::spawn(move || handler(stream));
+        }
+    });
+}
+
+pub fn write_response(mut stream: &TcpStream, status: &str, content_type: &str, body: &str) -> std::io::Result<()> {
+    write!(stream, "HTTP/1.1 {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        status, content_type, body.len(), body)?;
+    stream.flush()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::TcpListener;
+    use std::thread;
+    use super::*;
+
+    #[test]
+    fn check_request_and_response() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+
+        let client = thread::spawn(move || {
+            let mut stream = TcpStream::connect(address).unwrap();
+            stream.write_all(b"POST /pause HTTP/1.1\r\nHost: localhost\r\nAuthorization: Bearer secret\r\nContent-Length: 2\r\n\r\n{}").unwrap();
+
+            let mut response = String::new();
+            stream.read_to_string(&mut response).unwrap();
+            response
+        });
+
+        let (stream, _) = listener.accept().unwrap();
+        let request = read_request(&stream).unwrap();
+        assert_eq!(request.method, "POST");
+        assert_eq!(request.path, "/pause");
+        assert_eq!(request.header("authorization"), Some("Bearer secret"));
+        assert_eq!(request.body, "{}");
+
+        write_response(&stream, "200 OK", "text/plain", "ok\n").unwrap();
+        drop(stream);
+
+        assert_eq!(client.join().unwrap(), "HTTP/1.1 200 OK\r\nContent-Type: text/plain\r\nContent-Length: 3\r\nConnection: close\r\n\r\nok\n");
+    }
+
+    fn read_sent(request: Vec<u8>) -> Result<Request, RequestError> {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+
+        let client = thread::spawn(move || {
+            let mut stream = TcpStream::connect(address).unwrap();
+            // the server may refuse before reading everything
+            let _ = stream.write_all(&request);
+            stream
+        });
+
+        let (stream, _) = listener.accept().unwrap();
+        let result = read_request(&stream);
+        client.join().unwrap();
+        result
+    }
+
+    #[test]
+    fn check_limits() {
+        let body = format!("POST /pause HTTP/1.1\r\nContent-Length: {}\r\n\r\n", MAX_BODY_LENGTH + 1);
+        assert_eq!(read_sent(body.into_bytes()), Err(RequestError::BodyTooLarge));
+
+        let line = format!("GET /status HTTP/1.1\r\nX-Long: {}\r\n\r\n", "a".repeat(MAX_LINE_LENGTH as usize));
+        assert_eq!(read_sent(line.into_bytes()), Err(RequestError::HeadersTooLarge));
+
+        let headers = format!("GET /status HTTP/1.1\r\n{}\r\n", "X-Header: a\r\n".repeat(MAX_HEADERS + 1));
+        assert_eq!(read_sent(headers.into_bytes()), Err(RequestError::HeadersTooLarge));
+
+        assert_eq!(read_sent(b"GET /status HTTP/1.1\r\nContent-Length: x\r\n\r\n".to_vec()), Err(RequestError::Malformed));
+    }
+}
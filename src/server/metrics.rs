@@ -0,0 +1,86 @@
+use std::net::{TcpListener, TcpStream};
+use std::sync::Arc;
+
+use crossbeam_channel::Sender;
+use log::{info, warn};
+use rust_decimal::prelude::ToPrimitive;
+
+use crate::core::account::Accounts;
+use crate::core::domain::AccountState;
+use crate::core::entities::Command;
+use crate::core::event_bus::EventBus;
+use crate::core::latency::Latency;
+use crate::core::metrics::{render_gauge, render_histograms, Metrics};
+use crate::server::http::{read_request, serve_connections, write_response};
+
+// Serves GET /metrics in the prometheus text format
+pub struct MetricsServer {
+    metrics: Arc<Metrics>,
+    latency: Arc<Latency>,
+    accounts: Arc<Accounts>,
+    events: EventBus,
+    command_sender: Sender<Command>,
+}
+
+impl MetricsServer {
+    pub fn new(metrics: Arc<Metrics>,
+               latency: Arc<Latency>,
+               accounts: Arc<Accounts>,
+               events: EventBus,
+               command_sender: Sender<Command>) -> MetricsServer {
+        MetricsServer { metrics, latency, accounts, events, command_sender }
+    }
+
+    pub fn render(&self) -> String {
+        let mut out = String::new();
+
+        self.metrics.render(&mut out);
+
+        let histograms = self.latency.histograms().into_iter()
+            .map(|(kind, method, histogram)| (vec!(("kind".to_string(), kind.name().to_string()), ("method".to_string(), method)), histogram))
+            .collect();
+        render_histograms(&mut out, "ct_latency_seconds", histograms);
+        render_gauge(&mut out, "ct_clock_offset_seconds", vec!((vec!(), self.latency.clock_offset_ms() as f64 / 1000.0)));
+
+        let accounts = self.accounts.all();
+        let account_gauge = |value: fn(&AccountState) -> Option<f64>| {
+            accounts.iter()
+                .filter_map(|account| value(account).map(|value| (vec!(("currency".to_string(), account.currency.clone())), value)))
+                .collect::<Vec<_>>()
+        };
+        render_gauge(&mut out, "ct_equity", account_gauge(|account| account.equity.to_f64()));
+        render_gauge(&mut out, "ct_available_funds", account_gauge(|account| account.available_funds.to_f64()));
+        render_gauge(&mut out, "ct_margin_ratio", account_gauge(|account| account.margin_ratio().and_then(|ratio| ratio.to_f64())));
+        render_gauge(&mut out, "ct_session_pnl", account_gauge(|account| (account.session_rpl + account.session_upl).to_f64()));
+        render_gauge(&mut out, "ct_total_pnl", account_gauge(|account| account.total_pl.to_f64()));
+        render_gauge(&mut out, "ct_delta", account_gauge(|account| account.delta_total.to_f64()));
+
+        let lag = self.events.lag().into_iter()
+            .map(|(reader, lag)| (vec!(("reader".to_string(), reader)), lag as f64))
+            .collect();
+        render_gauge(&mut out, "ct_event_bus_lag", lag);
+        render_gauge(&mut out, "ct_command_queue_depth", vec!((vec!(), self.command_sender.len() as f64)));
+
+        out
+    }
+
+    fn handle(&self, stream: TcpStream) {
+        let result = match read_request(&stream) {
+            Ok(request) if request.method == "GET" && request.path == "/metrics" =>
+                write_response(&stream, "200 OK", "text/plain; version=0.0.4", &self.render()),
+            Ok(_) => write_response(&stream, "404 Not Found", "text/plain", "not found\n"),
+            Err(e) => write_response(&stream, e.status(), "text/plain", "bad request\n"),
+        };
+
+        if let Err(e) = result {
+            warn!("Can't write metrics response: {:?}", e);
+        }
+    }
+}
+
+pub fn serve(server: MetricsServer, address: &str) {
+    let listener = TcpListener::bind(address).expect("Can't bind metrics endpoint");
+    info!("Serving metrics on http://{}/metrics", address);
+
+    serve_connections(listener, "Metrics", move |stream| server.handle(stream));
+}
@@ -0,0 +1,93 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::RwLock;
+
+use log::warn;
+use rust_decimal::Decimal;
+use serde::{Serialize, Deserialize};
+
+// quoting parameters which can be changed while running
+#[derive(Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq)]
+pub struct StrategyParams {
+    // size of each quote leg
+    pub order_amount: Decimal,
+    // quotes sit between book levels quote_depth and quote_depth + 1, counted from 0
+    pub quote_depth: usize,
+}
+
+impl Default for StrategyParams {
+    fn default() -> StrategyParams {
+        StrategyParams { order_amount: Decimal::from(10), quote_depth: 2 }
+    }
+}
+
+// Shared between the market maker, the order manager and the admin API
+pub struct StrategyControl {
+    paused: AtomicBool,
+    params: RwLock<StrategyParams>,
+    max_quote_depth: usize,
+}
+
+impl StrategyControl {
+    pub fn new(params: StrategyParams, max_quote_depth: usize) -> StrategyControl {
+        StrategyControl { paused: AtomicBool::new(false), params: RwLock::new(params), max_quote_depth }
+    }
+
+    pub fn is_paused(&self) -> bool {
+        self.paused.load(Ordering::SeqCst)
+    }
+
+    pub fn pause(&self) {
+        if !self.paused.swap(true, Ordering::SeqCst) {
+            warn!(target: "alerts", "Strategy paused");
+        }
+    }
+
+    pub fn resume(&self) {
+        if self.paused.swap(false, Ordering::SeqCst) {
+            warn!(target: "alerts", "Strategy resumed");
+        }
+    }
+
+    pub fn params(&self) -> StrategyParams {
+        self.params.read().unwrap().clone()
+    }
+
+    pub fn set_params(&self, params: StrategyParams) -> Result<(), String> {
+        if params.order_amount <= Decimal::ZERO {
+            return Err(format!("order_amount must be positive, got {}", params.order_amount));
+        }
+        if params.quote_depth > self.max_quote_depth {
+            return Err(format!("quote_depth must be at most {}, got {}", self.max_quote_depth, params.quote_depth));
+        }
+
+        let mut current = self.params.write().unwrap();
+        warn!(target: "alerts", "Strategy params changed from {:?} to {:?}", *current, params);
+        *current = params;
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn check_pause_and_params() {
+        let control = StrategyControl::new(StrategyParams::default(), 10);
+
+        control.pause();
+        assert!(control.is_paused());
+        control.resume();
+        assert!(!control.is_paused());
+
+        let params = StrategyParams { order_amount: Decimal::from(20), quote_depth: 1 };
+        assert_eq!(control.set_params(params.clone()), Ok(()));
+        assert_eq!(control.params(), params);
+
+        assert!(control.set_params(StrategyParams { order_amount: Decimal::ZERO, quote_depth: 1 }).is_err());
+        assert!(control.set_params(StrategyParams { order_amount: Decimal::ONE, quote_depth: 11 }).is_err());
+        assert_eq!(control.params(), params);
+    }
+}
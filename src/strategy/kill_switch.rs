@@ -0,0 +1,114 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread;
+
+use crossbeam_channel::Sender;
+use log::{error, info, warn};
+use signal_hook::consts::SIGUSR1;
+use signal_hook::iterator::Signals;
+
+use crate::core::entities::Command;
+
+// Once engaged, strategies stop quoting, resting orders are cancelled and
+// MakeOrder commands are refused until the switch is re-armed.
+pub struct KillSwitch {
+    engaged: AtomicBool,
+    reason: Mutex<Option<String>>,
+    flatten_positions: bool,
+    currency: String,
+    command_sender: Sender<Command>,
+}
+
+impl KillSwitch {
+    pub fn new(command_sender: Sender<Command>, flatten_positions: bool, currency: String) -> KillSwitch {
+        KillSwitch {
+            engaged: AtomicBool::new(false),
+            reason: Mutex::new(None),
+            flatten_positions,
+            currency,
+            command_sender,
+        }
+    }
+
+    pub fn is_engaged(&self) -> bool {
+        self.engaged.load(Ordering::SeqCst)
+    }
+
+    pub fn reason(&self) -> Option<String> {
+        self.reason.lock().unwrap().clone()
+    }
+
+    pub fn engage(&self, reason: &str) {
+        self.trip(reason, self.flatten_positions);
+    }
+
+    // closes positions even if flatten_positions is off
+    pub fn engage_and_flatten(&self, reason: &str) {
+        self.trip(reason, true);
+    }
+
+    fn trip(&self, reason: &str, flatten_positions: bool) {
+        if self.engaged.swap(true, Ordering::SeqCst) {
+            warn!("Kill switch is already engaged, ignoring: {}", reason);
+            return;
+        }
+
+        *self.reason.lock().unwrap() = Some(reason.to_string());
+        error!(target: "alerts", "Kill switch engaged: {}", reason);
+
+        self.command_sender.send(Command::CancelAll).unwrap();
+
+        if flatten_positions {
+            self.command_sender.send(Command::ClosePositions { currency: self.currency.clone() }).unwrap();
+        }
+    }
+
+    pub fn rearm(&self) {
+        if self.engaged.swap(false, Ordering::SeqCst) {
+            let reason = self.reason.lock().unwrap().take();
+            warn!(target: "alerts", "Kill switch re-armed, was engaged by: {:?}", reason);
+        }
+    }
+}
+
+// `kill -USR1 <pid>` engages the kill switch
+pub fn listen_signals(kill_switch: Arc<KillSwitch>) {
+    let mut signals = Signals::new([SIGUSR1]).expect("Can't register signal handler");
+
+    thread::spawn(move || {
+        for signal in signals.forever() {
+            info!("Got signal {}", signal);
+            kill_switch.engage("SIGUSR1");
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use crossbeam_channel::bounded;
+    use super::*;
+
+    #[test]
+    fn check_engage_and_rearm() {
+        let (command_sender, command_receiver) = bounded(10);
+        let kill_switch = KillSwitch::new(command_sender, true, "BTC".to_string());
+
+        assert!(!kill_switch.is_engaged());
+
+        kill_switch.engage("test");
+        kill_switch.engage("test again");
+
+        assert!(kill_switch.is_engaged());
+        assert_eq!(kill_switch.reason(), Some("test".to_string()));
+
+        let commands: Vec<Command> = command_receiver.try_iter().collect();
+        assert_eq!(commands.len(), 2);
+        assert!(matches!(commands[0], Command::CancelAll));
+        assert!(matches!(&commands[1], Command::ClosePositions { currency } if currency == "BTC"));
+
+        kill_switch.rearm();
+
+        assert!(!kill_switch.is_engaged());
+        assert_eq!(kill_switch.reason(), None);
+    }
+}
@@ -0,0 +1,209 @@
+use std::sync::{Arc, Mutex};
+use std::thread;
+
+use crossbeam_channel::Sender;
+use log::{error, info, warn};
+use rust_decimal::Decimal;
+use serde::Serialize;
+
+use crate::config::MarginGuardConfig;
+use crate::core::domain::{AccountState, Side};
+use crate::core::entities::Command;
+use crate::core::event_bus::{Event, EventReader};
+use crate::strategy::kill_switch::KillSwitch;
+
+#[derive(Serialize)]
+#[serde(rename_all = "snake_case")]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum MarginTier {
+    Normal,
+    // alert only, quoting goes on
+    Warning,
+    // only quotes which bring the delta back to zero
+    ReduceOnly,
+    // positions are closed and the kill switch is engaged
+    Flatten,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct GuardState {
+    tier: MarginTier,
+    delta: Decimal,
+}
+
+// Watches margin utilization (maintenance margin / equity) and the distance to the
+// estimated liquidation price of one currency, the worse of the two picks the tier.
+pub struct MarginGuard {
+    state: Mutex<GuardState>,
+    config: MarginGuardConfig,
+    kill_switch: Arc<KillSwitch>,
+    command_sender: Sender<Command>,
+}
+
+impl MarginGuard {
+    pub fn new(command_sender: Sender<Command>, kill_switch: Arc<KillSwitch>, config: MarginGuardConfig) -> MarginGuard {
+        MarginGuard {
+            state: Mutex::new(GuardState { tier: MarginTier::Normal, delta: Decimal::ZERO }),
+            config,
+            kill_switch,
+            command_sender,
+        }
+    }
+
+    pub fn tier(&self) -> MarginTier {
+        self.state.lock().unwrap().tier
+    }
+
+    // whether a new quote on this side is allowed in the current tier
+    pub fn allows(&self, side: Side) -> bool {
+        let state = self.state.lock().unwrap();
+
+        match state.tier {
+            MarginTier::Normal | MarginTier::Warning => true,
+            MarginTier::ReduceOnly => match side {
+                Side::Bid => state.delta < Decimal::ZERO,
+                Side::Ask => state.delta > Decimal::ZERO,
+            },
+            MarginTier::Flatten => false,
+        }
+    }
+
+    // in the ReduceOnly tier quotes can't be larger than the delta they reduce, in currency units
+    pub fn reduce_only_limit(&self) -> Option<Decimal> {
+        let state = self.state.lock().unwrap();
+
+        match state.tier {
+            MarginTier::ReduceOnly => Some(state.delta.abs()),
+            _ => None,
+        }
+    }
+
+    // price move to the estimated liquidation price as a fraction of the price,
+    // deribit only estimates it without portfolio margining
+    pub fn liquidation_distance(account: &AccountState) -> Option<Decimal> {
+        account.estimated_liquidation_ratio
+            .filter(|ratio| !ratio.is_zero())
+            .map(|ratio| (Decimal::ONE - ratio).abs())
+    }
+
+    pub fn classify(&self, account: &AccountState) -> MarginTier {
+        let by_utilization = match account.margin_ratio() {
+            Some(ratio) if ratio >= self.config.flatten_utilization => MarginTier::Flatten,
+            Some(ratio) if ratio >= self.config.reduce_only_utilization => MarginTier::ReduceOnly,
+            Some(ratio) if ratio >= self.config.warning_utilization => MarginTier::Warning,
+            Some(_) => MarginTier::Normal,
+            // no equity left to carry any margin
+            None if account.maintenance_margin > Decimal::ZERO => MarginTier::Flatten,
+            None => MarginTier::Normal,
+        };
+
+        let by_distance = match MarginGuard::liquidation_distance(account) {
+            Some(distance) if distance <= self.config.flatten_liquidation_distance => MarginTier::Flatten,
+            Some(distance) if distance <= self.config.reduce_only_liquidation_distance => MarginTier::ReduceOnly,
+            Some(distance) if distance <= self.config.warning_liquidation_distance => MarginTier::Warning,
+            _ => MarginTier::Normal,
+        };
+
+        by_utilization.max(by_distance)
+    }
+
+    pub fn update(&self, account: &AccountState) -> MarginTier {
+        if account.currency != self.config.currency {
+            return self.tier();
+        }
+
+        let tier = self.classify(account);
+        let previous = {
+            let mut state = self.state.lock().unwrap();
+            let previous = state.tier;
+            *state = GuardState { tier, delta: account.delta_total };
+            previous
+        };
+
+        if tier != previous {
+            let message = format!("Margin tier {:?} -> {:?} on {}: margin ratio {:?}, liquidation distance {:?}",
+                previous, tier, account.currency, account.margin_ratio(), MarginGuard::liquidation_distance(account));
+
+            match tier {
+                MarginTier::Normal => info!(target: "alerts", "{}", message),
+                MarginTier::Warning | MarginTier::ReduceOnly => warn!(target: "alerts", "{}", message),
+                MarginTier::Flatten => {
+                    error!(target: "alerts", "{}", message);
+                    self.flatten();
+                }
+            }
+        }
+
+        tier
+    }
+
+    fn flatten(&self) {
+        if self.kill_switch.is_engaged() {
+            self.command_sender.send(Command::ClosePositions { currency: self.config.currency.clone() }).unwrap();
+        } else {
+            self.kill_switch.engage_and_flatten("margin guard");
+        }
+    }
+}
+
+pub fn watch(guard: Arc<MarginGuard>, events: EventReader) {
+    thread::spawn(move || {
+        for event in events {
+            if let Event::Portfolio(account) = event {
+                guard.update(&account);
+            }
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use crossbeam_channel::bounded;
+    use super::*;
+
+    fn account(equity: i64, maintenance_margin: i64, liquidation_ratio: Option<Decimal>, delta: i64) -> AccountState {
+        AccountState {
+            currency: "BTC".to_string(),
+            equity: Decimal::from(equity),
+            maintenance_margin: Decimal::from(maintenance_margin),
+            estimated_liquidation_ratio: liquidation_ratio,
+            delta_total: Decimal::from(delta),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn check_margin_tiers() {
+        let (command_sender, command_receiver) = bounded(10);
+        let kill_switch = Arc::new(KillSwitch::new(command_sender.clone(), false, "BTC".to_string()));
+        let guard = MarginGuard::new(command_sender, Arc::clone(&kill_switch), MarginGuardConfig::default());
+
+        assert_eq!(guard.update(&account(100, 10, None, 5)), MarginTier::Normal);
+        assert!(guard.allows(Side::Bid) && guard.allows(Side::Ask));
+        assert_eq!(guard.reduce_only_limit(), None);
+
+        assert_eq!(guard.update(&account(100, 55, None, 5)), MarginTier::Warning);
+        // liquidation price 8% away is worse than the utilization
+        assert_eq!(guard.update(&account(100, 55, Some(Decimal::new(92, 2)), 5)), MarginTier::ReduceOnly);
+        assert!(!guard.allows(Side::Bid));
+        assert!(guard.allows(Side::Ask));
+        assert_eq!(guard.reduce_only_limit(), Some(Decimal::from(5)));
+        assert_eq!(guard.update(&account(100, 55, Some(Decimal::new(92, 2)), -3)), MarginTier::ReduceOnly);
+        assert_eq!(guard.reduce_only_limit(), Some(Decimal::from(3)));
+
+        // other currencies don't move the tier
+        assert_eq!(guard.update(&AccountState { currency: "ETH".to_string(), ..Default::default() }), MarginTier::ReduceOnly);
+        assert!(command_receiver.try_recv().is_err());
+
+        assert_eq!(guard.update(&account(100, 90, None, 5)), MarginTier::Flatten);
+        assert!(!guard.allows(Side::Ask));
+        assert!(kill_switch.is_engaged());
+
+        let commands: Vec<Command> = command_receiver.try_iter().collect();
+        assert_eq!(commands.len(), 2);
+        assert!(matches!(commands[0], Command::CancelAll));
+        assert!(matches!(&commands[1], Command::ClosePositions { currency } if currency == "BTC"));
+
+        assert_eq!(guard.update(&account(100, 10, None, 0)), MarginTier::Normal);
+    }
+}
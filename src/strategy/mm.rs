@@ -1,54 +1,163 @@
-use std::sync::{Arc, Mutex};
-use std::thread;
+use std::sync::Arc;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 
-use crossbeam_channel::{Receiver, Sender};
+use crossbeam_channel::Sender;
+use log::{error, info};
 use rust_decimal::Decimal;
 use rust_decimal::prelude::ToPrimitive;
-use crate::core::entities::OrderbookUpdate;
+use crate::core::entities::Command;
+use crate::core::event_bus::{ConnectionEvent, Event, EventReader};
+use crate::core::market_state::MarketState;
+use crate::core::shutdown::Shutdown;
 use crate::orderbook::TreeOrderBook;
+use crate::core::domain::Side;
+use crate::strategy::control::StrategyControl;
+use crate::strategy::kill_switch::KillSwitch;
+use crate::strategy::margin_guard::MarginGuard;
+use crate::strategy::risk::RiskManager;
+use crate::strategy::stale_data::StaleDataGuard;
+use crate::strategy::mmp::MmpGuard;
 use crate::strategy::order_manager::OrderPosition;
+use crate::strategy::trade_flow::TradeFlow;
+
+const FLOW_REPORT_INTERVAL: Duration = Duration::from_secs(60);
+const BOOK_CHANNEL: &str = "book.BTC-PERPETUAL.raw";
 
 pub struct MarketMaker {
-    orderbook_receiver: Receiver<OrderbookUpdate>,
+    events: EventReader,
     signal_sender: Sender<OrderPosition>,
+    command_sender: Sender<Command>,
+    kill_switch: Arc<KillSwitch>,
+    mmp: Arc<MmpGuard>,
+    market_state: Arc<MarketState>,
+    margin_guard: Arc<MarginGuard>,
+    risk: Arc<RiskManager>,
+    stale_data: Arc<StaleDataGuard>,
+    control: Arc<StrategyControl>,
+    shutdown: Arc<Shutdown>,
+    orderbook: TreeOrderBook,
 }
 
 impl MarketMaker {
-    pub fn new(orderbook_receiver: Receiver<OrderbookUpdate>,
-               signal_sender: Sender<OrderPosition>) -> MarketMaker {
+    #[allow(clippy::too_many_arguments)]
+    pub fn new(events: EventReader,
+               signal_sender: Sender<OrderPosition>,
+               command_sender: Sender<Command>,
+               kill_switch: Arc<KillSwitch>,
+               mmp: Arc<MmpGuard>,
+               market_state: Arc<MarketState>,
+               margin_guard: Arc<MarginGuard>,
+               risk: Arc<RiskManager>,
+               stale_data: Arc<StaleDataGuard>,
+               control: Arc<StrategyControl>,
+               shutdown: Arc<Shutdown>) -> MarketMaker {
         MarketMaker {
-            orderbook_receiver,
+            events,
             signal_sender,
+            command_sender,
+            kill_switch,
+            mmp,
+            market_state,
+            margin_guard,
+            risk,
+            stale_data,
+            control,
+            shutdown,
+            orderbook: TreeOrderBook::new(),
         }
     }
 
-    pub fn run(&self) {
-        let mut orderbook = TreeOrderBook::new();
+    // the book is kept across restarts, the raw feed only sends it once as a snapshot
+    pub fn run(&mut self) {
+        let mut trade_flow = TradeFlow::new(vec!(Duration::from_secs(1), Duration::from_secs(10), Duration::from_secs(60)));
+        let mut flow_reported_at = Instant::now();
 
-        thread::scope(|s| {
-            s.spawn(move || {
+        loop {
+            let event = match self.events.next_event() {
+                Some(event) => event,
+                None => {
+                    info!("Strategy stopped: the event bus is closed");
+                    return;
+                }
+            };
 
-                let mut orderbook_iter = self.orderbook_receiver.iter();
-                loop {
+            if self.shutdown.is_requested() {
+                info!("Strategy stopped: shutting down");
+                return;
+            }
 
-                    let orderbook_update = orderbook_iter.next().unwrap();
+            let orderbook_update = match event {
+                Event::Book(update) => update,
+                Event::Trade(trade) => {
+                    trade_flow.add(trade);
 
-                    orderbook.add_bids(orderbook_update.bids);
-                    orderbook.add_asks(orderbook_update.asks);
+                    if flow_reported_at.elapsed() >= FLOW_REPORT_INTERVAL {
+                        for (window, stats) in trade_flow.all_stats() {
+                            info!("Trade flow over {:?}: {:?}", window, stats);
+                        }
+                        info!("Index price {:?}, mark price {:?}, funding {:?}, open interest {:?}",
+                            self.market_state.index_price("btc_usd"),
+                            self.market_state.mark_price("BTC-PERPETUAL"),
+                            self.market_state.funding("BTC-PERPETUAL").map(|funding| funding.interest),
+                            self.market_state.open_interest("BTC-PERPETUAL"));
+                        flow_reported_at = Instant::now();
+                    }
+                    continue;
+                }
+                Event::Connection(ConnectionEvent::Heartbeat) => {
+                    self.stale_data.on_heartbeat(Instant::now());
+                    continue;
+                }
+                Event::Connection(ConnectionEvent::Disconnected) => {
+                    // the book is resubscribed after reconnect and starts with a snapshot
+                    self.stale_data.on_disconnected();
+                    self.orderbook = TreeOrderBook::new();
+                    continue;
+                }
+                Event::Gap { missed } => {
+                    // the missed changes can't be replayed, a new subscription starts with a snapshot
+                    error!(target: "alerts", "Strategy missed {} events, resubscribing the book", missed);
+                    self.stale_data.on_disconnected();
+                    self.orderbook = TreeOrderBook::new();
+                    self.command_sender.send(Command::UnsubscribeData { channel: BOOK_CHANNEL.to_string() }).unwrap();
+                    self.command_sender.send(Command::SubscribeData { channel: BOOK_CHANNEL.to_string() }).unwrap();
+                    continue;
+                }
+                _ => continue,
+            };
 
+            let received_at = Instant::now();
+            let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_millis() as i64;
 
-                    let bid_price = Decimal::from_f64_retain((orderbook.get_nth_bid(2).unwrap().0 + orderbook.get_nth_bid(3).unwrap().0).to_f64().unwrap() / 2.0).unwrap();
-                    let ask_price = Decimal::from_f64_retain((orderbook.get_nth_ask(2).unwrap().0 + orderbook.get_nth_ask(3).unwrap().0).to_f64().unwrap() / 2.0).unwrap();
+            let orderbook = &mut self.orderbook;
+            orderbook.add_bids(orderbook_update.bids);
+            orderbook.add_asks(orderbook_update.asks);
 
+            self.stale_data.on_book(&orderbook_update.instrument_name, orderbook_update.timestamp, now, received_at,
+                orderbook.best_bid().map(|(price, _)| *price), orderbook.best_ask().map(|(price, _)| *price));
 
-                    let signal = OrderPosition {
-                        bid: bid_price,
-                        ask: ask_price
-                    };
+            if self.kill_switch.is_engaged() || self.mmp.is_frozen() || self.risk.is_tripped() || self.stale_data.is_pulled() || self.control.is_paused() {
+                continue;
+            }
 
-                    self.signal_sender.send(signal).unwrap();
-                }
-            });
-        });
+            let depth = self.control.params().quote_depth;
+            // no signal until the book has the levels on both sides
+            let (bid_price, ask_price) = match (orderbook.get_nth_bid(depth), orderbook.get_nth_bid(depth + 1), orderbook.get_nth_ask(depth), orderbook.get_nth_ask(depth + 1)) {
+                (Some(bid), Some(next_bid), Some(ask), Some(next_ask)) => (
+                    Decimal::from_f64_retain((bid.0 + next_bid.0).to_f64().unwrap() / 2.0).unwrap(),
+                    Decimal::from_f64_retain((ask.0 + next_ask.0).to_f64().unwrap() / 2.0).unwrap(),
+                ),
+                _ => continue,
+            };
+
+
+            let signal = OrderPosition {
+                bid: Some(bid_price).filter(|_| self.margin_guard.allows(Side::Bid)),
+                ask: Some(ask_price).filter(|_| self.margin_guard.allows(Side::Ask)),
+                reduce_only: self.margin_guard.reduce_only_limit(),
+            };
+
+            self.signal_sender.send(signal).unwrap();
+        }
     }
 }
\ No newline at end of file
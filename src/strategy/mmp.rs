@@ -0,0 +1,98 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crossbeam_channel::Sender;
+use log::{info, warn};
+
+use crate::core::entities::Command;
+
+const COOL_DOWN_CHECK_INTERVAL: Duration = Duration::from_millis(100);
+
+// Tracks whether deribit market maker protection has frozen our quotes.
+// Strategies don't quote while frozen, the freeze is reset after the cool-down.
+pub struct MmpGuard {
+    frozen: AtomicBool,
+    frozen_at: Mutex<Option<Instant>>,
+    index_name: String,
+    cool_down: Duration,
+    command_sender: Sender<Command>,
+}
+
+impl MmpGuard {
+    pub fn new(command_sender: Sender<Command>, index_name: String, cool_down: Duration) -> MmpGuard {
+        MmpGuard {
+            frozen: AtomicBool::new(false),
+            frozen_at: Mutex::new(None),
+            index_name,
+            cool_down,
+            command_sender,
+        }
+    }
+
+    pub fn is_frozen(&self) -> bool {
+        self.frozen.load(Ordering::SeqCst)
+    }
+
+    pub fn trigger(&self, reason: &str) {
+        if !self.frozen.swap(true, Ordering::SeqCst) {
+            *self.frozen_at.lock().unwrap() = Some(Instant::now());
+            warn!(target: "alerts", "MMP triggered on {}: {}, quoting is frozen for {:?}", self.index_name, reason, self.cool_down);
+        }
+    }
+
+    // sends private/reset_mmp once the cool-down has passed, returns true if it did
+    pub fn check_cool_down(&self, now: Instant) -> bool {
+        let mut frozen_at = self.frozen_at.lock().unwrap();
+
+        match *frozen_at {
+            Some(at) if now.duration_since(at) >= self.cool_down => {
+                info!("MMP cool-down on {} is over, resetting", self.index_name);
+
+                self.command_sender.send(Command::ResetMmp { index_name: self.index_name.clone() }).unwrap();
+                *frozen_at = None;
+                self.frozen.store(false, Ordering::SeqCst);
+                true
+            }
+            _ => false,
+        }
+    }
+}
+
+pub fn watch_cool_down(guard: Arc<MmpGuard>) {
+    thread::spawn(move || {
+        loop {
+            thread::sleep(COOL_DOWN_CHECK_INTERVAL);
+            guard.check_cool_down(Instant::now());
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use crossbeam_channel::bounded;
+    use super::*;
+
+    #[test]
+    fn check_reset_after_cool_down() {
+        let (command_sender, command_receiver) = bounded(10);
+        let guard = MmpGuard::new(command_sender, "btc_usd".to_string(), Duration::from_secs(10));
+
+        assert!(!guard.check_cool_down(Instant::now()));
+
+        guard.trigger("test");
+        guard.trigger("test again");
+        assert!(guard.is_frozen());
+
+        assert!(!guard.check_cool_down(Instant::now()));
+        assert!(guard.is_frozen());
+
+        assert!(guard.check_cool_down(Instant::now() + Duration::from_secs(10)));
+        assert!(!guard.is_frozen());
+
+        let commands: Vec<Command> = command_receiver.try_iter().collect();
+        assert_eq!(commands.len(), 1);
+        assert!(matches!(&commands[0], Command::ResetMmp { index_name } if index_name == "btc_usd"));
+    }
+}
@@ -1,3 +1,9 @@
 pub mod risk;
 pub mod mm;
 pub mod order_manager;
+pub mod kill_switch;
+pub mod mmp;
+pub mod trade_flow;
+pub mod margin_guard;
+pub mod stale_data;
+pub mod control;
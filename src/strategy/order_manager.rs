@@ -1,87 +1,318 @@
-use std::collections::{HashMap, HashSet};
+use std::collections::HashMap;
 use std::sync::{Arc, Mutex, RwLock};
-use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 use std::thread;
 use rust_decimal::Decimal;
 use crossbeam_channel::{Receiver, Sender};
 use log::{error, info, warn};
 use uuid::Uuid;
 
-use crate::core::entities::{Command, OrderSide};
-
-#[derive(Debug, PartialEq, Eq)]
-pub enum TradeDirection {
-    Bid,
-    Ask,
-}
-
+use crate::config::OrphanOrderPolicy;
+use crate::core::domain::{instrument_currency, ErrorAction, Order, OrderStatus, Side};
+use crate::core::entities::{Command, QuoteLeg};
+use crate::core::event_bus::{Event, EventReader};
+use crate::core::latency::{Latency, LatencyKind};
+use crate::core::metrics::Metrics;
+use crate::core::supervisor::Supervisor;
+use rust_decimal::prelude::ToPrimitive;
+use crate::strategy::control::StrategyControl;
+use crate::strategy::kill_switch::KillSwitch;
+use crate::strategy::mmp::MmpGuard;
+
+const ORDER_ACK_TIMEOUT: Duration = Duration::from_secs(5);
+const ORDER_STATE_QUERY_TIMEOUT: Duration = Duration::from_secs(5);
+const ORDER_STATE_QUERY_ATTEMPTS: u32 = 3;
+const UNCONFIRMED_CHECK_INTERVAL: Duration = Duration::from_millis(100);
+// BTC-PERPETUAL amounts are USD, in steps of the contract size
+const CONTRACT_SIZE: Decimal = Decimal::TEN;
+
+// None leaves that side unquoted
 #[derive(Debug)]
 pub struct OrderPosition {
-    pub bid: Decimal,
-    pub ask: Decimal,
-}
-
-
-#[derive(Debug)]
-pub enum OrderStatus {
-    Open,
-    Filled,
-    Rejected,
-    Cancelled,
-    Untriggered,
+    pub bid: Option<Decimal>,
+    pub ask: Option<Decimal>,
+    // quotes go out reduce only and no larger than this delta, in currency units
+    pub reduce_only: Option<Decimal>,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub enum OrderEvent {
     OrderChanged {
-        id: String,
-        direction: TradeDirection,
-        price: Decimal,
-        amount: Decimal,
-        status: OrderStatus,
-        label: String,
+        order: Order,
+        mmp_cancelled: bool,
     },
     OrderSuccess {
         uuid: Uuid,
+        // method of the acknowledged request, None once it's older than the request timeout
+        method: Option<String>,
+    },
+    // answer to Command::GetOrderState, the found order comes before it as OrderChanged
+    OrderStateResolved {
+        request_id: Uuid,
+        label: String,
+        found: bool,
+    },
+    // open orders on the exchange after (re)connect
+    OpenOrdersSnapshot {
+        orders: Vec<Order>,
+    },
+    // instrument -> signed position size
+    PositionsSnapshot {
+        positions: HashMap<String, Decimal>,
+    },
+    MmpTriggered {
+        index_name: String,
+    },
+    // a request was rejected before it became an order
+    OrderFailed {
+        request_id: Uuid,
+        label: String,
+        reason: String,
+        action: ErrorAction,
+        // with Backoff, until the exchange takes orders again
+        retry_after: Duration,
     },
 }
 
+// Client order ids are sent to deribit as order labels (max 64 chars).
+// The prefix holds the process start time so ids don't collide across restarts.
+pub struct ClientOrderIdGenerator {
+    prefix: String,
+    sequence: u64,
+}
+
+impl ClientOrderIdGenerator {
+    pub fn new() -> ClientOrderIdGenerator {
+        let start = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_millis();
+
+        ClientOrderIdGenerator::with_prefix(format!("ct{}", start))
+    }
+
+    pub fn with_prefix(prefix: String) -> ClientOrderIdGenerator {
+        ClientOrderIdGenerator { prefix, sequence: 0 }
+    }
+
+    pub fn next_id(&mut self) -> String {
+        self.sequence += 1;
+        format!("{}-{}", self.prefix, self.sequence)
+    }
+}
+
+// MakeOrder request which is not yet visible in user.orders
+#[derive(Debug)]
+pub struct PendingOrder {
+    request_id: Uuid,
+    direction: Side,
+    sent_at: Instant,
+    acked_at: Option<Instant>,
+    // named by the ack, MakeQuotes goes out as private/mass_quote or one buy/sell per leg
+    method: Option<String>,
+}
+
+// MakeOrder request waiting for its ack
 #[derive(Debug)]
-pub struct Order {
-    id: String,
-    direction: TradeDirection,
-    price: Decimal,
-    amount: Decimal,
-    status: OrderStatus,
-    label: String,
+pub struct UnconfirmedOrder {
+    client_order_id: String,
+    instrument: String,
+    deadline: Instant,
+    state_query: Option<Uuid>,
+    state_queries: u32,
+    // send -> update of an order which showed up before its ack, recorded with the ack
+    send_to_update: Option<Duration>,
+}
+
+impl UnconfirmedOrder {
+    pub fn new(client_order_id: String, instrument: String, now: Instant) -> UnconfirmedOrder {
+        UnconfirmedOrder {
+            client_order_id,
+            instrument,
+            deadline: now + ORDER_ACK_TIMEOUT,
+            state_query: None,
+            state_queries: 0,
+            send_to_update: None,
+        }
+    }
 }
 
+// Queries the state of every request whose ack or previous query has timed out.
+// After ORDER_STATE_QUERY_ATTEMPTS the order is cancelled by its label and forgotten.
+// order_ids maps client order ids to exchange ids for orders already seen in user.orders.
+pub fn check_unconfirmed(unconfirmed: &mut HashMap<Uuid, UnconfirmedOrder>,
+                         order_ids: &HashMap<String, String>,
+                         now: Instant) -> Vec<Command> {
+    let mut commands = vec!();
+    let mut abandoned = vec!();
+
+    for (request_id, order) in unconfirmed.iter_mut().filter(|(_, order)| order.deadline <= now) {
+        match order.state_query {
+            None => warn!("Order request {} ({}) wasn't acknowledged in {:?}, querying its state",
+                request_id, order.client_order_id, ORDER_ACK_TIMEOUT),
+            Some(query) => warn!("Order state query {} for {} timed out", query, order.client_order_id),
+        }
+
+        if order.state_queries >= ORDER_STATE_QUERY_ATTEMPTS {
+            error!(target: "alerts", "State of order {} (request {}) can't be determined after {} queries, cancelling it by label",
+                order.client_order_id, request_id, order.state_queries);
+
+            commands.push(Command::CancelByLabel {
+                currency: instrument_currency(&order.instrument).to_string(),
+                label: order.client_order_id.clone(),
+            });
+            abandoned.push(*request_id);
+            continue;
+        }
+
+        let query_id = Uuid::new_v4();
+        order.state_query = Some(query_id);
+        order.state_queries += 1;
+        order.deadline = now + ORDER_STATE_QUERY_TIMEOUT;
+
+        commands.push(Command::GetOrderState {
+            request_id: query_id,
+            instrument: order.instrument.clone(),
+            label: order.client_order_id.clone(),
+            order_id: order_ids.get(&order.client_order_id).cloned(),
+        });
+    }
+
+    for request_id in abandoned {
+        unconfirmed.remove(&request_id);
+    }
+
+    commands
+}
 
-pub struct Balance {
-    pub(crate) balance: Decimal
+// Quote amount in contracts, capped at the delta (in BTC) when reducing only.
+// Zero when the delta is worth less than a contract.
+pub fn quote_amount(amount: Decimal, price: Decimal, reduce_only: Option<Decimal>) -> Decimal {
+    match reduce_only {
+        Some(delta) => {
+            let cap = (delta * price / CONTRACT_SIZE).floor() * CONTRACT_SIZE;
+            amount.min(cap)
+        }
+        None => amount,
+    }
+}
+
+pub fn apply_fill(positions: &mut HashMap<String, Decimal>, instrument: &str, direction: &Side, filled: Decimal) {
+    if filled.is_zero() {
+        return;
+    }
+
+    let position = positions.entry(instrument.to_string()).or_insert(Decimal::ZERO);
+    match direction {
+        Side::Bid => *position += filled,
+        Side::Ask => *position -= filled,
+    }
+}
+
+// Diffs the open orders on the exchange against the local state.
+// Local orders missing on the exchange are dropped, unknown exchange orders are adopted
+// or cancelled according to the policy. Returns the cancel commands to send.
+pub fn reconcile_orders(active_orders: &mut HashMap<String, Order>,
+                        pending_orders: &mut HashMap<String, PendingOrder>,
+                        exchange_orders: Vec<Order>,
+                        policy: OrphanOrderPolicy) -> Vec<Command> {
+    let mut cancels = vec!();
+
+    let exchange_ids: Vec<String> = exchange_orders.iter().map(|order| order.id.clone()).collect();
+
+    let gone: Vec<String> = active_orders.keys()
+        .filter(|id| !exchange_ids.contains(id))
+        .cloned()
+        .collect();
+
+    for id in gone {
+        let order = active_orders.remove(&id);
+        warn!("Order {} isn't open on the exchange anymore: {:?}", id, order);
+    }
+
+    for order in exchange_orders {
+        if active_orders.contains_key(&order.id) {
+            continue;
+        }
+
+        if pending_orders.remove(&order.label).is_some() {
+            info!("Order {} ({}) found on the exchange before its update", order.id, order.label);
+            active_orders.insert(order.id.clone(), order);
+            continue;
+        }
+
+        match policy {
+            OrphanOrderPolicy::Adopt => {
+                warn!("Adopting orphan order {:?}", order);
+                active_orders.insert(order.id.clone(), order);
+            }
+            OrphanOrderPolicy::Cancel => {
+                warn!("Cancelling orphan order {:?}", order);
+                cancels.push(Command::CancelOrder { id: order.id });
+            }
+        }
+    }
+
+    cancels
 }
 
 pub struct Manager {
     signal_receiver: Receiver<OrderPosition>,
-    orders_receiver: Receiver<OrderEvent>,
-    portfolio_receiver: Receiver<Balance>,
+    order_events: EventReader,
+    portfolio_events: EventReader,
     command_sender: Sender<Command>,
+    orphan_orders: OrphanOrderPolicy,
+    kill_switch: Arc<KillSwitch>,
+    mmp: Arc<MmpGuard>,
+    latency: Arc<Latency>,
+    metrics: Arc<Metrics>,
+    control: Arc<StrategyControl>,
+    supervisor: Arc<Supervisor>,
+    // shared with the admin API
+    active_orders: Arc<Mutex<HashMap<String, Order>>>,
+    // instrument -> signed position, from fills and exchange snapshots
+    positions: Arc<Mutex<HashMap<String, Decimal>>>,
 }
 
 impl Manager {
+    #[allow(clippy::too_many_arguments)]
     pub fn new(signal_receiver: Receiver<OrderPosition>,
-               orders_receiver: Receiver<OrderEvent>,
-               portfolio_receiver: Receiver<Balance>,
-               command_sender: Sender<Command>) -> Manager {
+               order_events: EventReader,
+               portfolio_events: EventReader,
+               command_sender: Sender<Command>,
+               orphan_orders: OrphanOrderPolicy,
+               kill_switch: Arc<KillSwitch>,
+               mmp: Arc<MmpGuard>,
+               latency: Arc<Latency>,
+               metrics: Arc<Metrics>,
+               control: Arc<StrategyControl>,
+               supervisor: Arc<Supervisor>) -> Manager {
         Manager {
             signal_receiver,
-            orders_receiver,
-            portfolio_receiver,
+            order_events,
+            portfolio_events,
             command_sender,
+            orphan_orders,
+            kill_switch,
+            mmp,
+            latency,
+            metrics,
+            control,
+            supervisor,
+            active_orders: Arc::new(Mutex::new(HashMap::new())),
+            positions: Arc::new(Mutex::new(HashMap::new())),
         }
     }
 
-    pub fn run(&self) {
+    pub fn active_orders(&self) -> Arc<Mutex<HashMap<String, Order>>> {
+        Arc::clone(&self.active_orders)
+    }
+
+    pub fn positions(&self) -> Arc<Mutex<HashMap<String, Decimal>>> {
+        Arc::clone(&self.positions)
+    }
+
+    pub fn run(self) {
         let balance = Arc::new(Mutex::new(Decimal::from_f64_retain(0.0).unwrap()));
 
         let b1 = Arc::clone(&balance);
@@ -93,65 +324,151 @@ impl Manager {
         let oc2 = Arc::clone(&order_count);
 
         // todo store order data in arrays/vectors
-        let active_orders = Arc::new(Mutex::new(HashMap::<String, Order>::new()));
+        let active_orders = Arc::clone(&self.active_orders);
 
         let ao1 = Arc::clone(&active_orders);
         let ao2 = Arc::clone(&active_orders);
 
-        let unconfirmed_orders = Arc::new(Mutex::new(HashSet::<Uuid>::new()));
+        let unconfirmed_orders = Arc::new(Mutex::new(HashMap::<Uuid, UnconfirmedOrder>::new()));
         let uo1 = Arc::clone(&unconfirmed_orders);
-
-
-        let order_receiver_clone = crossbeam_channel::Receiver::clone(&self.orders_receiver); //
-        let portfolio_receiver_clone = self.portfolio_receiver.clone(); //
+        let uo2 = Arc::clone(&unconfirmed_orders);
+        let ao3 = Arc::clone(&active_orders);
+
+        // client order id -> request, until the order shows up in user.orders
+        let pending_orders = Arc::new(Mutex::new(HashMap::<String, PendingOrder>::new()));
+        let po1 = Arc::clone(&pending_orders);
+        let po2 = Arc::clone(&pending_orders);
+        let po3 = Arc::clone(&pending_orders);
+
+        let positions = Arc::clone(&self.positions);
+        let ps1 = Arc::clone(&positions);
+
+        // quoting pauses until then after a Backoff failure
+        let backoff_until = Arc::new(Mutex::new(None::<Instant>));
+        let bu1 = Arc::clone(&backoff_until);
+        let bu2 = Arc::clone(&backoff_until);
+
+        let orphan_orders = self.orphan_orders;
+        let command_sender_clone_3 = self.command_sender.clone();
+        let kill_switch = Arc::clone(&self.kill_switch);
+        let mmp = Arc::clone(&self.mmp);
+        let mmp_2 = Arc::clone(&self.mmp);
+        let latency = Arc::clone(&self.latency);
+        let metrics = Arc::clone(&self.metrics);
+        let control = Arc::clone(&self.control);
+        let supervisor_1 = Arc::clone(&self.supervisor);
+        let supervisor_2 = Arc::clone(&self.supervisor);
+        let supervisor_3 = Arc::clone(&self.supervisor);
+        let supervisor_4 = Arc::clone(&self.supervisor);
+
+        let mut order_events = self.order_events;
+        let mut portfolio_events = self.portfolio_events;
         let signal_receiver_clone = self.signal_receiver.clone(); //
         let command_sender_clone = crossbeam_channel::Sender::clone(&self.command_sender.clone());
+        let command_sender_clone_2 = self.command_sender.clone();
 
         thread::spawn(move || { // update orders
-            let mut orders = order_receiver_clone.iter();
-            loop {
-                let order_response = orders.next().unwrap();
+            supervisor_1.run("order updates", || loop {
+                let order_response = match order_events.next_event() {
+                    Some(Event::Order(order_event)) => order_event,
+                    // order updates were lost, the snapshot reconciles the orders with the exchange
+                    Some(Event::Gap { missed }) => {
+                        error!(target: "alerts", "Order manager missed {} events, requesting a snapshot", missed);
+                        command_sender_clone_3.send(Command::GetSnapshot { currency: "BTC".to_string() }).unwrap();
+                        continue;
+                    }
+                    Some(Event::Fill(fill)) => {
+                        info!("Fill {} at {}: order {} ({}) {:?} {} {} @ {}",
+                            fill.trade_id, fill.timestamp, fill.order_id, fill.label,
+                            fill.direction, fill.instrument_name, fill.amount, fill.price);
+                        continue;
+                    }
+                    Some(_) => continue,
+                    None => return,
+                };
 
                 info!("Got order update: {:?}", &order_response);
 
                 match order_response {
-                    OrderEvent::OrderChanged { id, direction, price, amount, status, label } => {
+                    OrderEvent::OrderChanged { order, mmp_cancelled } => {
+                        let Order { id, instrument, direction, price, amount, filled_amount, status, label } = order;
+
+                        if status == OrderStatus::Rejected {
+                            metrics.inc("ct_orders_rejected_total", &[]);
+                        }
+
+                        if mmp_cancelled {
+                            mmp.trigger(&format!("order {} was cancelled by MMP", id));
+                        }
+
                         let mut existed_orders = ao1.lock().unwrap();
+                        let mut early_update = None;
 
-                        match (*existed_orders).get(id.as_str()) {
+                        match (*existed_orders).get_mut(id.as_str()) {
                             Some(ord) => {
+                                if ord.label.is_empty() && !label.is_empty() {
+                                    // mass quote orders get their label from the quote result
+                                    po1.lock().unwrap().remove(label.as_str());
+                                    ord.label = label.clone();
+                                }
+
+                                apply_fill(&mut ps1.lock().unwrap(), &instrument, &direction, filled_amount - ord.filled_amount);
+                                ord.filled_amount = filled_amount;
+
                                 match status {
                                     OrderStatus::Filled | OrderStatus::Cancelled => {
                                         (*existed_orders).remove(id.as_str());
                                     }
+                                    OrderStatus::Open => {
+                                        info!("Order {} is partially filled: {} of {}", id, filled_amount, amount);
+                                    }
                                     smth_else => {
                                         warn!("Got incorrect order state for existed order: {:?}, {:?}", smth_else, ord);
                                     }
                                 }
                             }
                             None => {
+                                apply_fill(&mut ps1.lock().unwrap(), &instrument, &direction, filled_amount);
+
+                                let pending = po1.lock().unwrap().remove(label.as_str());
+
+                                if let Some(pending) = &pending {
+                                    match (&pending.method, pending.acked_at) {
+                                        (Some(method), Some(acked_at)) => {
+                                            latency.record(LatencyKind::SendToUpdate, method, pending.sent_at.elapsed());
+                                            latency.record(LatencyKind::AckToUpdate, method, acked_at.elapsed());
+                                        }
+                                        (_, None) => early_update = Some((pending.request_id, pending.sent_at.elapsed())),
+                                        _ => (),
+                                    }
+
+                                    info!("Order {} for request {} ({} {:?}) is {:?}, send -> update {:?}, send -> ack {:?}",
+                                        id, pending.request_id, label, pending.direction, status,
+                                        pending.sent_at.elapsed(),
+                                        pending.acked_at.map(|acked_at| acked_at - pending.sent_at));
+                                } else if label.is_empty() {
+                                    info!("Got order {} without label, it wasn't placed by the bot", id);
+                                } else {
+                                    info!("Got order {} with unknown label {}", id, label);
+                                }
+
                                 match status {
                                     OrderStatus::Open => {
-                                        let order_label = label.clone();
-
                                         let order = Order {
                                             id: id.clone(),
+                                            instrument,
                                             direction,
                                             price,
                                             amount,
+                                            filled_amount,
                                             status,
                                             label,
                                         };
 
                                         (*existed_orders).insert(id, order);
-                                        let current_timestamp = SystemTime::now()
-                                            .duration_since(UNIX_EPOCH)
-                                            .unwrap()
-                                            .as_millis();
-
-                                        let round_trip = current_timestamp - order_label.parse::<u128>().unwrap();
-
-                                        info!("Round trip: {}", round_trip);
+                                    }
+                                    OrderStatus::Filled | OrderStatus::Cancelled | OrderStatus::Rejected if pending.is_some() => {
+                                        info!("Order {} was closed before it was tracked: {:?}", id, status);
                                     }
                                     smth_else => {
                                         warn!("Got incorrect order state for non-existed order: {:?}", smth_else);
@@ -161,24 +478,124 @@ impl Manager {
                         }
 
                         info!("existed orders: {:?}", &existed_orders);
+                        // the other threads lock the unconfirmed orders before the active ones
+                        drop(existed_orders);
+
+                        if let Some((request_id, send_to_update)) = early_update {
+                            if let Some(order) = unconfirmed_orders.lock().unwrap().get_mut(&request_id) {
+                                order.send_to_update = Some(send_to_update);
+                            }
+                        }
                     }
 
-                    OrderEvent::OrderSuccess { uuid } => {
-                        let mut unconfirmed = unconfirmed_orders.lock().unwrap();
-                        if (*unconfirmed).contains(&uuid) {
-                            (*unconfirmed).remove(&uuid);
+                    OrderEvent::OrderSuccess { uuid, method } => {
+                        let mut pending = po1.lock().unwrap();
+                        if let Some(order) = (*pending).values_mut().find(|order| order.request_id == uuid) {
+                            order.acked_at = Some(Instant::now());
+                            order.method = method.clone();
+                            metrics.inc("ct_orders_acked_total", &[]);
                         };
+                        drop(pending);
+
+                        let mut unconfirmed = unconfirmed_orders.lock().unwrap();
+                        let send_to_update = (*unconfirmed).remove(&uuid).and_then(|order| order.send_to_update);
+                        if let (Some(send_to_update), Some(method)) = (send_to_update, &method) {
+                            latency.record(LatencyKind::SendToUpdate, method, send_to_update);
+                        }
+                    }
+
+                    OrderEvent::OrderStateResolved { request_id, label, found } => {
+                        let mut unconfirmed = unconfirmed_orders.lock().unwrap();
+
+                        let resolved: Vec<Uuid> = (*unconfirmed).iter()
+                            .filter(|(_, order)| order.state_query == Some(request_id))
+                            .map(|(uuid, _)| *uuid)
+                            .collect();
+
+                        let instruments: Vec<String> = resolved.iter()
+                            .filter_map(|uuid| (*unconfirmed).remove(uuid))
+                            .map(|order| order.instrument)
+                            .collect();
+                        drop(unconfirmed);
+
+                        if !found {
+                            // a fill whose update was missed would be lost with the request,
+                            // the positions snapshot replaces the local positions
+                            for instrument in instruments {
+                                command_sender_clone_3.send(Command::GetPositions { currency: instrument_currency(&instrument).to_string() }).unwrap();
+                            }
+                            po1.lock().unwrap().remove(label.as_str());
+                            warn!("Order {} isn't known to the exchange, treating its request as failed", label);
+                        } else {
+                            info!("Order {} was found on the exchange", label);
+                        }
+                    }
+
+                    OrderEvent::OpenOrdersSnapshot { orders } => {
+                        let mut existed_orders = ao1.lock().unwrap();
+                        let mut pending = po1.lock().unwrap();
+
+                        let cancels = reconcile_orders(&mut existed_orders, &mut pending, orders, orphan_orders);
+                        for cancel in cancels {
+                            command_sender_clone_3.send(cancel).unwrap();
+                        }
+
+                        info!("existed orders after reconciliation: {:?}", &existed_orders);
+                    }
+
+                    OrderEvent::OrderFailed { request_id, label, reason, action, retry_after } => {
+                        if action == ErrorAction::Alert {
+                            error!(target: "alerts", "Order request {} ({}) failed: {}", request_id, label, reason);
+                        } else {
+                            warn!("Order request {} ({}) failed: {}, {:?}", request_id, label, reason, action);
+                        }
+                        metrics.inc("ct_orders_rejected_total", &[]);
+
+                        po1.lock().unwrap().remove(label.as_str());
+                        unconfirmed_orders.lock().unwrap().remove(&request_id);
+
+                        match action {
+                            ErrorAction::Backoff => *bu1.lock().unwrap() = Some(Instant::now() + retry_after),
+                            // nothing is left pending for the label, the next signal quotes a fresh price
+                            ErrorAction::Reprice => info!("Requoting {} on the next signal", label),
+                            // the connector engages the kill switch on Halt and authorizes again on Reauthenticate
+                            _ => (),
+                        }
+                    }
+
+                    OrderEvent::MmpTriggered { index_name } => {
+                        mmp.trigger(&format!("user.mmp_trigger.{}", index_name));
+                    }
+
+                    OrderEvent::PositionsSnapshot { positions } => {
+                        let mut local_positions = ps1.lock().unwrap();
+
+                        for (instrument, size) in positions.iter() {
+                            let local = local_positions.get(instrument).cloned().unwrap_or(Decimal::ZERO);
+                            if local != *size {
+                                warn!("Position mismatch on {}: local {}, exchange {}", instrument, local, size);
+                            }
+                        }
+
+                        *local_positions = positions;
                     }
                 }
-            }
+
+                for (instrument, size) in ps1.lock().unwrap().iter() {
+                    metrics.set("ct_position", &[("instrument", instrument)], size.to_f64().unwrap_or_default());
+                }
+            });
         });
 
 
         thread::spawn(move || { // update the balance
-            let mut portfolio_iter = portfolio_receiver_clone.iter();
-
-            loop {
-                let p = portfolio_iter.next().unwrap();
+            // a gap needs nothing, every update carries the whole balance
+            supervisor_2.run("balance updates", || loop {
+                let p = match portfolio_events.next_event() {
+                    Some(Event::Portfolio(balance)) => balance,
+                    Some(_) => continue,
+                    None => return,
+                };
 
                 let mut existed_balance = b1.lock().unwrap();
 
@@ -186,7 +603,7 @@ impl Manager {
                 *existed_balance = p.balance;
 
                 info!("Updated balance - old: {}, current: {}", old_balance, p.balance);
-            }
+            });
         });
 
 
@@ -194,12 +611,21 @@ impl Manager {
             let mut signal_iter = signal_receiver_clone.iter();
 
             let instrument = "BTC-PERPETUAL";
-            let default_amount = Decimal::from_f64_retain(10.0).unwrap();
+
+            let mut client_order_ids = ClientOrderIdGenerator::new();
 
 
-            loop {
+            supervisor_3.run("order signals", || loop {
                 let signal = signal_iter.next().unwrap();
 
+                if kill_switch.is_engaged() || mmp_2.is_frozen() || supervisor_3.shutdown().is_requested() {
+                    continue;
+                }
+
+                if bu2.lock().unwrap().is_some_and(|until| Instant::now() < until) {
+                    continue;
+                }
+
                 // info!("Got signal {:?} on {:?}",  signal, SystemTime::now());
 
                 // info!("lock unconfirmed");
@@ -209,48 +635,76 @@ impl Manager {
                     let orders = ao2.lock().unwrap();
                     let balance = b2.lock().unwrap();
 
-                    let bid = (*orders).values().filter(|order| order.direction == TradeDirection::Bid).count();
-                    let ask = (*orders).values().filter(|order| order.direction == TradeDirection::Ask).count();
+                    let bid = (*orders).values().filter(|order| order.direction == Side::Bid).count();
+                    let ask = (*orders).values().filter(|order| order.direction == Side::Ask).count();
 
                     if bid == 0 && ask == 0 {
-                        let current_timestamp = SystemTime::now()
-                            .duration_since(UNIX_EPOCH)
-                            .unwrap()
-                            .as_millis();
-
-                        let ask_uuid = Uuid::new_v4();
-                        let ask_order = Command::MakeOrder {
-                            request_id: ask_uuid.clone(),
-                            direction: OrderSide::Ask,
-                            instrument: instrument.to_string(),
-                            price: signal.ask,
-                            amount: default_amount,
-                            label: current_timestamp.to_string(),
-                        };
-
-                        command_sender_clone.send(ask_order).unwrap();
-                        (*unconfirmed).insert(ask_uuid);
+                        let default_amount = control.params().order_amount;
+                        let mut pending = po2.lock().unwrap();
+
+                        let legs: Vec<QuoteLeg> = [(Side::Ask, signal.ask), (Side::Bid, signal.bid)].into_iter()
+                            .filter_map(|(direction, price)| price.map(|price| (direction, price, quote_amount(default_amount, price, signal.reduce_only))))
+                            .filter(|(_, _, amount)| *amount > Decimal::ZERO)
+                            .map(|(direction, price, amount)| QuoteLeg {
+                                request_id: Uuid::new_v4(),
+                                client_order_id: client_order_ids.next_id(),
+                                direction,
+                                price,
+                                amount,
+                                reduce_only: signal.reduce_only.is_some(),
+                            })
+                            .collect();
+
+                        if legs.is_empty() {
+                            continue;
+                        }
 
-                        let bid_uuid = Uuid::new_v4();
+                        for leg in &legs {
+                            (*pending).insert(leg.client_order_id.clone(), PendingOrder { request_id: leg.request_id, direction: leg.direction, sent_at: Instant::now(), acked_at: None, method: None });
+                            (*unconfirmed).insert(leg.request_id, UnconfirmedOrder::new(leg.client_order_id.clone(), instrument.to_string(), Instant::now()));
+                        }
 
-                        let bid_order = Command::MakeOrder {
-                            request_id: bid_uuid.clone(),
-                            direction: OrderSide::Bid,
+                        let quotes = Command::MakeQuotes {
+                            request_id: Uuid::new_v4(),
                             instrument: instrument.to_string(),
-                            price: signal.bid,
-                            amount: default_amount,
-                            label: current_timestamp.to_string(),
+                            legs,
                         };
 
-                        command_sender_clone.send(bid_order).unwrap();
-                        (*unconfirmed).insert(bid_uuid);
+                        command_sender_clone.send(quotes).unwrap();
                     }
                 } else {
                     info!("unconfirmed {:?}", *unconfirmed);
                 }
                 // info!("release unconfirmed");
                 // thread::sleep(Duration::from_micros(1));
-            }
+            });
+        });
+
+
+        thread::spawn(move || { // recover lost acks
+            supervisor_4.run("ack recovery", || loop {
+                thread::sleep(UNCONFIRMED_CHECK_INTERVAL);
+
+                let commands = {
+                    let mut unconfirmed = uo2.lock().unwrap();
+                    if (*unconfirmed).is_empty() {
+                        continue;
+                    }
+
+                    let order_ids: HashMap<String, String> = ao3.lock().unwrap().values()
+                        .map(|order| (order.label.clone(), order.id.clone()))
+                        .collect();
+
+                    check_unconfirmed(&mut unconfirmed, &order_ids, Instant::now())
+                };
+
+                for command in commands {
+                    if let Command::CancelByLabel { label, .. } = &command {
+                        po3.lock().unwrap().remove(label.as_str());
+                    }
+                    command_sender_clone_2.send(command).unwrap();
+                }
+            });
         });
     }
 }
@@ -262,4 +716,136 @@ mod tests {
 
     #[test]
     fn check_balances() {}
+
+    #[test]
+    fn check_client_order_ids() {
+        let mut ids = ClientOrderIdGenerator::with_prefix("ct1".to_string());
+
+        assert_eq!(ids.next_id(), "ct1-1");
+        assert_eq!(ids.next_id(), "ct1-2");
+
+        let mut restarted = ClientOrderIdGenerator::new();
+        assert!(restarted.next_id().starts_with("ct"));
+        assert_ne!(restarted.next_id(), ids.next_id());
+    }
+
+    #[test]
+    fn check_unconfirmed_order_queries() {
+        let start = Instant::now();
+        let request_id = Uuid::new_v4();
+
+        let mut unconfirmed = HashMap::new();
+        unconfirmed.insert(request_id, UnconfirmedOrder::new("ct1-1".to_string(), "BTC-PERPETUAL".to_string(), start));
+
+        let order_ids = HashMap::new();
+
+        assert!(check_unconfirmed(&mut unconfirmed, &order_ids, start).is_empty());
+
+        let queries = check_unconfirmed(&mut unconfirmed, &order_ids, start + ORDER_ACK_TIMEOUT);
+        assert_eq!(queries.len(), 1);
+        match &queries[0] {
+            Command::GetOrderState { request_id, label, order_id, .. } => {
+                assert_eq!(unconfirmed.values().next().unwrap().state_query, Some(*request_id));
+                assert_eq!(label, "ct1-1");
+                assert_eq!(order_id, &None);
+            }
+            other => panic!("Unexpected command {:?}", other),
+        }
+
+        // a query which is still running isn't repeated
+        assert!(check_unconfirmed(&mut unconfirmed, &order_ids, start + ORDER_ACK_TIMEOUT).is_empty());
+
+        let mut order_ids = HashMap::new();
+        order_ids.insert("ct1-1".to_string(), "14490265484".to_string());
+
+        let queries = check_unconfirmed(&mut unconfirmed, &order_ids, start + ORDER_ACK_TIMEOUT + ORDER_STATE_QUERY_TIMEOUT);
+        match &queries[0] {
+            Command::GetOrderState { order_id, .. } => assert_eq!(order_id, &Some("14490265484".to_string())),
+            other => panic!("Unexpected command {:?}", other),
+        }
+        assert_eq!(unconfirmed[&request_id].state_queries, 2);
+
+        let mut deadline = start + ORDER_ACK_TIMEOUT + ORDER_STATE_QUERY_TIMEOUT;
+        for _ in 2..ORDER_STATE_QUERY_ATTEMPTS {
+            deadline += ORDER_STATE_QUERY_TIMEOUT;
+            assert!(matches!(check_unconfirmed(&mut unconfirmed, &order_ids, deadline)[..], [Command::GetOrderState { .. }]));
+        }
+
+        // the attempts are used up, the order is cancelled instead of queried again
+        let commands = check_unconfirmed(&mut unconfirmed, &order_ids, deadline + ORDER_STATE_QUERY_TIMEOUT);
+        match &commands[..] {
+            [Command::CancelByLabel { currency, label }] => {
+                assert_eq!(currency, "BTC");
+                assert_eq!(label, "ct1-1");
+            }
+            other => panic!("Unexpected commands {:?}", other),
+        }
+        assert!(unconfirmed.is_empty());
+    }
+
+    fn order(id: &str, label: &str) -> Order {
+        Order {
+            id: id.to_string(),
+            instrument: "BTC-PERPETUAL".to_string(),
+            direction: Side::Bid,
+            price: Decimal::from(19094),
+            amount: Decimal::from(10),
+            filled_amount: Decimal::ZERO,
+            status: OrderStatus::Open,
+            label: label.to_string(),
+        }
+    }
+
+    #[test]
+    fn check_orders_reconciliation() {
+        let mut active = HashMap::new();
+        active.insert("1".to_string(), order("1", "ct1-1"));
+        active.insert("2".to_string(), order("2", "ct1-2"));
+
+        let mut pending = HashMap::new();
+        pending.insert("ct1-3".to_string(), PendingOrder { request_id: Uuid::new_v4(), direction: Side::Bid, sent_at: Instant::now(), acked_at: None, method: None });
+
+        let exchange = vec!(order("1", "ct1-1"), order("3", "ct1-3"), order("4", ""));
+
+        let cancels = reconcile_orders(&mut active, &mut pending, exchange, OrphanOrderPolicy::Cancel);
+
+        assert!(active.contains_key("1"));
+        assert!(!active.contains_key("2"));
+        assert!(active.contains_key("3"));
+        assert!(pending.is_empty());
+
+        assert_eq!(cancels.len(), 1);
+        match &cancels[0] {
+            Command::CancelOrder { id } => assert_eq!(id, "4"),
+            other => panic!("Unexpected command {:?}", other),
+        }
+
+        let cancels = reconcile_orders(&mut active, &mut pending, vec!(order("4", "")), OrphanOrderPolicy::Adopt);
+        assert!(cancels.is_empty());
+        assert_eq!(active.keys().collect::<Vec<_>>(), vec!("4"));
+    }
+
+    #[test]
+    fn check_reduce_only_quote_amount() {
+        let price = Decimal::from(20000);
+
+        assert_eq!(quote_amount(Decimal::from(100), price, None), Decimal::from(100));
+        // 0.002 BTC is 40 USD at 20000
+        assert_eq!(quote_amount(Decimal::from(100), price, Some(Decimal::new(2, 3))), Decimal::from(40));
+        assert_eq!(quote_amount(Decimal::from(100), price, Some(Decimal::new(21, 4))), Decimal::from(40));
+        assert_eq!(quote_amount(Decimal::from(100), price, Some(Decimal::ONE)), Decimal::from(100));
+        assert_eq!(quote_amount(Decimal::from(100), price, Some(Decimal::new(1, 4))), Decimal::ZERO);
+    }
+
+    #[test]
+    fn check_fills_update_positions() {
+        let mut positions = HashMap::new();
+
+        apply_fill(&mut positions, "BTC-PERPETUAL", &Side::Bid, Decimal::from(30));
+        apply_fill(&mut positions, "BTC-PERPETUAL", &Side::Ask, Decimal::from(10));
+        apply_fill(&mut positions, "ETH-PERPETUAL", &Side::Ask, Decimal::ZERO);
+
+        assert_eq!(positions["BTC-PERPETUAL"], Decimal::from(20));
+        assert!(!positions.contains_key("ETH-PERPETUAL"));
+    }
 }
\ No newline at end of file
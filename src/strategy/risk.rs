@@ -1,4 +1,309 @@
+use std::fs;
+use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use crossbeam_channel::Sender;
+use log::{error, info, warn};
+use rust_decimal::Decimal;
+use serde::{Serialize, Deserialize};
+use signal_hook::consts::SIGUSR2;
+use signal_hook::iterator::Signals;
+
+use crate::config::RiskConfig;
+use crate::core::domain::AccountState;
+use crate::core::entities::Command;
+use crate::core::event_bus::{Event, EventReader};
+
+const DAY_MS: i64 = 24 * 60 * 60 * 1000;
+// a new high-water mark alone is written at most this often
+const SAVE_INTERVAL: Duration = Duration::from_secs(5);
+
+#[derive(Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq)]
+pub struct Trip {
+    pub reason: String,
+    // milliseconds
+    pub timestamp: i64,
+}
+
+// Kept on disk so a restart neither clears a tripped breaker nor forgets the
+// high-water mark and the equity the day started with.
+#[derive(Serialize, Deserialize)]
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct BreakerState {
+    pub tripped: Option<Trip>,
+    // days since the epoch, utc
+    pub session_day: i64,
+    pub session_start_equity: Option<Decimal>,
+    pub high_water_mark: Option<Decimal>,
+}
+
+// Daily loss and drawdown circuit breaker. Once tripped no new quotes are made
+// and resting orders are cancelled until someone resets it by hand.
 pub struct RiskManager {
+    state: Mutex<BreakerState>,
+    // time of the last write, held while writing so the writes don't overtake each other
+    saved_at: Mutex<Option<Instant>>,
+    config: RiskConfig,
+    path: PathBuf,
+    command_sender: Sender<Command>,
+}
+
+impl RiskManager {
+    pub fn new(command_sender: Sender<Command>, config: RiskConfig) -> RiskManager {
+        let path = PathBuf::from(&config.state_file);
+        let state = load_state(&path, now_millis());
+
+        if let Some(trip) = &state.tripped {
+            error!(target: "alerts", "Circuit breaker is still tripped since {}: {}", trip.timestamp, trip.reason);
+            // orders may have been left resting by the previous run
+            command_sender.send(Command::CancelAll).unwrap();
+        }
+
+        RiskManager {
+            state: Mutex::new(state),
+            saved_at: Mutex::new(None),
+            config,
+            path,
+            command_sender,
+        }
+    }
+
+    pub fn is_tripped(&self) -> bool {
+        self.state.lock().unwrap().tripped.is_some()
+    }
+
+    pub fn state(&self) -> BreakerState {
+        self.state.lock().unwrap().clone()
+    }
+
+    pub fn update(&self, account: &AccountState, now: i64) {
+        if account.currency != self.config.currency {
+            return;
+        }
+
+        let equity = account.equity;
+        let mut state = self.state.lock().unwrap();
+        let mut changed = false;
+        // written right away, a new high-water mark alone can wait for SAVE_INTERVAL
+        let mut urgent = false;
+
+        let day = now.div_euclid(DAY_MS);
+        if state.session_day != day || state.session_start_equity.is_none() {
+            info!("Risk session {} starts with equity {}", day, equity);
+            state.session_day = day;
+            state.session_start_equity = Some(equity);
+            changed = true;
+            urgent = true;
+        }
+
+        if state.high_water_mark.is_none_or(|high| equity > high) {
+            state.high_water_mark = Some(equity);
+            changed = true;
+        }
+
+        if state.tripped.is_none() {
+            let loss = state.session_start_equity.unwrap() - equity;
+            let high = state.high_water_mark.unwrap();
+            let drawdown = if high > Decimal::ZERO { (high - equity) / high } else { Decimal::ZERO };
+
+            let reason = if loss >= self.config.max_daily_loss {
+                Some(format!("daily loss {} {} exceeds {}", loss, self.config.currency, self.config.max_daily_loss))
+            } else if drawdown >= self.config.max_drawdown {
+                Some(format!("drawdown {} from {} exceeds {}", drawdown.round_dp(4).normalize(), high, self.config.max_drawdown))
+            } else {
+                None
+            };
+
+            if let Some(reason) = reason {
+                error!(target: "alerts", "Circuit breaker tripped: {}", reason);
+                state.tripped = Some(Trip { reason, timestamp: now });
+                changed = true;
+                urgent = true;
+
+                self.command_sender.send(Command::CancelAll).unwrap();
+            }
+        }
+        drop(state);
+
+        if changed {
+            self.save(urgent);
+        }
+    }
+
+    // manual reset, the high-water mark restarts from the current equity
+    pub fn reset(&self) {
+        let mut state = self.state.lock().unwrap();
+
+        if let Some(trip) = state.tripped.take() {
+            warn!(target: "alerts", "Circuit breaker reset, was tripped by: {}", trip.reason);
+            state.session_start_equity = None;
+            state.high_water_mark = None;
+            drop(state);
+            self.save(true);
+        }
+    }
+
+    // writes the current state outside of the state lock, through a temporary file
+    // so a crash mid-write can't leave a truncated one behind
+    fn save(&self, urgent: bool) {
+        let mut saved_at = self.saved_at.lock().unwrap();
+        if !urgent && saved_at.is_some_and(|at| at.elapsed() < SAVE_INTERVAL) {
+            return;
+        }
+        *saved_at = Some(Instant::now());
+
+        let raw = serde_json::to_string_pretty(&self.state()).unwrap();
+
+        if let Some(dir) = self.path.parent() {
+            if let Err(e) = fs::create_dir_all(dir) {
+                error!("Can't create {:?}: {:?}", dir, e);
+            }
+        }
+
+        let temp = self.path.with_extension("tmp");
+        if let Err(e) = fs::write(&temp, raw).and_then(|_| fs::rename(&temp, &self.path)) {
+            error!("Can't save circuit breaker state to {:?}: {:?}", self.path, e);
+        }
+    }
+}
+
+// a state which can't be read starts tripped, the marks it held are lost and
+// trading shouldn't resume without someone looking at it
+fn load_state(path: &Path, now: i64) -> BreakerState {
+    let error = match fs::read_to_string(path) {
+        Ok(raw) => match serde_json::from_str(&raw) {
+            Ok(state) => return state,
+            Err(e) => format!("can't parse {:?}: {}", path, e),
+        },
+        Err(e) if e.kind() == ErrorKind::NotFound => return BreakerState::default(),
+        Err(e) => format!("can't read {:?}: {}", path, e),
+    };
+
+    error!(target: "alerts", "Circuit breaker starts tripped: {}", error);
+
+    BreakerState {
+        tripped: Some(Trip { reason: error, timestamp: now }),
+        ..Default::default()
+    }
+}
+
+fn now_millis() -> i64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_millis() as i64
+}
 
+pub fn watch(risk: Arc<RiskManager>, events: EventReader) {
+    thread::spawn(move || {
+        for event in events {
+            if let Event::Portfolio(account) = event {
+                risk.update(&account, now_millis());
+            }
+        }
+    });
 }
 
+// `kill -USR2 <pid>` resets a tripped circuit breaker
+pub fn listen_signals(risk: Arc<RiskManager>) {
+    let mut signals = Signals::new([SIGUSR2]).expect("Can't register signal handler");
+
+    thread::spawn(move || {
+        for signal in signals.forever() {
+            info!("Got signal {}", signal);
+            risk.reset();
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use crossbeam_channel::bounded;
+    use super::*;
+
+    fn account(equity: i64) -> AccountState {
+        AccountState { currency: "BTC".to_string(), equity: Decimal::from(equity), ..Default::default() }
+    }
+
+    fn config(name: &str) -> RiskConfig {
+        let path = std::env::temp_dir().join(format!("ct-{}-{}.json", name, std::process::id()));
+        let _ = fs::remove_file(&path);
+
+        RiskConfig {
+            currency: "BTC".to_string(),
+            max_daily_loss: Decimal::from(10),
+            max_drawdown: Decimal::new(2, 1),
+            state_file: path.to_str().unwrap().to_string(),
+        }
+    }
+
+    #[test]
+    fn check_daily_loss_trips_and_persists() {
+        let (command_sender, command_receiver) = bounded(10);
+        let config = config("daily-loss");
+        let risk = RiskManager::new(command_sender.clone(), config.clone());
+
+        risk.update(&account(100), 0);
+        risk.update(&account(95), 1000);
+        assert!(!risk.is_tripped());
+        assert_eq!(risk.state().session_start_equity, Some(Decimal::from(100)));
+        assert_eq!(risk.state().high_water_mark, Some(Decimal::from(100)));
+
+        // a new day starts from the current equity
+        risk.update(&account(92), DAY_MS);
+        risk.update(&account(83), DAY_MS + 1000);
+        assert!(!risk.is_tripped());
+
+        risk.update(&account(82), DAY_MS + 2000);
+        assert!(risk.is_tripped());
+        assert!(matches!(command_receiver.try_recv(), Ok(Command::CancelAll)));
+
+        // still tripped after a restart, which cancels what the last run left
+        let restarted = RiskManager::new(command_sender, config.clone());
+        assert!(restarted.is_tripped());
+        assert_eq!(restarted.state(), risk.state());
+        assert!(matches!(command_receiver.try_recv(), Ok(Command::CancelAll)));
+
+        restarted.reset();
+        assert!(!restarted.is_tripped());
+        assert!(!RiskManager::new(bounded(1).0, config.clone()).is_tripped());
+
+        fs::remove_file(config.state_file).unwrap();
+    }
+
+    #[test]
+    fn check_drawdown_from_high_water_mark() {
+        let (command_sender, _command_receiver) = bounded(10);
+        let config = config("drawdown");
+        let risk = RiskManager::new(command_sender, config.clone());
+
+        risk.update(&account(40), 0);
+        risk.update(&account(50), DAY_MS);
+        risk.update(&account(41), 2 * DAY_MS);
+        assert!(!risk.is_tripped());
+
+        risk.update(&account(40), 2 * DAY_MS + 1000);
+        assert_eq!(risk.state().tripped.unwrap().reason, "drawdown 0.2 from 50 exceeds 0.2");
+
+        fs::remove_file(config.state_file).unwrap();
+    }
+
+    #[test]
+    fn check_corrupt_state_starts_tripped() {
+        let (command_sender, command_receiver) = bounded(10);
+        let config = config("corrupt");
+        fs::write(&config.state_file, "{\"tripped\": nul").unwrap();
+
+        let risk = RiskManager::new(command_sender, config.clone());
+        assert!(risk.is_tripped());
+        assert!(risk.state().tripped.unwrap().reason.starts_with("can't parse"));
+        assert!(matches!(command_receiver.try_recv(), Ok(Command::CancelAll)));
+
+        // the reset writes a valid state again
+        risk.reset();
+        assert!(!RiskManager::new(bounded(1).0, config.clone()).is_tripped());
+
+        fs::remove_file(config.state_file).unwrap();
+    }
+}
@@ -0,0 +1,197 @@
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crossbeam_channel::Sender;
+use log::{info, warn};
+use rust_decimal::Decimal;
+
+use crate::config::StaleDataConfig;
+use crate::core::entities::Command;
+use crate::core::latency::Latency;
+
+const STALE_CHECK_INTERVAL: Duration = Duration::from_millis(100);
+
+#[derive(Debug, Clone)]
+pub struct Freshness {
+    // exchange timestamp of the last book update, milliseconds
+    pub exchange_timestamp: i64,
+    pub received_at: Instant,
+    // local receive time on the exchange clock minus exchange timestamp
+    pub exchange_lag_ms: i64,
+}
+
+#[derive(Default)]
+struct GuardState {
+    freshness: HashMap<String, Freshness>,
+    // last exchange heartbeat, None until the first one of a connection
+    heartbeat_at: Option<Instant>,
+    // why quotes are pulled, None while quoting
+    pulled: Option<String>,
+}
+
+// Pulls quotes when the book stops updating, lags the exchange too much, is crossed,
+// the heartbeats stop or the connection drops. Quoting resumes with the next fresh book
+// which isn't crossed while the heartbeats come.
+pub struct StaleDataGuard {
+    state: Mutex<GuardState>,
+    max_update_gap: Duration,
+    max_exchange_lag_ms: i64,
+    max_heartbeat_gap: Duration,
+    latency: Arc<Latency>,
+    command_sender: Sender<Command>,
+}
+
+impl StaleDataGuard {
+    pub fn new(command_sender: Sender<Command>, config: &StaleDataConfig, latency: Arc<Latency>) -> StaleDataGuard {
+        StaleDataGuard {
+            // nothing is known before the first book
+            state: Mutex::new(GuardState { freshness: HashMap::new(), heartbeat_at: None, pulled: Some("no book yet".to_string()) }),
+            max_update_gap: Duration::from_millis(config.max_update_gap_ms),
+            max_exchange_lag_ms: config.max_exchange_lag_ms,
+            max_heartbeat_gap: Duration::from_millis(config.max_heartbeat_gap_ms),
+            latency,
+            command_sender,
+        }
+    }
+
+    pub fn is_pulled(&self) -> bool {
+        self.state.lock().unwrap().pulled.is_some()
+    }
+
+    pub fn books(&self) -> Vec<(String, Freshness)> {
+        self.state.lock().unwrap().freshness.iter()
+            .map(|(instrument, freshness)| (instrument.clone(), freshness.clone()))
+            .collect()
+    }
+
+    // called for every applied book update with the resulting top of book
+    pub fn on_book(&self, instrument: &str, exchange_timestamp: i64, now: i64, received_at: Instant,
+                   best_bid: Option<Decimal>, best_ask: Option<Decimal>) {
+        let exchange_lag_ms = now + self.latency.clock_offset_ms() - exchange_timestamp;
+        let mut state = self.state.lock().unwrap();
+
+        state.freshness.insert(instrument.to_string(), Freshness { exchange_timestamp, received_at, exchange_lag_ms });
+
+        let problem = self.heartbeat_missing(&state, received_at).or_else(|| match (best_bid, best_ask) {
+            (Some(bid), Some(ask)) if bid >= ask => Some(format!("{} book is crossed: bid {} >= ask {}", instrument, bid, ask)),
+            (Some(_), Some(_)) if exchange_lag_ms > self.max_exchange_lag_ms => Some(format!("{} book lags the exchange by {}ms", instrument, exchange_lag_ms)),
+            (Some(_), Some(_)) => None,
+            _ => Some(format!("{} book is one sided", instrument)),
+        });
+
+        match problem {
+            Some(reason) => self.pull(&mut state, reason),
+            None => {
+                if let Some(reason) = state.pulled.take() {
+                    info!(target: "alerts", "Fresh {} book, quoting resumes after: {}", instrument, reason);
+                }
+            }
+        }
+    }
+
+    pub fn on_heartbeat(&self, received_at: Instant) {
+        self.state.lock().unwrap().heartbeat_at = Some(received_at);
+    }
+
+    pub fn on_disconnected(&self) {
+        let mut state = self.state.lock().unwrap();
+        state.freshness.clear();
+        state.heartbeat_at = None;
+        self.pull(&mut state, "connection lost".to_string());
+    }
+
+    // books which haven't updated within the allowed gap
+    pub fn check(&self, now: Instant) {
+        let mut state = self.state.lock().unwrap();
+
+        let stale = state.freshness.iter()
+            .find(|(_, freshness)| now.saturating_duration_since(freshness.received_at) > self.max_update_gap)
+            .map(|(instrument, freshness)| format!("no {} book update for {:?}", instrument, now.saturating_duration_since(freshness.received_at)));
+
+        if let Some(reason) = stale.or_else(|| self.heartbeat_missing(&state, now)) {
+            self.pull(&mut state, reason);
+        }
+    }
+
+    fn heartbeat_missing(&self, state: &GuardState, now: Instant) -> Option<String> {
+        state.heartbeat_at
+            .map(|heartbeat_at| now.saturating_duration_since(heartbeat_at))
+            .filter(|gap| *gap > self.max_heartbeat_gap)
+            .map(|gap| format!("no heartbeat for {:?}", gap))
+    }
+
+    fn pull(&self, state: &mut GuardState, reason: String) {
+        if state.pulled.is_none() {
+            warn!(target: "alerts", "Pulling quotes: {}", reason);
+            self.command_sender.send(Command::CancelAll).unwrap();
+            state.pulled = Some(reason);
+        }
+    }
+}
+
+pub fn watch(guard: Arc<StaleDataGuard>) {
+    thread::spawn(move || {
+        loop {
+            thread::sleep(STALE_CHECK_INTERVAL);
+            guard.check(Instant::now());
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use crossbeam_channel::bounded;
+    use super::*;
+
+    #[test]
+    fn check_pull_and_resume() {
+        let (command_sender, command_receiver) = bounded(10);
+        let guard = StaleDataGuard::new(command_sender, &StaleDataConfig { max_update_gap_ms: 1000, max_exchange_lag_ms: 500, max_heartbeat_gap_ms: 2000 }, Arc::new(Latency::new()));
+        let start = Instant::now();
+        let (bid, ask) = (Some(Decimal::from(100)), Some(Decimal::from(101)));
+
+        assert!(guard.is_pulled());
+        guard.on_book("BTC-PERPETUAL", 1000, 1010, start, bid, ask);
+        assert!(!guard.is_pulled());
+        assert_eq!(guard.books()[0].1.exchange_lag_ms, 10);
+
+        guard.check(start + Duration::from_millis(500));
+        assert!(!guard.is_pulled());
+        guard.check(start + Duration::from_millis(1500));
+        assert!(guard.is_pulled());
+
+        // a crossed book doesn't bring the quotes back
+        guard.on_book("BTC-PERPETUAL", 2000, 2010, start + Duration::from_millis(1600), ask, bid);
+        assert!(guard.is_pulled());
+        guard.on_book("BTC-PERPETUAL", 2100, 2110, start + Duration::from_millis(1700), bid, ask);
+        assert!(!guard.is_pulled());
+
+        // neither does one lagging the exchange
+        guard.on_book("BTC-PERPETUAL", 2200, 3000, start + Duration::from_millis(1800), bid, ask);
+        assert!(guard.is_pulled());
+        guard.on_book("BTC-PERPETUAL", 3000, 3010, start + Duration::from_millis(1900), bid, ask);
+        assert!(!guard.is_pulled());
+
+        // the books keep coming but the heartbeats stopped
+        guard.on_heartbeat(start + Duration::from_millis(2000));
+        guard.on_book("BTC-PERPETUAL", 4000, 4010, start + Duration::from_millis(3000), bid, ask);
+        guard.check(start + Duration::from_millis(3000));
+        assert!(!guard.is_pulled());
+        guard.on_book("BTC-PERPETUAL", 6000, 6010, start + Duration::from_millis(4500), bid, ask);
+        assert!(guard.is_pulled());
+        guard.on_heartbeat(start + Duration::from_millis(4600));
+        guard.on_book("BTC-PERPETUAL", 6100, 6110, start + Duration::from_millis(4600), bid, ask);
+        assert!(!guard.is_pulled());
+
+        guard.on_disconnected();
+        guard.on_disconnected();
+        assert!(guard.is_pulled());
+        assert!(guard.books().is_empty());
+
+        let commands: Vec<Command> = command_receiver.try_iter().collect();
+        assert_eq!(commands.len(), 4);
+        assert!(commands.iter().all(|command| matches!(command, Command::CancelAll)));
+    }
+}
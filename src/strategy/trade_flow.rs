@@ -0,0 +1,146 @@
+use std::collections::{HashMap, VecDeque};
+use std::time::Duration;
+use rust_decimal::Decimal;
+use crate::core::domain::{Side, Trade};
+
+// Detects missed public trades: deribit numbers them per instrument with trade_seq
+#[derive(Default)]
+pub struct TradeSeqTracker {
+    last_seqs: HashMap<String, i64>,
+}
+
+impl TradeSeqTracker {
+    pub fn new() -> TradeSeqTracker {
+        TradeSeqTracker { last_seqs: HashMap::new() }
+    }
+
+    // number of trades missed before this one, 0 for the first trade of an instrument
+    pub fn check(&mut self, trade: &Trade) -> i64 {
+        let missed = match self.last_seqs.get(&trade.instrument_name) {
+            Some(last) if trade.trade_seq > last + 1 => trade.trade_seq - last - 1,
+            _ => 0,
+        };
+
+        let last = self.last_seqs.entry(trade.instrument_name.clone()).or_insert(trade.trade_seq);
+        *last = (*last).max(trade.trade_seq);
+
+        missed
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub struct FlowStats {
+    pub trades: usize,
+    pub volume: Decimal,
+    // aggressive buys minus aggressive sells
+    pub signed_flow: Decimal,
+    pub vwap: Option<Decimal>,
+}
+
+// Rolling trade statistics over several windows, the windows are measured back from the
+// latest trade timestamp so the stats don't depend on the local clock.
+pub struct TradeFlow {
+    windows: Vec<Duration>,
+    trades: VecDeque<Trade>,
+}
+
+impl TradeFlow {
+    pub fn new(windows: Vec<Duration>) -> TradeFlow {
+        TradeFlow { windows, trades: VecDeque::new() }
+    }
+
+    pub fn add(&mut self, trade: Trade) {
+        let longest = self.windows.iter().max().cloned().unwrap_or_default().as_millis() as i64;
+        let cutoff = trade.timestamp - longest;
+
+        self.trades.push_back(trade);
+
+        while self.trades.front().is_some_and(|trade| trade.timestamp <= cutoff) {
+            self.trades.pop_front();
+        }
+    }
+
+    pub fn stats(&self, window: Duration) -> FlowStats {
+        let latest = self.trades.back().map_or(0, |trade| trade.timestamp);
+        let cutoff = latest - window.as_millis() as i64;
+
+        let mut stats = FlowStats { trades: 0, volume: Decimal::ZERO, signed_flow: Decimal::ZERO, vwap: None };
+        let mut notional = Decimal::ZERO;
+
+        for trade in self.trades.iter().rev().take_while(|trade| trade.timestamp > cutoff) {
+            stats.trades += 1;
+            stats.volume += trade.amount;
+            notional += trade.price * trade.amount;
+
+            match trade.direction {
+                Side::Bid => stats.signed_flow += trade.amount,
+                Side::Ask => stats.signed_flow -= trade.amount,
+            }
+        }
+
+        if !stats.volume.is_zero() {
+            stats.vwap = Some(notional / stats.volume);
+        }
+
+        stats
+    }
+
+    // stats for every configured window
+    pub fn all_stats(&self) -> Vec<(Duration, FlowStats)> {
+        self.windows.iter().map(|window| (*window, self.stats(*window))).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn trade(timestamp: i64, trade_seq: i64, direction: Side, price: i64, amount: i64) -> Trade {
+        Trade {
+            timestamp,
+            instrument_name: "BTC-PERPETUAL".to_string(),
+            trade_id: trade_seq.to_string(),
+            trade_seq,
+            direction,
+            price: Decimal::from(price),
+            amount: Decimal::from(amount),
+            mark_price: Decimal::from(price),
+            index_price: Decimal::from(price),
+        }
+    }
+
+    #[test]
+    fn check_trade_seq_gaps() {
+        let mut tracker = TradeSeqTracker::new();
+
+        assert_eq!(tracker.check(&trade(0, 10, Side::Bid, 100, 1)), 0);
+        assert_eq!(tracker.check(&trade(0, 11, Side::Bid, 100, 1)), 0);
+        assert_eq!(tracker.check(&trade(0, 15, Side::Bid, 100, 1)), 3);
+        // a late duplicate isn't a gap
+        assert_eq!(tracker.check(&trade(0, 12, Side::Bid, 100, 1)), 0);
+        assert_eq!(tracker.check(&trade(0, 16, Side::Bid, 100, 1)), 0);
+    }
+
+    #[test]
+    fn check_flow_windows() {
+        let mut flow = TradeFlow::new(vec!(Duration::from_secs(1), Duration::from_secs(10)));
+
+        flow.add(trade(1_000, 1, Side::Bid, 100, 10));
+        flow.add(trade(9_500, 2, Side::Ask, 110, 20));
+        flow.add(trade(10_000, 3, Side::Bid, 120, 10));
+
+        assert_eq!(flow.stats(Duration::from_secs(1)), FlowStats {
+            trades: 2,
+            volume: Decimal::from(30),
+            signed_flow: Decimal::from(-10),
+            vwap: Some(Decimal::from(3400) / Decimal::from(30)),
+        });
+        assert_eq!(flow.stats(Duration::from_secs(10)).trades, 3);
+        assert_eq!(flow.stats(Duration::from_secs(10)).vwap, Some(Decimal::from(110)));
+
+        // the first trade falls out of the longest window
+        flow.add(trade(11_000, 4, Side::Ask, 100, 10));
+        assert_eq!(flow.stats(Duration::from_secs(10)).trades, 3);
+        assert_eq!(flow.stats(Duration::from_secs(10)).signed_flow, Decimal::from(-20));
+    }
+}